@@ -0,0 +1,180 @@
+// Byte-oriented line capture for job output
+// Jobs can print anything: invalid UTF-8, megabyte-long lines, or progress bars that redraw
+// themselves with `\r`. This splits a raw stream into printable lines without ever panicking.
+
+use std::io::{self, Read};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decoding {
+    // Invalid UTF-8 becomes U+FFFD
+    Lossy,
+    // Invalid UTF-8 bytes become `\xNN`, so the original bytes can be recovered
+    Escaped,
+}
+
+impl Decoding {
+    pub fn parse(name: &str) -> Option<Decoding> {
+        match name {
+            "lossy" => Some(Decoding::Lossy),
+            "escaped" => Some(Decoding::Escaped),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CaptureOptions {
+    // Longer lines are split into several, 0 disables
+    pub max_line_bytes: usize,
+    pub decoding: Decoding,
+}
+
+pub struct LineSplitter<R> {
+    reader: R,
+    options: CaptureOptions,
+    chunk: Vec<u8>,
+    pos: usize,
+    len: usize,
+    line: Vec<u8>,
+    // Saw a `\r` and don't yet know if it's a line ending or a redraw
+    pending_cr: bool,
+    eof: bool,
+}
+
+impl<R: Read> LineSplitter<R> {
+    pub fn new(reader: R, options: CaptureOptions) -> LineSplitter<R> {
+        LineSplitter {
+            reader: reader,
+            options: options,
+            chunk: vec![0; 8192],
+            pos: 0,
+            len: 0,
+            line: Vec::new(),
+            pending_cr: false,
+            eof: false,
+        }
+    }
+
+    fn take_line(&mut self, at: usize) -> String {
+        let rest = self.line.split_off(at);
+        let bytes = ::std::mem::replace(&mut self.line, rest);
+        decode(&bytes, self.options.decoding)
+    }
+}
+
+impl<R: Read> Iterator for LineSplitter<R> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<io::Result<String>> {
+        loop {
+            if self.pos == self.len {
+                if self.eof {
+                    if self.line.is_empty() {
+                        return None;
+                    }
+
+                    let end = self.line.len();
+                    return Some(Ok(self.take_line(end)));
+                }
+
+                match self.reader.read(&mut self.chunk) {
+                    Ok(0) => self.eof = true,
+                    Ok(n) => {
+                        self.pos = 0;
+                        self.len = n;
+                    },
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                    Err(e) => return Some(Err(e)),
+                }
+
+                continue;
+            }
+
+            let byte = self.chunk[self.pos];
+            self.pos += 1;
+
+            if self.pending_cr {
+                self.pending_cr = false;
+
+                if byte != b'\n' {
+                    // A bare `\r` redraws the line, so only the last redraw is kept
+                    self.line.clear();
+                }
+            }
+
+            match byte {
+                b'\n' => {
+                    let end = self.line.len();
+                    return Some(Ok(self.take_line(end)));
+                },
+                b'\r' => self.pending_cr = true,
+                _ => self.line.push(byte),
+            }
+
+            // Only split once a line is actually too long, so a line of exactly
+            // `max_line_bytes` followed by `\n` stays one line
+            let max = self.options.max_line_bytes;
+            if max > 0 && self.line.len() > max {
+                let at = split_point(&self.line[..max]);
+                return Some(Ok(self.take_line(at)));
+            }
+        }
+    }
+}
+
+// Where to split a full line so a UTF-8 sequence cut off at the end carries over to the next one
+fn split_point(bytes: &[u8]) -> usize {
+    let len = bytes.len();
+    let mut start = len;
+
+    // Walk back over continuation bytes (0b10xxxxxx) to the start of the last character
+    while start > 0 && len - start < 4 {
+        start -= 1;
+        if bytes[start] & 0xC0 != 0x80 {
+            break;
+        }
+    }
+
+    let expected = match bytes[start] {
+        b if b & 0xE0 == 0xC0 => 2,
+        b if b & 0xF0 == 0xE0 => 3,
+        b if b & 0xF8 == 0xF0 => 4,
+        _ => 1,
+    };
+
+    if start > 0 && start + expected > len {
+        start
+    } else {
+        len
+    }
+}
+
+pub fn decode(bytes: &[u8], decoding: Decoding) -> String {
+    match decoding {
+        Decoding::Lossy => String::from_utf8_lossy(bytes).into_owned(),
+        Decoding::Escaped => {
+            let mut out = String::with_capacity(bytes.len());
+            let mut rest = bytes;
+
+            loop {
+                match ::std::str::from_utf8(rest) {
+                    Ok(valid) => {
+                        out.push_str(valid);
+                        return out;
+                    },
+                    Err(e) => {
+                        let (valid, after) = rest.split_at(e.valid_up_to());
+                        out.push_str(::std::str::from_utf8(valid).unwrap());
+
+                        let bad = e.error_len().unwrap_or(after.len());
+                        for b in &after[..bad] {
+                            out.push_str(&format!("\\x{:02x}", b));
+                        }
+
+                        rest = &after[bad..];
+                    }
+                }
+            }
+        }
+    }
+}
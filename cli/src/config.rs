@@ -0,0 +1,84 @@
+// Local settings for kraken, read from `~/.kraken.json`
+// Anything missing from the file falls back to the defaults below.
+
+use std::env;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Config {
+    // Kraken server the CLI talks to
+    pub server_url: String,
+    // Where job output, error and pid files are written
+    pub output_dir: String,
+    // Rotate the output file once it reaches this many bytes (0 disables)
+    pub rotate_bytes: u64,
+    // Rotate the output file once it has been open this many seconds (0 disables)
+    pub rotate_seconds: u64,
+    // Compression for rotated segments: "none", "gzip" or "zstd"
+    pub compression: String,
+    // Captured lines longer than this are split (0 disables)
+    pub max_line_bytes: usize,
+    // How invalid UTF-8 in job output is stored: "lossy" or "escaped"
+    pub decoding: String,
+    // How job output reaches the server: "stream" (falls back to "http" when it can't) or "http"
+    pub log_transport: String,
+    // Seconds between resource usage samples of a running job (0 disables)
+    pub sample_interval_secs: u64,
+    // Mail relay for `--notify email:` as host:port, spoken to in plain SMTP without a login
+    pub smtp_server: String,
+    // Sender address of notification emails
+    pub smtp_from: String,
+    // How `new job --host` runs commands on a server, called as `<ssh_command> <host> <command>`
+    pub ssh_command: String,
+    // Seconds a job gets to exit after SIGTERM when its daemon is stopped, before it's killed
+    pub grace_period_secs: u64,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            server_url: "http://kraken-grantgumina.herokuapp.com".to_string(),
+            output_dir: "/tmp".to_string(),
+            rotate_bytes: 10 * 1024 * 1024,
+            rotate_seconds: 0,
+            compression: "gzip".to_string(),
+            max_line_bytes: 16 * 1024,
+            decoding: "lossy".to_string(),
+            log_transport: "stream".to_string(),
+            sample_interval_secs: 5,
+            smtp_server: "localhost:25".to_string(),
+            smtp_from: "kraken@localhost".to_string(),
+            ssh_command: "ssh".to_string(),
+            grace_period_secs: 30,
+        }
+    }
+}
+
+pub fn load() -> Config {
+
+    let config_file_path = dirs::home_dir().unwrap().join(".kraken.json");
+
+    let mut config = match std::fs::read_to_string(config_file_path) {
+        Ok(contents) => {
+            match serde_json::from_str(&contents) {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("Couldn't parse ~/.kraken.json, using defaults: {}", e);
+                    Config::default()
+                }
+            }
+        },
+        Err(_) => Config::default(),
+    };
+
+    // The environment wins over the config file
+    if let Ok(dir) = env::var("KRAKEN_OUTPUT_DIR") {
+        config.output_dir = dir;
+    }
+
+    if let Ok(url) = env::var("KRAKEN_SERVER_URL") {
+        config.server_url = url.trim_end_matches('/').to_string();
+    }
+
+    config
+}
@@ -0,0 +1,252 @@
+// Local web dashboard for `kraken serve`
+// Serves a single HTML page plus a small JSON API over the local job registry and output
+// files, so a machine can be watched from a browser without the hosted server.
+//
+//   GET /                               the dashboard
+//   GET /api/jobs                       every job in the registry
+//   GET /api/jobs/<name>                one job
+//   GET /api/jobs/<name>/logs?after=N   log lines with a sequence number above N
+
+use std::net::SocketAddr;
+
+use hyper::rt::{self, Future};
+use hyper::service::service_fn_ok;
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+
+use job_output;
+use job_registry::{self, JobRecord};
+use log_line::{self, LogLine};
+use protocol;
+
+// How many lines the log view gets per poll
+static LOG_PAGE_SIZE: usize = 500;
+
+pub fn serve(addr: SocketAddr, output_dir: String) {
+
+    let new_service = move || {
+        let dir = output_dir.clone();
+        service_fn_ok(move |req| route(&dir, req))
+    };
+
+    let server = Server::bind(&addr).serve(new_service);
+
+    // The bound address, which differs from `addr` when it asked for port 0
+    println!("Kraken dashboard running on http://{}", server.local_addr());
+
+    let server = server.map_err(|e| eprintln!("Dashboard error: {}", e));
+
+    rt::run(server);
+}
+
+fn route(dir: &str, req: Request<Body>) -> Response<Body> {
+
+    if req.method() != Method::GET {
+        return error(StatusCode::METHOD_NOT_ALLOWED, "only GET is supported");
+    }
+
+    let path = req.uri().path().to_string();
+    let query = req.uri().query().unwrap_or("").to_string();
+    let decoded: Vec<String> = path.trim_matches('/').split('/').map(protocol::decode_path_segment).collect();
+    let segments: Vec<&str> = decoded.iter().map(|s| s.as_str()).collect();
+
+    match segments.as_slice() {
+        [""] => html(DASHBOARD_HTML),
+        ["api", "jobs"] => jobs(dir),
+        ["api", "jobs", name] => job(dir, name),
+        ["api", "jobs", name, "logs"] => logs(dir, name, &query),
+        _ => error(StatusCode::NOT_FOUND, "not found"),
+    }
+}
+
+fn jobs(dir: &str) -> Response<Body> {
+    match job_registry::list(dir) {
+        Ok(records) => {
+            let jobs: Vec<serde_json::Value> = records.iter().map(|r| job_json(dir, r)).collect();
+            json(StatusCode::OK, &json!(jobs))
+        },
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+fn job(dir: &str, name: &str) -> Response<Body> {
+    if !valid_name(name) {
+        return error(StatusCode::BAD_REQUEST, "invalid job name");
+    }
+
+    match job_registry::load(dir, name) {
+        Ok(record) => json(StatusCode::OK, &job_json(dir, &record)),
+        Err(_) => error(StatusCode::NOT_FOUND, "no such job"),
+    }
+}
+
+fn logs(dir: &str, name: &str, query: &str) -> Response<Body> {
+    if !valid_name(name) {
+        return error(StatusCode::BAD_REQUEST, "invalid job name");
+    }
+
+    let after = query_param(query, "after").and_then(|a| a.parse::<u64>().ok()).unwrap_or(0);
+
+    // Older segments are only read while `after` is still in them
+    let covered = |record: &str| LogLine::parse_record(record).map_or(false, |line| line.seq <= after + 1);
+
+    let records = match job_output::read_recent_lines(dir, name, covered) {
+        Ok(records) => records,
+        Err(_) => return error(StatusCode::NOT_FOUND, "no output for this job"),
+    };
+
+    let lines: Vec<serde_json::Value> = records.iter()
+        .filter_map(|r| LogLine::parse_record(r))
+        .filter(|line| line.seq > after)
+        .take(LOG_PAGE_SIZE)
+        .map(|line| json!({
+            "seq": line.seq,
+            "timestamp": log_line::format_timestamp(&line.timestamp),
+            "stream": line.stream.as_str(),
+            "text": line.text,
+        }))
+        .collect();
+
+    json(StatusCode::OK, &json!(lines))
+}
+
+// Only what the page shows. The rest of the record, like the `--env` values in its spec, isn't
+// for anyone who can reach the port.
+fn job_json(dir: &str, record: &JobRecord) -> serde_json::Value {
+    json!({
+        "name": record.name,
+        "machine": record.machine,
+        "status": record.status,
+        "started_at": record.started_at,
+        "finished_at": record.finished_at,
+        "exit_code": record.exit_code,
+        "progress": record.progress,
+        "running": job_registry::is_running(dir, &record.name),
+    })
+}
+
+// Job names end up in file paths, so anything that could leave the output directory is refused
+fn valid_name(name: &str) -> bool {
+    protocol::check_job_name(name).is_ok()
+}
+
+fn query_param<'a>(query: &'a str, key: &str) -> Option<&'a str> {
+    query.split('&')
+        .filter_map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(k), Some(v)) if k == key => Some(v),
+                _ => None,
+            }
+        })
+        .next()
+}
+
+fn json(status: StatusCode, value: &serde_json::Value) -> Response<Body> {
+    let mut res = Response::new(Body::from(value.to_string()));
+    *res.status_mut() = status;
+    res.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    res
+}
+
+fn html(page: &'static str) -> Response<Body> {
+    let mut res = Response::new(Body::from(page));
+    res.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"));
+    res
+}
+
+fn error(status: StatusCode, message: &str) -> Response<Body> {
+    json(status, &json!({ "message": message }))
+}
+
+static DASHBOARD_HTML: &str = r#"<!doctype html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Kraken</title>
+<style>
+  body { font-family: sans-serif; margin: 0; display: flex; height: 100vh; }
+  #jobs { width: 40%; overflow-y: auto; border-right: 1px solid #ccc; }
+  #log { flex: 1; display: flex; flex-direction: column; }
+  table { border-collapse: collapse; width: 100%; }
+  th, td { text-align: left; padding: 4px 8px; border-bottom: 1px solid #eee; }
+  tr.job { cursor: pointer; }
+  tr.job:hover, tr.selected { background: #eef; }
+  .running { color: #07a; } .done { color: #090; } .failed { color: #c00; }
+  pre { flex: 1; margin: 0; padding: 8px; overflow-y: auto; background: #111; color: #ddd; }
+  .stderr { color: #f88; } .kraken { color: #8cf; }
+  h2 { margin: 8px; font-size: 1em; }
+</style>
+</head>
+<body>
+<div id="jobs">
+  <table>
+    <thead><tr><th>Job Name</th><th>Machine</th><th>Status</th><th>Started</th></tr></thead>
+    <tbody id="job-rows"></tbody>
+  </table>
+</div>
+<div id="log">
+  <h2 id="log-title">Select a job</h2>
+  <pre id="log-lines"></pre>
+</div>
+<script>
+var selected = null;
+var lastSeq = 0;
+
+function status(job) {
+  return job.running ? "running" : job.status;
+}
+
+function refreshJobs() {
+  fetch("/api/jobs").then(function (r) { return r.json(); }).then(function (jobs) {
+    var rows = document.getElementById("job-rows");
+    rows.innerHTML = "";
+    jobs.reverse().forEach(function (job) {
+      var tr = document.createElement("tr");
+      tr.className = "job" + (job.name === selected ? " selected" : "");
+      [job.name, job.machine, status(job), new Date(job.started_at * 1000).toLocaleString()].forEach(function (text, i) {
+        var td = document.createElement("td");
+        td.textContent = text;
+        if (i === 2) { td.className = status(job); }
+        tr.appendChild(td);
+      });
+      tr.onclick = function () { select(job.name); };
+      rows.appendChild(tr);
+    });
+  });
+}
+
+function select(name) {
+  selected = name;
+  lastSeq = 0;
+  document.getElementById("log-title").textContent = name;
+  document.getElementById("log-lines").innerHTML = "";
+  refreshJobs();
+  refreshLog();
+}
+
+function refreshLog() {
+  if (selected === null) { return; }
+  var name = selected;
+  fetch("/api/jobs/" + encodeURIComponent(name) + "/logs?after=" + lastSeq).then(function (r) { return r.json(); }).then(function (lines) {
+    if (name !== selected || !Array.isArray(lines)) { return; }
+    var pre = document.getElementById("log-lines");
+    var atBottom = pre.scrollTop + pre.clientHeight >= pre.scrollHeight - 4;
+    lines.forEach(function (line) {
+      var span = document.createElement("span");
+      span.className = line.stream;
+      span.textContent = line.text + "\n";
+      pre.appendChild(span);
+      lastSeq = line.seq;
+    });
+    if (atBottom) { pre.scrollTop = pre.scrollHeight; }
+  });
+}
+
+refreshJobs();
+setInterval(refreshJobs, 5000);
+setInterval(refreshLog, 1000);
+</script>
+</body>
+</html>
+"#;
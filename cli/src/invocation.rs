@@ -0,0 +1,138 @@
+// Running a job's command
+// A command given as one string runs with `/bin/sh -c`, or with the shell `--shell` names, like
+// bash, zsh or fish. Words given after `--` are a program and its arguments, which run directly
+// without a shell, so they reach the program exactly as typed. `--script` runs a script file,
+// with its own `#!` line or a shell, passing it the words after `--`. The script is copied next
+// to the job's output and kept in its spec, so the job runs what the file held when it started
+// and can be run again once the file has changed or from another machine.
+
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use clap::ArgMatches;
+
+use kraken_utils;
+
+// What `--shell` defaults to
+pub const DEFAULT_SHELL: &str = "/bin/sh";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Script {
+    pub path: String,
+    pub contents: String,
+}
+
+impl Script {
+    pub fn read(path: &str) -> Result<Script, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("Couldn't read --script {}: {}", path, e))?;
+        Ok(Script { path: path.to_string(), contents: contents })
+    }
+
+    // The script's file name, which is what the job's command shows
+    pub fn file_name(&self) -> String {
+        Path::new(&self.path).file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| self.path.clone())
+    }
+
+    /// Writes the script to `path`, executable so a `#!` line can run it
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        path.parent().map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(path, &self.contents))
+            .and_then(|_| fs::set_permissions(path, fs::Permissions::from_mode(0o755)))
+            .map_err(|e| format!("Couldn't write {}: {}", path.display(), e))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Invocation {
+    Shell { shell: String, command: String },
+    Direct(Vec<String>),
+    Script { shell: Option<String>, script: Script, args: Vec<String> },
+}
+
+impl Invocation {
+    pub fn from_matches(command: &ArgMatches) -> Result<Invocation, String> {
+        let shell = command.value_of("SHELL").map(|s| s.to_string());
+        let argv: Vec<String> = command.values_of("ARGV").into_iter().flatten().map(|a| a.to_string()).collect();
+
+        if let Some(path) = command.value_of("SCRIPT") {
+            return Ok(Invocation::Script { shell: shell, script: Script::read(path)?, args: argv });
+        }
+
+        if !argv.is_empty() {
+            if shell.is_some() {
+                return Err("--shell can't be used with a command given after --, which runs without a shell".to_string());
+            }
+            return Ok(Invocation::Direct(argv));
+        }
+
+        match command.value_of("COMMAND") {
+            Some(c) => Ok(Invocation::Shell { shell: shell.unwrap_or(DEFAULT_SHELL.to_string()), command: c.to_string() }),
+            None => Err("No command to run".to_string()),
+        }
+    }
+
+    /// The command as the job record and `show jobs` show it
+    pub fn display(&self) -> String {
+        match *self {
+            Invocation::Shell { ref command, .. } => command.clone(),
+            Invocation::Direct(ref argv) => quote(argv),
+            Invocation::Script { ref script, ref args, .. } => {
+                let mut words = vec![script.file_name()];
+                words.extend(args.iter().cloned());
+                quote(&words)
+            },
+        }
+    }
+
+    pub fn script(&self) -> Option<&Script> {
+        match *self {
+            Invocation::Script { ref script, .. } => Some(script),
+            _ => None,
+        }
+    }
+
+    /// The program and arguments to run, with a script run from its copy at `saved_script`
+    pub fn argv(&self, saved_script: &Path) -> Vec<String> {
+        match *self {
+            Invocation::Shell { ref shell, ref command } => vec![shell.clone(), "-c".to_string(), command.clone()],
+            Invocation::Direct(ref argv) => argv.clone(),
+            Invocation::Script { ref shell, ref script, ref args } => {
+                let saved = saved_script.to_string_lossy().into_owned();
+                let mut argv = match *shell {
+                    Some(ref shell) => vec![shell.clone(), saved],
+                    None if script.contents.starts_with("#!") => vec![saved],
+                    None => vec![DEFAULT_SHELL.to_string(), saved],
+                };
+                argv.extend(args.iter().cloned());
+                argv
+            },
+        }
+    }
+}
+
+/// Whether `program` can be run from `workdir`, searching the job's PATH when it has no `/`.
+/// A missing program is caught here, before the job is registered and can't report its error.
+pub fn find_program(program: &str, workdir: &str, env_vars: &[(String, String)]) -> bool {
+    let runnable = |path: &Path| path.metadata().map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0).unwrap_or(false);
+
+    if program.contains('/') {
+        return runnable(&Path::new(workdir).join(program));
+    }
+
+    let path_var = env_vars.iter().rev()
+        .find(|&&(ref name, _)| name == "PATH")
+        .map(|&(_, ref value)| value.clone())
+        .or_else(|| env::var("PATH").ok())
+        .unwrap_or_default();
+
+    env::split_paths(&path_var).any(|dir| runnable(&dir.join(program)))
+}
+
+/// `argv` as a shell command line, for the job's header
+pub fn quote(argv: &[String]) -> String {
+    argv.iter().map(|a| kraken_utils::shell_quote(a)).collect::<Vec<_>>().join(" ")
+}
@@ -0,0 +1,159 @@
+// Job input
+// Jobs run detached from the terminal, so their stdin is /dev/null unless they're given input.
+// `--stdin-file` feeds a job a file, and with `--stdin-file -` the input piped or redirected into
+// `kraken new job` is spooled to a file next to the job's output before kraken detaches, then fed
+// to the job the same way. Kraken's stdin is otherwise left alone, since a script or loop
+// running kraken may be using it or holding it open. With `--stdin-pipe` the job's stdin stays
+// open after that for `kraken send job`, which writes to it through a named pipe next to the
+// job's output, until the job exits or `send job --eof` removes the pipe, which closes it.
+
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use clap::ArgMatches;
+
+use job_output;
+
+// How long `send job` waits for a job between two senders to start reading again
+const SEND_RETRIES: u32 = 50;
+
+/// Where a job started here reads its input from: the `--stdin-file`, or with `--stdin-file -`
+/// what was piped into kraken, spooled to the job's `.stdin` file. `None` leaves it with /dev/null.
+pub fn prepare(command: &ArgMatches, dir: &str, job_name: &str) -> Result<Option<PathBuf>, String> {
+    match command.value_of("STDIN_FILE") {
+        Some("-") => {},
+        Some(path) => {
+            // Absolute, since the job runs in its own working directory
+            return File::open(path)
+                .and_then(|_| fs::canonicalize(path))
+                .map(Some)
+                .map_err(|e| format!("Couldn't read --stdin-file {}: {}", path, e));
+        },
+        None => return Ok(None),
+    }
+
+    let spool = job_output::job_file_path(dir, job_name, "stdin");
+    let copied = fs::create_dir_all(dir)
+        .and_then(|_| File::create(&spool))
+        .and_then(|mut file| io::copy(&mut io::stdin(), &mut file))
+        .map_err(|e| format!("Couldn't save the input for {}: {}", job_name, e))?;
+
+    if copied == 0 {
+        let _ = fs::remove_file(&spool);
+        return Ok(None);
+    }
+
+    Ok(Some(spool))
+}
+
+/// The input for a job started on another machine, which gets it over ssh
+pub fn read(command: &ArgMatches) -> Result<Option<Vec<u8>>, String> {
+    let mut input = Vec::new();
+
+    match command.value_of("STDIN_FILE") {
+        Some("-") => {
+            io::stdin().read_to_end(&mut input).map_err(|e| format!("Couldn't read the input: {}", e))?;
+        },
+        Some(path) => {
+            File::open(path)
+                .and_then(|mut file| file.read_to_end(&mut input))
+                .map_err(|e| format!("Couldn't read --stdin-file {}: {}", path, e))?;
+        },
+        None => return Ok(None),
+    }
+
+    Ok(if input.is_empty() { None } else { Some(input) })
+}
+
+/// The named pipe `send job` writes to
+pub fn pipe_path(dir: &str, job_name: &str) -> PathBuf {
+    job_output::job_file_path(dir, job_name, "stdin-pipe")
+}
+
+pub fn create_pipe(path: &Path) -> Result<(), String> {
+    let _ = fs::remove_file(path);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("Couldn't create {}: {}", dir.display(), e))?;
+    }
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(|e| e.to_string())?;
+    if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+        return Err(format!("Couldn't create {}: {}", path.display(), io::Error::last_os_error()));
+    }
+
+    Ok(())
+}
+
+/// Feeds the job's stdin: `input` first, then with a `pipe`, whatever `send job` writes to it
+/// until the pipe is removed. The job's stdin is closed once this returns.
+pub fn feed<W: Write>(mut stdin: W, input: Option<PathBuf>, pipe: Option<PathBuf>) {
+    if let Some(path) = input {
+        let copied = File::open(&path).and_then(|mut file| io::copy(&mut file, &mut stdin));
+        if copied.is_err() {
+            return;
+        }
+    }
+
+    if let Some(path) = pipe {
+        // Each sender opens and closes the pipe, so it's opened again for the next
+        loop {
+            let copied = File::open(&path).and_then(|mut sender| io::copy(&mut sender, &mut stdin));
+            if copied.is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// Writes `input` to the stdin of a job running on this machine
+pub fn send<R: Read>(dir: &str, job_name: &str, input: &mut R) -> Result<(), String> {
+    let mut pipe = open_pipe(dir, job_name)?;
+
+    io::copy(input, &mut pipe)
+        .and_then(|_| pipe.flush())
+        .map_err(|e| format!("Couldn't send input to {}: {}", job_name, e))
+}
+
+/// Closes the stdin of a job running on this machine once it has read what was sent
+pub fn close(dir: &str, job_name: &str) -> Result<(), String> {
+    // Holding the pipe open while removing it lets the job finish reading, and its next open fails
+    let _pipe = open_pipe(dir, job_name)?;
+    fs::remove_file(pipe_path(dir, job_name)).map_err(|e| format!("Couldn't close the input of {}: {}", job_name, e))
+}
+
+fn open_pipe(dir: &str, job_name: &str) -> Result<File, String> {
+    let path = pipe_path(dir, job_name);
+
+    let is_pipe = fs::metadata(&path).map(|m| m.file_type().is_fifo()).unwrap_or(false);
+    if !is_pipe {
+        return Err(format!(
+            "{} isn't reading input from kraken. Jobs started here with --stdin-pipe read it until they exit or it's closed with --eof.",
+            job_name
+        ));
+    }
+
+    // Opening without blocking fails instead of waiting forever when nothing reads the pipe
+    for _ in 0..SEND_RETRIES {
+        match OpenOptions::new().write(true).custom_flags(libc::O_NONBLOCK).open(&path) {
+            Ok(pipe) => {
+                // Block on writes again, so large input waits for the job to read it
+                unsafe {
+                    let fd = pipe.as_raw_fd();
+                    libc::fcntl(fd, libc::F_SETFL, libc::fcntl(fd, libc::F_GETFL) & !libc::O_NONBLOCK);
+                }
+                return Ok(pipe);
+            },
+            Err(ref e) if e.raw_os_error() == Some(libc::ENXIO) => thread::sleep(Duration::from_millis(100)),
+            Err(e) => return Err(format!("Couldn't open {}: {}", path.display(), e)),
+        }
+    }
+
+    Err(format!("{} isn't reading input anymore", job_name))
+}
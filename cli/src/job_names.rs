@@ -0,0 +1,84 @@
+// Job names
+// A job's name is part of its local file names and of every URL kraken sends about it, so it's
+// checked before the job starts. A name already used by a job on this machine or on the server
+// is only reused with `new job --replace`, which removes the old job first. `--auto-suffix` picks
+// the first free `<name>-2`, `<name>-3`, ... instead, which is also what happens when a generated
+// name is taken.
+
+use std::fs;
+
+use clap::ArgMatches;
+
+use job_output;
+use job_registry;
+use krephis;
+use protocol;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnCollision {
+    Refuse,
+    Replace,
+    Suffix,
+}
+
+impl OnCollision {
+    pub fn from_matches(command: &ArgMatches) -> OnCollision {
+        if command.is_present("REPLACE") {
+            OnCollision::Replace
+        } else if command.is_present("AUTO_SUFFIX") {
+            OnCollision::Suffix
+        } else {
+            OnCollision::Refuse
+        }
+    }
+}
+
+/// The name to start a job as. `output_dir` is where the job's local files go, if they go on this
+/// machine. Jobs on the server are only checked when it can be reached.
+pub fn resolve(name: &str, output_dir: Option<&str>, on_collision: OnCollision) -> Result<String, String> {
+    protocol::check_job_name(name)?;
+
+    let server_jobs = krephis::jobs().unwrap_or_default();
+    let on_server = |name: &str| server_jobs.iter().find(|j| j.name == name);
+    let local = |name: &str| output_dir.map_or(false, |dir| job_output::artifacts(dir, name).map(|a| !a.is_empty()).unwrap_or(false));
+
+    if on_server(name).is_none() && !local(name) {
+        return Ok(name.to_string());
+    }
+
+    match on_collision {
+        OnCollision::Refuse => Err(format!(
+            "A job named {} already exists{}. Use --replace to remove it first, or --auto-suffix to pick a free name.",
+            name,
+            if local(name) { " on this machine" } else { " on the server" }
+        )),
+
+        OnCollision::Replace => {
+            let running = on_server(name).map_or(false, |j| j.status.as_ref().map(|s| s.as_str()) == Some("running"))
+                || output_dir.map_or(false, |dir| job_registry::is_running(dir, name));
+
+            if running {
+                return Err(format!("{} is still running, so it can't be replaced", name));
+            }
+
+            if on_server(name).is_some() {
+                krephis::delete_job(name).map_err(|e| format!("Couldn't remove {} from the server: {}", name, e))?;
+            }
+
+            if let Some(dir) = output_dir {
+                for path in job_output::artifacts(dir, name).unwrap_or_default() {
+                    fs::remove_file(&path).map_err(|e| format!("Couldn't remove {}: {}", path.display(), e))?;
+                }
+            }
+
+            Ok(name.to_string())
+        },
+
+        OnCollision::Suffix => {
+            (2..).map(|n| format!("{}-{}", name, n))
+                .find(|candidate| on_server(candidate).is_none() && !local(candidate))
+                .ok_or(format!("No free name left for {}", name))
+                .and_then(|candidate| protocol::check_job_name(&candidate).map(|_| candidate))
+        },
+    }
+}
@@ -0,0 +1,366 @@
+// Local job output files
+// Output goes through one buffered writer and is rotated into numbered segments
+// (`kraken-job-<name>.out.1.gz`, `.out.2.gz`, ...) so a chatty job can't fill the disk
+// with a single file. The live segment is always `kraken-job-<name>.out`.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn parse(name: &str) -> Option<Compression> {
+        match name {
+            "none" => Some(Compression::None),
+            "gzip" | "gz" => Some(Compression::Gzip),
+            "zstd" | "zst" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match *self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RotationPolicy {
+    pub max_bytes: u64,
+    pub max_age: Option<Duration>,
+    pub compression: Compression,
+}
+
+pub fn job_file_path(dir: &str, job_name: &str, extension: &str) -> PathBuf {
+    Path::new(dir).join(format!("kraken-job-{}.{}", job_name, extension))
+}
+
+pub struct JobOutput {
+    dir: String,
+    job_name: String,
+    policy: RotationPolicy,
+    writer: BufWriter<File>,
+    bytes_written: u64,
+    opened_at: Instant,
+    flushed_at: Instant,
+    next_segment: u32,
+}
+
+impl JobOutput {
+    /// Creates (or truncates) the live output file for a job, removing segments rotated out by
+    /// an earlier job with the same name so its output isn't read as this one's
+    pub fn create(dir: &str, job_name: &str, policy: RotationPolicy) -> io::Result<JobOutput> {
+        fs::create_dir_all(dir)?;
+
+        for (_, path) in segments(dir, job_name)? {
+            fs::remove_file(path)?;
+        }
+
+        let file = File::create(job_file_path(dir, job_name, "out"))?;
+
+        Ok(JobOutput {
+            dir: dir.to_string(),
+            job_name: job_name.to_string(),
+            policy: policy,
+            writer: BufWriter::new(file),
+            bytes_written: 0,
+            opened_at: Instant::now(),
+            flushed_at: Instant::now(),
+            next_segment: 1,
+        })
+    }
+
+    /// Writes one line, rotating first if the live segment is due.
+    /// The buffer is flushed at most once a second so `tail -f` still works.
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.should_rotate() {
+            self.rotate()?;
+        }
+
+        writeln!(self.writer, "{}", line)?;
+        self.bytes_written += line.len() as u64 + 1;
+
+        if self.flushed_at.elapsed() >= Duration::from_secs(1) {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Rotates and flushes on time when no line has arrived, so a job that goes quiet doesn't
+    /// keep its live segment open past `max_age`. The runner calls this about once a second.
+    pub fn tick(&mut self) -> io::Result<()> {
+        if self.should_rotate() {
+            self.rotate()?;
+        }
+
+        if self.flushed_at.elapsed() >= Duration::from_secs(1) {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.flushed_at = Instant::now();
+        self.writer.flush()
+    }
+
+    fn should_rotate(&self) -> bool {
+        if self.bytes_written == 0 {
+            return false;
+        }
+
+        let too_big = self.policy.max_bytes > 0 && self.bytes_written >= self.policy.max_bytes;
+        let too_old = match self.policy.max_age {
+            Some(age) => self.opened_at.elapsed() >= age,
+            None => false,
+        };
+
+        too_big || too_old
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+
+        let live_path = job_file_path(&self.dir, &self.job_name, "out");
+        let segment_path = job_file_path(
+            &self.dir,
+            &self.job_name,
+            &format!("out.{}{}", self.next_segment, self.policy.compression.extension()),
+        );
+
+        match self.policy.compression {
+            Compression::None => {
+                fs::copy(&live_path, &segment_path)?;
+            },
+            Compression::Gzip => {
+                let mut input = File::open(&live_path)?;
+                let mut encoder = flate2::write::GzEncoder::new(File::create(&segment_path)?, flate2::Compression::default());
+                io::copy(&mut input, &mut encoder)?;
+                encoder.finish()?;
+            },
+            Compression::Zstd => {
+                let input = File::open(&live_path)?;
+                zstd::stream::copy_encode(input, File::create(&segment_path)?, 0)?;
+            },
+        }
+
+        // Truncate in place so anyone tailing the live file keeps the same path
+        self.writer = BufWriter::new(File::create(&live_path)?);
+        self.bytes_written = 0;
+        self.opened_at = Instant::now();
+        self.next_segment += 1;
+
+        Ok(())
+    }
+}
+
+/// Rotated segments for a job, oldest first
+pub fn segments(dir: &str, job_name: &str) -> io::Result<Vec<(u32, PathBuf)>> {
+    let prefix = format!("kraken-job-{}.out.", job_name);
+    let mut found = Vec::new();
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(found),
+        Err(e) => return Err(e),
+    };
+
+    for entry in entries {
+        let path = entry?.path();
+        let file_name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(n) => n.to_string(),
+            None => continue,
+        };
+
+        if !file_name.starts_with(&prefix) {
+            continue;
+        }
+
+        // Only `<n>`, `<n>.gz` or `<n>.zst`, since a job named `<name>.out.<n>` has files with
+        // this prefix too
+        let mut index = &file_name[prefix.len()..];
+        for compressed in &[".gz", ".zst"] {
+            if index.ends_with(compressed) {
+                index = &index[..index.len() - compressed.len()];
+            }
+        }
+
+        if let Ok(n) = index.parse::<u32>() {
+            found.push((n, path));
+        }
+    }
+
+    found.sort_by_key(|s| s.0);
+    Ok(found)
+}
+
+fn open_segment(path: &Path) -> io::Result<Box<dyn Read>> {
+    let file = File::open(path)?;
+
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("gz") => Ok(Box::new(flate2::read::GzDecoder::new(file))),
+        Some("zst") => Ok(Box::new(zstd::stream::read::Decoder::new(file)?)),
+        _ => Ok(Box::new(file)),
+    }
+}
+
+/// The last `count` lines of the live output file, without reading the whole thing. Older lines
+/// in rotated segments aren't included.
+pub fn tail_lines(dir: &str, job_name: &str, count: usize) -> io::Result<Vec<String>> {
+    let mut file = File::open(job_file_path(dir, job_name, "out"))?;
+    let length = file.metadata()?.len();
+
+    // Lines are capped at `max_line_bytes` (16 KiB by default), so this is usually plenty
+    let window = (count as u64 + 1) * 512;
+    let start = length.saturating_sub(window.max(64 * 1024));
+    file.seek(SeekFrom::Start(start))?;
+
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+
+    let tail = String::from_utf8_lossy(&tail);
+    let mut lines: Vec<&str> = tail.lines().collect();
+
+    // The first line is probably cut in half
+    if start > 0 && !lines.is_empty() {
+        lines.remove(0);
+    }
+
+    let first = lines.len().saturating_sub(count);
+    Ok(lines[first..].iter().map(|l| l.to_string()).collect())
+}
+
+/// Reads every line a job has written locally, across rotated segments
+pub fn read_lines(dir: &str, job_name: &str) -> io::Result<Vec<String>> {
+    read_recent_lines(dir, job_name, |_| false)
+}
+
+/// Reads a job's lines back from the live segment, stopping at the first segment whose first
+/// line `covered` accepts, so a reader that only wants the newest lines doesn't decompress every
+/// older segment. Lines come back oldest first.
+pub fn read_recent_lines<F: Fn(&str) -> bool>(dir: &str, job_name: &str, covered: F) -> io::Result<Vec<String>> {
+    let mut paths: Vec<PathBuf> = segments(dir, job_name)?.into_iter().map(|s| s.1).collect();
+
+    let live_path = job_file_path(dir, job_name, "out");
+    if live_path.exists() {
+        paths.push(live_path);
+    }
+
+    if paths.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("no local output for job {}", job_name)));
+    }
+
+    let mut newest_first = Vec::new();
+
+    for path in paths.iter().rev() {
+        let reader = BufReader::new(open_segment(path)?);
+        let lines = reader.lines().collect::<io::Result<Vec<String>>>()?;
+
+        let done = lines.first().map_or(false, |first| covered(first.as_str()));
+        newest_first.push(lines);
+
+        if done {
+            break;
+        }
+    }
+
+    Ok(newest_first.into_iter().rev().flatten().collect())
+}
+
+// Works out which job a `kraken-job-*` file belongs to
+fn job_name_from_file(file_name: &str) -> Option<String> {
+    if !file_name.starts_with("kraken-job-") {
+        return None;
+    }
+
+    let mut rest = &file_name["kraken-job-".len()..];
+
+    for compressed in &[".gz", ".zst"] {
+        if rest.ends_with(compressed) {
+            rest = &rest[..rest.len() - compressed.len()];
+        }
+    }
+
+    // Rotated segments look like `<name>.out.<n>`
+    if let Some(i) = rest.rfind(".out.") {
+        if rest[i + ".out.".len()..].parse::<u32>().is_ok() {
+            return Some(rest[..i].to_string());
+        }
+    }
+
+    for extension in &[".out", ".err", ".pid", ".killed", ".json", ".metrics", ".resources", ".script", ".stdin", ".stdin-pipe", ".tty"] {
+        if rest.ends_with(extension) {
+            return Some(rest[..rest.len() - extension.len()].to_string());
+        }
+    }
+
+    None
+}
+
+/// Names of every job with files in `dir`
+pub fn job_names(dir: &str) -> io::Result<Vec<String>> {
+    let mut names = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if let Some(name) = path.file_name().and_then(|n| n.to_str()).and_then(job_name_from_file) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+
+    names.sort();
+    Ok(names)
+}
+
+/// Every local file belonging to a job: output, rotated segments, error, pid, registry record, metrics and resource samples
+pub fn artifacts(dir: &str, job_name: &str) -> io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        let belongs = path.file_name()
+            .and_then(|n| n.to_str())
+            .and_then(job_name_from_file)
+            .map(|name| name == job_name)
+            .unwrap_or(false);
+
+        if belongs {
+            paths.push(path);
+        }
+    }
+
+    paths.sort();
+    Ok(paths)
+}
+
+/// Unix timestamp of the most recently modified file belonging to a job
+pub fn last_modified(dir: &str, job_name: &str) -> io::Result<i64> {
+    let mut newest = 0;
+
+    for path in artifacts(dir, job_name)? {
+        let modified = fs::metadata(&path)?.modified()?;
+        let secs = modified.duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+
+        if secs > newest {
+            newest = secs;
+        }
+    }
+
+    Ok(newest)
+}
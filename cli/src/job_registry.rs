@@ -0,0 +1,231 @@
+// Local registry of jobs started on this machine
+// Each job gets a `kraken-job-<name>.json` record next to its output files, so the
+// machine knows what it ran without asking the server. Updates load, change and save the whole
+// record, so while a job runs they're only made from its runner thread.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::process;
+
+use chrono::prelude::*;
+use job_output;
+use protocol::{Alert, Artifact, JobProgress, JobSpec, ResourceSummary};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JobRecord {
+    pub name: String,
+    pub machine: String,
+    pub description: String,
+    pub command: String,
+    // "running", "done", "failed" or "interrupted"
+    pub status: String,
+    // Unix timestamps in seconds
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    pub exit_code: Option<i64>,
+    // Latest `##kraken progress` report, for jobs that print them
+    #[serde(default)]
+    pub progress: Option<JobProgress>,
+    // Files registered with `##kraken artifact`
+    #[serde(default)]
+    pub artifacts: Vec<Artifact>,
+    // Filled in once the job exits
+    #[serde(default)]
+    pub resources: Option<ResourceSummary>,
+    // Output lines that matched `--alert-on` or `--fail-on`
+    #[serde(default)]
+    pub alerts: Vec<Alert>,
+    // Set for jobs started together by `new job --hosts`
+    #[serde(default)]
+    pub group: Option<String>,
+    // How to start the job again. Missing for jobs started before kraken kept it.
+    #[serde(default)]
+    pub spec: Option<JobSpec>,
+    // From `--tag NAME=VALUE`
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    // The job `kraken resume` started in its place, once it was interrupted
+    #[serde(default)]
+    pub resumed_as: Option<String>,
+}
+
+impl JobRecord {
+    pub fn new(name: &str, machine: &str, description: &str, command: &str) -> JobRecord {
+        JobRecord {
+            name: name.to_string(),
+            machine: machine.to_string(),
+            description: description.to_string(),
+            command: command.to_string(),
+            status: "running".to_string(),
+            started_at: Utc::now().timestamp(),
+            finished_at: None,
+            exit_code: None,
+            progress: None,
+            artifacts: Vec::new(),
+            resources: None,
+            alerts: Vec::new(),
+            group: None,
+            spec: None,
+            labels: BTreeMap::new(),
+            resumed_as: None,
+        }
+    }
+}
+
+pub fn save(dir: &str, record: &JobRecord) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+
+    // Write then rename so a reader never sees half a record
+    let path = job_output::job_file_path(dir, &record.name, "json");
+    let tmp_path = job_output::job_file_path(dir, &record.name, &format!("json.{}.tmp", process::id()));
+
+    let file = File::create(&tmp_path)?;
+    serde_json::to_writer_pretty(file, record)?;
+    fs::rename(tmp_path, path)
+}
+
+pub fn load(dir: &str, job_name: &str) -> io::Result<JobRecord> {
+    let contents = fs::read_to_string(job_output::job_file_path(dir, job_name, "json"))?;
+    let record = serde_json::from_str(&contents)?;
+    Ok(record)
+}
+
+/// Marks a job as finished with the given exit code and resource usage. A job `--fail-on`
+/// stopped has failed whatever its exit code.
+pub fn finish(dir: &str, job_name: &str, exit_code: Option<i64>, resources: Option<ResourceSummary>) -> io::Result<()> {
+    let mut record = load(dir, job_name)?;
+
+    let stopped = record.alerts.iter().any(|a| a.action == "fail");
+    record.status = if exit_code == Some(0) && !stopped { "done".to_string() } else { "failed".to_string() };
+    record.finished_at = Some(Utc::now().timestamp());
+    record.exit_code = exit_code;
+    record.resources = resources;
+
+    save(dir, &record)
+}
+
+/// Marks a job as interrupted by a shutdown, whatever its exit code
+pub fn interrupt(dir: &str, job_name: &str, exit_code: Option<i64>, resources: Option<ResourceSummary>) -> io::Result<()> {
+    let mut record = load(dir, job_name)?;
+
+    record.status = "interrupted".to_string();
+    record.finished_at = Some(Utc::now().timestamp());
+    record.exit_code = exit_code;
+    record.resources = resources;
+
+    save(dir, &record)
+}
+
+/// Marks a job the user killed as failed, whatever its exit code. The daemon gets the same
+/// SIGTERM from a kill as from a shutdown, but a killed job isn't one to resume.
+pub fn kill(dir: &str, job_name: &str, exit_code: Option<i64>, resources: Option<ResourceSummary>) -> io::Result<()> {
+    let mut record = load(dir, job_name)?;
+
+    record.status = "failed".to_string();
+    record.finished_at = Some(Utc::now().timestamp());
+    record.exit_code = exit_code;
+    record.resources = resources;
+
+    save(dir, &record)
+}
+
+/// Leaves a marker telling a job's daemon that the SIGTERM it's about to get is a kill
+pub fn mark_killed(dir: &str, job_name: &str) -> io::Result<()> {
+    File::create(job_output::job_file_path(dir, job_name, "killed")).map(|_| ())
+}
+
+/// Whether the job was marked killed, clearing the marker
+pub fn take_killed(dir: &str, job_name: &str) -> bool {
+    fs::remove_file(job_output::job_file_path(dir, job_name, "killed")).is_ok()
+}
+
+pub fn set_resumed(dir: &str, job_name: &str, resumed_as: &str) -> io::Result<()> {
+    let mut record = load(dir, job_name)?;
+    record.resumed_as = Some(resumed_as.to_string());
+    save(dir, &record)
+}
+
+pub fn set_progress(dir: &str, job_name: &str, progress: &JobProgress) -> io::Result<()> {
+    let mut record = load(dir, job_name)?;
+    record.progress = Some(progress.clone());
+    save(dir, &record)
+}
+
+pub fn add_alert(dir: &str, job_name: &str, alert: &Alert) -> io::Result<()> {
+    let mut record = load(dir, job_name)?;
+    record.alerts.push(alert.clone());
+    save(dir, &record)
+}
+
+/// Adds an artifact, replacing any registered earlier from the same path. Artifacts are kept by
+/// name on the server, so one from another path with a name already in use is numbered, e.g.
+/// `model-2.pt`. Returns the artifact under the name it was registered with.
+pub fn add_artifact(dir: &str, job_name: &str, artifact: &Artifact) -> io::Result<Artifact> {
+    let mut record = load(dir, job_name)?;
+    let mut artifact = artifact.clone();
+
+    match record.artifacts.iter().position(|a| a.path == artifact.path) {
+        Some(index) => {
+            artifact.name = record.artifacts[index].name.clone();
+            record.artifacts[index] = artifact.clone();
+        },
+        None => {
+            let taken = |name: &str| record.artifacts.iter().any(|a| a.name == name);
+            if taken(artifact.name.as_str()) {
+                artifact.name = (2..).map(|n| numbered(&artifact.name, n)).find(|name| !taken(name.as_str())).unwrap();
+            }
+            record.artifacts.push(artifact.clone());
+        },
+    }
+
+    save(dir, &record)?;
+    Ok(artifact)
+}
+
+// `model.pt` becomes `model-<n>.pt`
+fn numbered(name: &str, n: u32) -> String {
+    match name.rfind('.') {
+        Some(i) if i > 0 => format!("{}-{}{}", &name[..i], n, &name[i..]),
+        _ => format!("{}-{}", name, n),
+    }
+}
+
+/// Every job with a record in `dir`
+pub fn list(dir: &str) -> io::Result<Vec<JobRecord>> {
+    let mut records = Vec::new();
+
+    for name in job_output::job_names(dir)? {
+        if let Ok(record) = load(dir, &name) {
+            records.push(record);
+        }
+    }
+
+    records.sort_by_key(|r| r.started_at);
+    Ok(records)
+}
+
+/// Reads the daemon's pid file for a job, if there is one
+pub fn read_pid(dir: &str, job_name: &str) -> Option<i32> {
+    let contents = fs::read_to_string(job_output::job_file_path(dir, job_name, "pid")).ok()?;
+    contents.trim().parse().ok()
+}
+
+pub fn pid_alive(pid: i32) -> bool {
+    // Signal 0 only checks that the process exists and we could signal it
+    let result = unsafe { libc::kill(pid, 0) };
+    result == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Whether a job's daemon is still alive according to its pid file. A job recorded as finished
+/// isn't, whatever has the pid now.
+pub fn is_running(dir: &str, job_name: &str) -> bool {
+    if load(dir, job_name).map_or(false, |record| record.finished_at.is_some()) {
+        return false;
+    }
+
+    match read_pid(dir, job_name) {
+        Some(pid) => pid_alive(pid),
+        None => false,
+    }
+}
@@ -0,0 +1,257 @@
+// Job specs
+// Every job keeps the spec it was started from: its command, the `--env` variables and working
+// directory it ran with, its description and its other `new job` options. It's saved in the
+// local job record and sent to the server, so `rerun job` and `new job --from` can start the same
+// job again, from this machine or any other, with options given on the command line replacing
+// the saved ones. `--env` values are often secrets, so the server only gets the variables' names.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use clap::{App, ArgMatches};
+
+use config::Config;
+use invocation::{Invocation, Script};
+use job_registry;
+use krephis;
+use protocol::JobSpec;
+
+// `new job` options kept in the spec's `options`, with their flags
+const VALUE_ARGS: &[(&str, &str)] = &[
+    ("OUTPUT_DIR", "--output-dir"),
+    ("ROTATE_SIZE", "--rotate-size"),
+    ("ROTATE_INTERVAL", "--rotate-interval"),
+    ("COMPRESS", "--compress"),
+    ("SAMPLE_INTERVAL", "--sample-interval"),
+    ("NOTIFY", "--notify"),
+    ("ALERT_ON", "--alert-on"),
+    ("FAIL_ON", "--fail-on"),
+    ("TAG", "--tag"),
+    ("SHELL", "--shell"),
+    ("GRACE_PERIOD", "--grace-period"),
+    ("RESUME_CMD", "--resume-cmd"),
+];
+const FLAG_ARGS: &[(&str, &str)] = &[
+    ("UPLOAD_ARTIFACTS", "--upload-artifacts"),
+    ("STDIN_PIPE", "--stdin-pipe"),
+    ("TTY", "--tty"),
+];
+
+// Options that pick where and as what the job runs, which only apply to the job they're given for
+const LAUNCH_ARGS: &[(&str, &str)] = &[
+    ("JOB_NAME", "--name"),
+    ("HOST", "--host"),
+    ("HOSTS", "--hosts"),
+    ("HOSTS_FILE", "--hosts-file"),
+    ("GROUP", "--group"),
+    ("STDIN_FILE", "--stdin-file"),
+];
+const LAUNCH_FLAGS: &[(&str, &str)] = &[
+    ("REPLACE", "--replace"),
+    ("AUTO_SUFFIX", "--auto-suffix"),
+];
+
+/// The spec of a job `new job` is starting, which runs `invocation` as `argv`
+pub fn from_matches(command: &ArgMatches, workdir: &str, invocation: &Invocation, argv: &[String]) -> JobSpec {
+    let (command_text, words, script) = match *invocation {
+        Invocation::Shell { command: ref c, .. } => (c.clone(), Vec::new(), None),
+        Invocation::Direct(ref words) => (invocation.display(), words.clone(), None),
+        Invocation::Script { ref script, ref args, .. } => (script.file_name(), args.clone(), Some(script.contents.clone())),
+    };
+
+    JobSpec {
+        command: command_text,
+        env: command.values_of("ENV").into_iter().flatten().map(|e| e.to_string()).collect(),
+        workdir: workdir.to_string(),
+        description: command.value_of("DESCRIPTION").unwrap_or("").to_string(),
+        options: option_args(command),
+        argv: words,
+        script: script,
+        invocation: argv.to_vec(),
+    }
+}
+
+/// The copy of a spec sent to the server, with only the names of its `--env` variables
+pub fn for_server(spec: &JobSpec) -> JobSpec {
+    let mut spec = spec.clone();
+    spec.env = spec.env.iter().map(|e| e.split('=').next().unwrap_or("").to_string()).collect();
+    spec
+}
+
+/// The spec's options given to `command`, as command line arguments
+pub fn option_args(command: &ArgMatches) -> Vec<String> {
+    let mut args = Vec::new();
+
+    for &(name, flag) in VALUE_ARGS {
+        for value in command.values_of(name).into_iter().flatten() {
+            args.push(flag.to_string());
+            args.push(value.to_string());
+        }
+    }
+
+    for &(name, flag) in FLAG_ARGS {
+        if command.is_present(name) {
+            args.push(flag.to_string());
+        }
+    }
+
+    args
+}
+
+/// Splits `--env` values into names and values, in order
+pub fn parse_env<'a, I: IntoIterator<Item = &'a str>>(values: I) -> Result<Vec<(String, String)>, String> {
+    values.into_iter().map(|pair| {
+        match pair.find('=') {
+            Some(i) if i > 0 => Ok((pair[..i].to_string(), pair[i + 1..].to_string())),
+            _ => Err(format!("Invalid --env {}, expected NAME=VALUE", pair)),
+        }
+    }).collect()
+}
+
+/// A job's spec, from its local record if it ran here and from the server otherwise
+pub fn load(job_name: &str, config: &Config) -> Result<JobSpec, String> {
+    if let Some(spec) = job_registry::load(&config.output_dir, job_name).ok().and_then(|r| r.spec) {
+        return Ok(spec);
+    }
+
+    match krephis::spec(job_name) {
+        Ok(Some(spec)) => Ok(spec),
+        Ok(None) => Err(format!("{} was started by a kraken that didn't keep its spec, so it can't be run again", job_name)),
+        Err(e) => Err(format!("Couldn't get the spec of {}: {}", job_name, e)),
+    }
+}
+
+/// Arguments for `new job` that start `spec` again, with the options in `overrides` replacing
+/// the saved ones. `new_job` parses the saved options the same way `new job` did.
+pub fn relaunch_args(spec: &JobSpec, overrides: &ArgMatches, new_job: App) -> Result<Vec<String>, String> {
+    let saved = saved_options(spec, new_job)?;
+
+    let mut args = vec!["job".to_string()];
+
+    for &(name, flag) in LAUNCH_ARGS {
+        if let Some(value) = overrides.value_of(name) {
+            args.push(flag.to_string());
+            args.push(value.to_string());
+        }
+    }
+
+    for &(name, flag) in LAUNCH_FLAGS {
+        if overrides.is_present(name) {
+            args.push(flag.to_string());
+        }
+    }
+
+    args.push("--description".to_string());
+    args.push(overrides.value_of("DESCRIPTION").unwrap_or(&spec.description).to_string());
+
+    args.push("--workdir".to_string());
+    args.push(overrides.value_of("WORKDIR").unwrap_or(&spec.workdir).to_string());
+
+    // Variables given again replace the saved ones, the rest are kept
+    let given = parse_env(overrides.values_of("ENV").into_iter().flatten())?;
+    let mut variables = BTreeMap::new();
+    for entry in &spec.env {
+        let (name, value) = match entry.find('=') {
+            Some(_) => parse_env(Some(entry.as_str()))?.remove(0),
+            // Specs from the server only have the name, so the value comes from here
+            None if given.iter().any(|&(ref name, _)| name == entry) => continue,
+            None => match env::var(entry) {
+                Ok(value) => (entry.clone(), value),
+                Err(_) => return Err(format!(
+                    "The job was started with --env {0}, whose value isn't kept on the server. Set {0} here or give --env {0}=VALUE.",
+                    entry
+                )),
+            },
+        };
+        variables.insert(name, value);
+    }
+    for (name, value) in given {
+        variables.insert(name, value);
+    }
+    for (name, value) in variables {
+        args.push("--env".to_string());
+        args.push(format!("{}={}", name, value));
+    }
+
+    // A command given here replaces the saved one, however either was given
+    let replaced = overrides.is_present("COMMAND") || overrides.is_present("ARGV") || overrides.is_present("SCRIPT");
+    let (script, command, argv) = if replaced {
+        (
+            overrides.value_of("SCRIPT").map(|s| s.to_string()),
+            overrides.value_of("COMMAND").map(|c| c.to_string()),
+            overrides.values_of("ARGV").into_iter().flatten().map(|a| a.to_string()).collect(),
+        )
+    } else {
+        match spec.script {
+            Some(ref contents) => (Some(write_script(spec, contents)?), None, spec.argv.clone()),
+            None if !spec.argv.is_empty() => (None, None, spec.argv.clone()),
+            None => (None, Some(spec.command.clone()), Vec::new()),
+        }
+    };
+
+    for &(name, flag) in VALUE_ARGS {
+        // A program run directly has no shell
+        if name == "SHELL" && script.is_none() && !argv.is_empty() {
+            continue;
+        }
+
+        let values = if overrides.is_present(name) { overrides.values_of(name) } else { saved.values_of(name) };
+        for value in values.into_iter().flatten() {
+            args.push(flag.to_string());
+            args.push(value.to_string());
+        }
+    }
+
+    for &(name, flag) in FLAG_ARGS {
+        if overrides.is_present(name) || saved.is_present(name) {
+            args.push(flag.to_string());
+        }
+    }
+
+    if let Some(script) = script {
+        args.push("--script".to_string());
+        args.push(script);
+    }
+
+    if let Some(command) = command {
+        args.push(command);
+    }
+
+    if !argv.is_empty() {
+        args.push("--".to_string());
+        args.extend(argv);
+    }
+
+    Ok(args)
+}
+
+/// The `--resume-cmd` `kraken resume` runs instead of the job's command, if it was given one
+pub fn resume_command(spec: &JobSpec, new_job: App) -> Result<Option<String>, String> {
+    Ok(saved_options(spec, new_job)?.value_of("RESUME_CMD").map(|c| c.to_string()))
+}
+
+// The spec's options as `new job` parsed them, with a placeholder for the command
+fn saved_options<'a>(spec: &JobSpec, new_job: App<'a, '_>) -> Result<ArgMatches<'a>, String> {
+    let mut saved_args = vec!["job".to_string()];
+    saved_args.extend(spec.options.iter().cloned());
+    saved_args.push("true".to_string());
+
+    new_job.get_matches_from_safe(saved_args).map_err(|e| format!("Couldn't read the saved options: {}", e.message))
+}
+
+/// Where `relaunch_args` writes a saved script for `new job --script` to read
+pub fn relaunch_dir() -> PathBuf {
+    env::temp_dir().join(format!("kraken-rerun-{}", process::id()))
+}
+
+// Writes the script of `spec` out under its own file name, so the new job shows the same command
+fn write_script(spec: &JobSpec, contents: &str) -> Result<String, String> {
+    let file_name = Path::new(&spec.command).file_name().map(|n| n.to_os_string()).unwrap_or("script".into());
+    let path = relaunch_dir().join(file_name);
+
+    let script = Script { path: path.to_string_lossy().into_owned(), contents: contents.to_string() };
+    script.save(&path)?;
+    Ok(script.path)
+}
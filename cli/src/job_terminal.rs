@@ -0,0 +1,363 @@
+// Job terminals
+// `new job --tty` runs the job under a pseudo-terminal whose other end the daemon holds, for
+// programs that only work interactively on a terminal, like shells, REPLs and debuggers. Its
+// output is logged and shipped like any other job's. `kraken attach job` connects to it over a
+// Unix socket next to the job's output to watch it live and type into it, until the job exits or
+// the detach keys leave it running.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::ptr;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use termion;
+use termion::raw::IntoRawMode;
+
+use job_output;
+
+// Ctrl-p Ctrl-q, as in docker, since Ctrl-a and Ctrl-b mean something to shells
+const DETACH_KEYS: [u8; 2] = [0x10, 0x11];
+pub const DETACH_KEYS_NAME: &str = "Ctrl-p Ctrl-q";
+
+// How much of the job's latest output is shown to someone attaching, so they see where it's at
+const REPLAY_BYTES: usize = 16 * 1024;
+
+// Chunks of output queued for someone attached before they count as stalled and are dropped,
+// since the job's output can't wait for them
+const CLIENT_QUEUE: usize = 256;
+
+// The terminal's size until someone attaches with their own
+const DEFAULT_ROWS: u16 = 24;
+const DEFAULT_COLS: u16 = 80;
+
+/// The socket `attach job` connects to
+pub fn socket_path(dir: &str, job_name: &str) -> PathBuf {
+    job_output::job_file_path(dir, job_name, "tty")
+}
+
+/// A pseudo-terminal for a job. The job gets `slave` as its stdin, stdout and stderr, and kraken
+/// reads and writes the `master` end.
+pub struct Terminal {
+    pub master: File,
+    pub slave: File,
+}
+
+pub fn open() -> io::Result<Terminal> {
+    let mut master = 0;
+    let mut slave = 0;
+    let size = libc::winsize { ws_row: DEFAULT_ROWS, ws_col: DEFAULT_COLS, ws_xpixel: 0, ws_ypixel: 0 };
+
+    if unsafe { libc::openpty(&mut master, &mut slave, ptr::null_mut(), ptr::null(), &size) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Neither end should leak into the job's children except as their stdio
+    unsafe {
+        libc::fcntl(master, libc::F_SETFD, libc::FD_CLOEXEC);
+        libc::fcntl(slave, libc::F_SETFD, libc::FD_CLOEXEC);
+    }
+
+    Ok(Terminal {
+        master: unsafe { File::from_raw_fd(master) },
+        slave: unsafe { File::from_raw_fd(slave) },
+    })
+}
+
+impl Terminal {
+    /// Runs `command` on the terminal, in a session of its own with the terminal as its
+    /// controlling terminal, so Ctrl-c typed into it interrupts the job. The job's end of the
+    /// terminal is handed over, so reading the master ends when the job and its children exit.
+    pub fn run_on(self, command: &mut Command) -> io::Result<File> {
+        command
+            .stdin(Stdio::from(self.slave.try_clone()?))
+            .stdout(Stdio::from(self.slave.try_clone()?))
+            .stderr(Stdio::from(self.slave));
+
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+
+                // Kraken started in the background ignores these, which the job would inherit
+                for &signal in &[libc::SIGINT, libc::SIGQUIT, libc::SIGTSTP] {
+                    libc::signal(signal, libc::SIG_DFL);
+                }
+                Ok(())
+            });
+        }
+
+        Ok(self.master)
+    }
+}
+
+fn set_size(master: &File, rows: u16, cols: u16) {
+    let size = libc::winsize { ws_row: rows, ws_col: cols, ws_xpixel: 0, ws_ypixel: 0 };
+    unsafe {
+        libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ as _, &size);
+    }
+}
+
+// Someone attached, whose output is written by a thread of its own
+struct Client {
+    queue: SyncSender<Vec<u8>>,
+    stream: UnixStream,
+}
+
+struct Attached {
+    clients: Vec<Client>,
+    replay: Vec<u8>,
+    closed: bool,
+}
+
+/// The job's output, read from its terminal and copied to everyone attached to it
+pub struct Output {
+    master: File,
+    attached: Arc<Mutex<Attached>>,
+}
+
+/// Lets `attach job` connect to the job's terminal through its socket, writing what's typed to
+/// the terminal. The job's output has to be read from the returned `Output` for them to see it.
+pub fn share(dir: &str, job_name: &str, master: File) -> io::Result<Output> {
+    let path = socket_path(dir, job_name);
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+
+    // Whoever can connect can type into the job
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+
+    let attached = Arc::new(Mutex::new(Attached { clients: Vec::new(), replay: Vec::new(), closed: false }));
+    let input = master.try_clone()?;
+    let shared = attached.clone();
+
+    thread::spawn(move || {
+        for client in listener.incoming() {
+            let client = match client {
+                Ok(client) => client,
+                Err(_) => continue,
+            };
+
+            let input = match input.try_clone() {
+                Ok(input) => input,
+                Err(_) => continue,
+            };
+
+            let shared = shared.clone();
+            thread::spawn(move || serve(client, input, shared));
+        }
+    });
+
+    Ok(Output { master: master, attached: attached })
+}
+
+// Shows one client the job's recent output and passes what they type to the job
+fn serve(client: UnixStream, mut input: File, attached: Arc<Mutex<Attached>>) {
+    // The client starts with its terminal's size, `ROWS COLS`, read a byte at a time so none of
+    // what's typed after it is taken
+    let mut header = Vec::new();
+    let mut byte = [0];
+    loop {
+        if (&client).read_exact(&mut byte).is_err() {
+            return;
+        }
+        if byte[0] == b'\n' {
+            break;
+        }
+        header.push(byte[0]);
+    }
+
+    let size: Vec<u16> = String::from_utf8_lossy(&header).split_whitespace().filter_map(|n| n.parse::<u16>().ok()).collect();
+    if size.len() == 2 && size[0] > 0 && size[1] > 0 {
+        set_size(&input, size[0], size[1]);
+    }
+
+    let (output, stream) = match (client.try_clone(), client.try_clone()) {
+        (Ok(output), Ok(stream)) => (output, stream),
+        _ => return,
+    };
+
+    {
+        let mut attached = attached.lock().unwrap();
+        if attached.closed {
+            return;
+        }
+
+        // Queued under the lock, so no output is missed or shown twice between the replay and the rest
+        let (queue, queued) = mpsc::sync_channel(CLIENT_QUEUE);
+        let _ = queue.try_send(attached.replay.clone());
+        attached.clients.push(Client { queue: queue, stream: stream });
+        thread::spawn(move || write_output(output, queued));
+    }
+
+    let _ = io::copy(&mut &client, &mut input);
+
+    // Once they detach, what's already been written reaches them and their output ends
+    let _ = client.shutdown(Shutdown::Both);
+}
+
+// Writes a client's queued output until the job's output ends or the client is dropped
+fn write_output(mut output: UnixStream, queued: Receiver<Vec<u8>>) {
+    for chunk in queued {
+        if output.write_all(&chunk).is_err() {
+            break;
+        }
+    }
+    let _ = output.shutdown(Shutdown::Both);
+}
+
+impl Read for Output {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = match self.master.read(buf) {
+            Ok(read) => read,
+            // Linux reports the job's end of the terminal closing as an error rather than an EOF
+            Err(ref e) if e.raw_os_error() == Some(libc::EIO) => 0,
+            Err(e) => return Err(e),
+        };
+
+        let mut attached = self.attached.lock().unwrap();
+
+        // Their threads finish writing what's queued and hang up
+        if read == 0 {
+            attached.closed = true;
+            attached.clients.clear();
+            return Ok(0);
+        }
+
+        // Clients that went away or stalled are dropped, hanging up on them so their thread
+        // isn't left waiting to write
+        attached.clients.retain(|client| match client.queue.try_send(buf[..read].to_vec()) {
+            Ok(()) => true,
+            Err(_) => {
+                let _ = client.stream.shutdown(Shutdown::Both);
+                false
+            },
+        });
+
+        attached.replay.extend_from_slice(&buf[..read]);
+        if attached.replay.len() > REPLAY_BYTES {
+            let excess = attached.replay.len() - REPLAY_BYTES;
+            attached.replay.drain(..excess);
+        }
+
+        Ok(read)
+    }
+}
+
+/// How an `attach job` session ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ending {
+    Detached,
+    Exited,
+}
+
+/// Connects this terminal to a job started here with `--tty`, until the job exits or the detach
+/// keys are typed
+pub fn attach(dir: &str, job_name: &str) -> Result<Ending, String> {
+    let stream = UnixStream::connect(socket_path(dir, job_name)).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused => format!(
+            "{} isn't running with a terminal. Jobs started here with --tty can be attached to while they run.",
+            job_name
+        ),
+        _ => format!("Couldn't attach to {}: {}", job_name, e),
+    })?;
+
+    let (cols, rows) = termion::terminal_size().unwrap_or((0, 0));
+    let mut writer = &stream;
+    writer.write_all(format!("{} {}\n", rows, cols).as_bytes())
+        .map_err(|e| format!("Couldn't attach to {}: {}", job_name, e))?;
+
+    // Raw mode hands every key to the job, whose terminal echoes it. Input that isn't a terminal
+    // is passed along as it is.
+    let _raw = if termion::is_tty(&io::stdin()) && termion::is_tty(&io::stdout()) {
+        Some(io::stdout().into_raw_mode().map_err(|e| format!("Couldn't set up the terminal: {}", e))?)
+    } else {
+        None
+    };
+
+    let (ending_sender, ending_receiver) = mpsc::channel();
+
+    let mut output = stream.try_clone().map_err(|e| e.to_string())?;
+    let exited = ending_sender.clone();
+    thread::spawn(move || {
+        let mut buf = [0; 8192];
+        let stdout = io::stdout();
+        loop {
+            match output.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => {
+                    let mut stdout = stdout.lock();
+                    let _ = stdout.write_all(&buf[..read]);
+                    let _ = stdout.flush();
+                },
+            }
+        }
+        let _ = exited.send(Ending::Exited);
+    });
+
+    let mut input = stream.try_clone().map_err(|e| e.to_string())?;
+    thread::spawn(move || {
+        let mut buf = [0; 1024];
+        let mut typed = Vec::new();
+        // The first detach key is held back until the next key shows whether it's a detach
+        let mut held = false;
+
+        loop {
+            // Running out of input leaves the job running, as detaching does
+            let read = match io::stdin().read(&mut buf) {
+                Ok(0) | Err(_) => {
+                    if held {
+                        let _ = input.write_all(&DETACH_KEYS[..1]);
+                    }
+                    let _ = ending_sender.send(Ending::Detached);
+                    return;
+                },
+                Ok(read) => read,
+            };
+
+            typed.clear();
+            for &byte in &buf[..read] {
+                if held {
+                    held = false;
+                    if byte == DETACH_KEYS[1] {
+                        let _ = input.write_all(&typed);
+                        let _ = ending_sender.send(Ending::Detached);
+                        return;
+                    }
+                    typed.push(DETACH_KEYS[0]);
+                }
+
+                if byte == DETACH_KEYS[0] {
+                    held = true;
+                } else {
+                    typed.push(byte);
+                }
+            }
+
+            if input.write_all(&typed).is_err() {
+                return;
+            }
+        }
+    });
+
+    let ending = ending_receiver.recv().unwrap_or(Ending::Exited);
+
+    // The job's output written before detaching is shown before saying so, which the daemon ends
+    // once it sees there's no more input
+    if ending == Ending::Detached {
+        let _ = stream.shutdown(Shutdown::Write);
+        let _ = ending_receiver.recv_timeout(Duration::from_secs(1));
+    }
+
+    let _ = stream.shutdown(Shutdown::Both);
+    Ok(ending)
+}
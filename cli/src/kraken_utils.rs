@@ -1,5 +1,9 @@
-use std::io::{Write, Result};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, BufRead, Write, Result};
 use std::fs::OpenOptions;
+use chrono::prelude::*;
+use protocol;
 
 pub fn store_token(token: &str) {
 
@@ -28,39 +32,123 @@ pub fn retrieve_token() -> Result<String> {
 
 }
 
-#[derive(Deserialize, Debug)]
-pub struct TokenJSON {
-    pub auth: bool,
-    pub token: String
+// Ask a yes/no question on the terminal. Anything but y/yes (including EOF) is a no.
+pub fn confirm(question: &str) -> bool {
+
+    print!("{} [y/N] ", question);
+    let _ = io::stdout().flush();
+
+    let mut answer = String::new();
+    match io::stdin().lock().read_line(&mut answer) {
+        Ok(_) => {
+            let answer = answer.trim().to_lowercase();
+            answer == "y" || answer == "yes"
+        },
+        Err(_) => false,
+    }
+
+}
+
+// Parse an age like `30s`, `15m`, `12h`, `7d` or `2w` into seconds
+pub fn parse_age(age: &str) -> Option<i64> {
+
+    let age = age.trim();
+    let last = age.chars().last()?;
+
+    let (number, unit) = age.split_at(age.len() - last.len_utf8());
+    let (number, multiplier) = match unit {
+        "s" => (number, 1),
+        "m" => (number, 60),
+        "h" => (number, 60 * 60),
+        "d" => (number, 24 * 60 * 60),
+        "w" => (number, 7 * 24 * 60 * 60),
+        _ => (age, 1),
+    };
+
+    number.parse::<i64>().ok().map(|n| n * multiplier)
 }
 
-#[derive(Deserialize, Debug)]
-pub struct JobJSON {
-    pub _id: String,
-    pub machine: String,
-    pub name: String,
-    pub description: Option<String>,
-    pub status: Option<String>,
+// Parse either an RFC 3339 time or an age (see `parse_age`) counted back from now
+pub fn parse_time(time: &str) -> Option<DateTime<Utc>> {
+
+    if let Ok(t) = DateTime::parse_from_rfc3339(time) {
+        return Some(t.with_timezone(&Utc));
+    }
+
+    parse_age(time).map(|secs| Utc::now() - chrono::Duration::seconds(secs))
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct LogJSON {
-    pub job_id: String,
-    pub line: String,
+// Format seconds the way `kraken top` and progress bars show them, like `4m05s` or `2h10m`
+pub fn format_duration(secs: i64) -> String {
+    match secs {
+        s if s < 60 => format!("{}s", s),
+        s if s < 3600 => format!("{}m{:02}s", s / 60, s % 60),
+        s if s < 86400 => format!("{}h{:02}m", s / 3600, (s % 3600) / 60),
+        s => format!("{}d{:02}h", s / 86400, (s % 86400) / 3600),
+    }
 }
 
-#[derive(Deserialize, Debug)]
-pub struct ErrorJSON {
-    pub auth: Option<bool>,
-    pub message: String,
+// Quote a string for a POSIX shell, like the command line `new job --host` runs over ssh
+pub fn shell_quote(s: &str) -> String {
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || "-_./=:@%+,".contains(c)) {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
+}
+
+// Parse `--tag NAME=VALUE` values into a job's labels
+pub fn parse_labels<'a, I: IntoIterator<Item = &'a str>>(tags: I) -> std::result::Result<BTreeMap<String, String>, String> {
+    let mut labels = BTreeMap::new();
+
+    for tag in tags {
+        match tag.find('=') {
+            Some(i) if i > 0 => {
+                labels.insert(tag[..i].to_string(), tag[i + 1..].to_string());
+            },
+            _ => return Err(format!("Invalid --tag {}, expected NAME=VALUE", tag)),
+        }
+    }
+
+    Ok(labels)
+}
+
+// Whether a job's labels match every `--tag` filter, each either NAME=VALUE or just NAME
+pub fn has_tags<S: AsRef<str>>(labels: &BTreeMap<String, String>, filters: &[S]) -> bool {
+    filters.iter().map(|f| f.as_ref()).all(|filter| match filter.find('=') {
+        Some(i) => labels.get(&filter[..i]).map(|v| v.as_str()) == Some(&filter[i + 1..]),
+        None => labels.contains_key(filter),
+    })
+}
+
+// Format labels the way tables show them, like `owner=me, project=resnet`
+pub fn format_labels(labels: &BTreeMap<String, String>) -> String {
+    labels.iter().map(|(name, value)| format!("{}={}", name, value)).collect::<Vec<_>>().join(", ")
+}
+
+// Format a byte count like `512 B`, `1.5 KiB` or `2.0 GiB`
+pub fn format_bytes(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, units[unit])
+    }
 }
 
 // Define a type so we can return multiple types of errors
 pub enum FetchError {
     Http(hyper::Error),
     Json(serde_json::Error),
-    KrakenServerError(ErrorJSON),
+    KrakenServerError(protocol::ErrorResponse),
     Other(String),
 }
 
@@ -75,3 +163,15 @@ impl From<serde_json::Error> for FetchError {
         FetchError::Json(err)
     }
 }
+
+// Same wording as the messages `krephis` prints
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FetchError::Http(ref e) => write!(f, "http error: {}", e),
+            FetchError::Json(ref e) => write!(f, "json parsing error: {}", e),
+            FetchError::KrakenServerError(ref e) => write!(f, "Server error: {}", e.message),
+            FetchError::Other(ref e) => write!(f, "{}", e),
+        }
+    }
+}
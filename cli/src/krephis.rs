@@ -2,15 +2,26 @@
 // https://mtgcardsmith.com/view/krephis-kraken-overlord
 
 use hyper::rt::{self, Future, Stream};
-use hyper::{Client, Body, Request, StatusCode};
+use hyper::{Client, Body, Request, Response, StatusCode};
 use hyper::header::{HeaderValue, HeaderMap};
 use hyper_tls::HttpsConnector;
+use config;
 use kraken_utils;
+use log_line::{self, LogFilter, LogLine};
+use protocol;
 use prettytable::{Table};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::sync::{Arc, Mutex};
+use chrono::prelude::*;
+use futures::sync::mpsc;
 
 // new job command WILL NOT work if this is HTTPS?!? The thread seems to panic or something. Zero visibility.
-static BASE_URL: &str = "http://kraken-grantgumina.herokuapp.com";
-// static BASE_URL: &str = "http://localhost:5000";
+// Set `server_url` in ~/.kraken.json or KRAKEN_SERVER_URL to use a self-hosted kraken-server.
+lazy_static! {
+    static ref BASE_URL: String = config::load().server_url;
+}
 
 pub fn login(email: String, password: String) {
 
@@ -44,18 +55,30 @@ pub fn logout() {
 
 }
 
-pub fn show_jobs() {
+// Every job, or only the ones whose labels match all of `tags`
+pub fn show_jobs(tags: &[&str]) {
     
     match kraken_utils::retrieve_token() {
        
         Ok(token) => {
+
+            let tags: Vec<String> = tags.iter().map(|t| t.to_string()).collect();
             
-            let fut = fetch_jobs(&token).map(|jobs_response| {
+            let fut = fetch_jobs(&token).map(move |jobs_response| {
+
+                let jobs: Vec<&protocol::Job> = jobs_response.iter()
+                    .filter(|job| kraken_utils::has_tags(&job.labels, &tags))
+                    .collect();
+
+                if jobs.is_empty() && !tags.is_empty() {
+                    eprintln!("No jobs tagged {}", tags.join(", "));
+                    return;
+                }
 
                 let mut table = Table::new();
-                table.add_row(row![b->"Job Name", b->"Description", b->"Status"]);
+                table.add_row(row![b->"Job Name", b->"Description", b->"Status", b->"Tags"]);
 
-                for job in &jobs_response {
+                for job in jobs {
 
                     let mut d = Some(String::new());
                     let mut s = Some(String::new());
@@ -67,8 +90,13 @@ pub fn show_jobs() {
                     if job.status.is_some() && job.status != Some("".to_string()) {
                         s = job.status.clone();
                     }
+
+                    // Output matched one of the job's `--alert-on` or `--fail-on` patterns
+                    if let Some(ref warning) = job.warning {
+                        s = Some(format!("{} ({})", s.unwrap(), warning));
+                    }
                     
-                    table.add_row(row![job.name, d.unwrap(), s.unwrap()]);
+                    table.add_row(row![job.name, d.unwrap(), s.unwrap(), kraken_utils::format_labels(&job.labels)]);
                 }
 
                 table.printstd();
@@ -104,16 +132,61 @@ pub fn show_jobs() {
 
 }
 
-pub fn show_job(job_id: &str, line_limit: &str) {
+// Status and exit code of every job one `new job --hosts` started
+pub fn show_group(group: &str) {
+
+    let jobs = match jobs() {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            print_fetch_error(e);
+            return;
+        }
+    };
+
+    let members: Vec<&protocol::Job> = jobs.iter()
+        .filter(|job| job.group.as_ref().map(|g| g.as_str()) == Some(group))
+        .collect();
+
+    if members.is_empty() {
+        eprintln!("No jobs in group {}", group);
+        return;
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![b->"Job Name", b->"Machine", b->"Status", b->"Exit Code"]);
+
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+
+    for job in &members {
+        let status = job.status.clone().unwrap_or_default();
+        let exit_code = job.exit_code.map(|c| c.to_string()).unwrap_or_default();
+
+        table.add_row(row![job.name, job.machine, status, exit_code]);
+        *counts.entry(status).or_insert(0) += 1;
+    }
+
+    table.printstd();
+
+    let counts: Vec<String> = counts.iter().map(|(status, n)| format!("{} {}", n, status)).collect();
+    println!("{} jobs: {}", members.len(), counts.join(", "));
+
+}
+
+pub fn show_job(job_id: &str, line_limit: &str, filter: &LogFilter) {
 
     match kraken_utils::retrieve_token() {
         
         Ok(token) => {
 
-            let fut = fetch_logs(&token, job_id, line_limit).map(|logs_response| {
+            let filter = filter.clone();
+
+            let fut = fetch_logs(&token, job_id, line_limit, filter.since, filter.until).map(move |logs_response| {
 
                 for log in &logs_response {
-                    println!("{}", log.line);
+                    let timestamp = log.timestamp.as_ref().and_then(|t| log_line::parse_timestamp(t));
+                    if filter.matches(timestamp) {
+                        println!("{}", filter.format(timestamp, log.stream.as_ref().map(|s| s.as_str()), &log.line));
+                    }
                 }
 
             }).map_err(|e| {
@@ -147,13 +220,19 @@ pub fn show_job(job_id: &str, line_limit: &str) {
 
 }
 
-pub fn new_job(machine_name: &str, job_name: &str, description: &str) {
+pub fn new_job(machine_name: &str, job_name: &str, description: &str, group: Option<&str>, spec: &protocol::JobSpec, labels: &BTreeMap<String, String>) {
 
     match kraken_utils::retrieve_token() {
        
         Ok(token) => {
 
-            let fut = create_job_async(&token, machine_name, job_name, description).map(|_response| {
+            let name = job_name.to_string();
+
+            let fut = create_job_async(&token, machine_name, job_name, description, group, spec, labels).map(move |status| {
+                // Taken since `new job` checked
+                if status == StatusCode::CONFLICT {
+                    eprintln!("The server already has a job named {}", name);
+                }
             }).map_err(|e| {
 
                 match e {
@@ -184,71 +263,59 @@ pub fn new_job(machine_name: &str, job_name: &str, description: &str) {
 
 }
 
-pub fn new_log(job_id: &str, line: &str) {
-    
+// Remove a job, printing any error
+pub fn remove_job(job_name: &str) -> Result<(), kraken_utils::FetchError> {
+    let result = delete_job(job_name);
+
+    if let Err(ref e) = result {
+        eprintln!("{}", e);
+    }
+
+    result
+}
+
+// Fetch every job on the server, printing any error
+pub fn list_jobs() -> Option<Vec<protocol::Job>> {
+
     match kraken_utils::retrieve_token() {
-       
-        Ok(token) => {
 
-            let fut = create_log_async(&token, job_id, line).map(|_response| {
-                
-            }).map_err(|e| {
+        Ok(token) => {
 
-                match e {
-                    kraken_utils::FetchError::Http(e) => {
-                        eprintln!("http error: {}", e);
-                    },
-                    kraken_utils::FetchError::Json(e) => {
-                        eprintln!("json parsing error: {}", e);
-                    },
-                    kraken_utils::FetchError::KrakenServerError(e) => {
-                        eprintln!("Server error: {}", e.message);
-                    },                    
-                    kraken_utils::FetchError::Other(e) => {
-                        eprintln!("Error: {}", e);
-                    }
+            match run_blocking(fetch_jobs(&token)) {
+                Ok(jobs) => Some(jobs),
+                Err(e) => {
+                    print_fetch_error(e);
+                    None
                 }
+            }
 
-            });
-
-            rt::run(fut);
         },
 
         Err(error) => {
-            eprintln!("Run `kraken login` to authenticate this machine. Token will be stored in ~/.krakenrc.\n{}", error)
+            eprintln!("Run `kraken login` to authenticate this machine. Token will be stored in ~/.krakenrc.\n{}", error);
+            None
         }
 
     }
+
 }
 
-pub fn remove_job(job_name: &str) {
+// Undo a removal while the server still holds the soft-deleted job
+pub fn restore_job(job_name: &str) {
 
     match kraken_utils::retrieve_token() {
-       
-        Ok(token) => {
-
-            let fut = remove_job_async(&token, job_name).map(|_response| {
-                
-            }).map_err(|e| {
 
-                match e {
-                    kraken_utils::FetchError::Http(e) => {
-                        eprintln!("http error: {}", e);
-                    },
-                    kraken_utils::FetchError::Json(e) => {
-                        eprintln!("json parsing error: {}", e);
-                    },
-                    kraken_utils::FetchError::KrakenServerError(e) => {
-                        eprintln!("Server error: {}", e.message);
-                    },                    
-                    kraken_utils::FetchError::Other(e) => {
-                        eprintln!("Error: {}", e);
-                    }
-                }
+        Ok(token) => {
 
-            });
+            match run_blocking(restore_job_async(&token, job_name)) {
+                Ok(StatusCode::OK) => println!("Restored {}", job_name),
+                Ok(StatusCode::NOT_FOUND) => eprintln!("No removed job named {}", job_name),
+                Ok(StatusCode::GONE) => eprintln!("The undo window for {} has expired", job_name),
+                Ok(StatusCode::CONFLICT) => eprintln!("A live job already uses the name {}. Remove or rename it first.", job_name),
+                Ok(status) => eprintln!("Server error: couldn't restore {} ({})", job_name, status),
+                Err(e) => print_fetch_error(e),
+            }
 
-            rt::run(fut);
         },
 
         Err(error) => {
@@ -259,60 +326,202 @@ pub fn remove_job(job_name: &str) {
 
 }
 
-pub fn remove_all_jobs() {
-     match kraken_utils::retrieve_token() {
-       
-        Ok(token) => {
+// The calls below hand errors back instead of printing them, for callers like the log shipper
+// and `kraken top` that handle errors themselves
 
-            let fut = remove_all_jobs_async(&token).map(|_response| {
-                
-            }).map_err(|e| {
+pub fn jobs() -> Result<Vec<protocol::Job>, kraken_utils::FetchError> {
+    let token = require_token()?;
+    run_blocking(fetch_jobs(&token))
+}
 
-                match e {
-                    kraken_utils::FetchError::Http(e) => {
-                        eprintln!("http error: {}", e);
-                    },
-                    kraken_utils::FetchError::Json(e) => {
-                        eprintln!("json parsing error: {}", e);
-                    },
-                    kraken_utils::FetchError::KrakenServerError(e) => {
-                        eprintln!("Server error: {}", e.message);
-                    },                    
-                    kraken_utils::FetchError::Other(e) => {
-                        eprintln!("Error: {}", e);
-                    }
-                }
+// The last `line_limit` lines of a job
+pub fn logs(job_name: &str, line_limit: usize) -> Result<Vec<protocol::Log>, kraken_utils::FetchError> {
+    let token = require_token()?;
+    run_blocking(fetch_logs(&token, job_name, &line_limit.to_string(), None, None))
+}
 
-            });
+pub fn delete_job(job_name: &str) -> Result<(), kraken_utils::FetchError> {
+    let token = require_token()?;
 
-            rt::run(fut);
-        },
+    match run_blocking(remove_job_async(&token, job_name))? {
+        status if status.is_success() => Ok(()),
+        status => Err(kraken_utils::FetchError::Other(format!("couldn't remove {} ({})", job_name, status))),
+    }
+}
 
-        Err(error) => {
-            eprintln!("Run `kraken login` to authenticate this machine. Token will be stored in ~/.krakenrc.\n{}", error)
+// Sends one line, failing unless the server stored it
+pub fn send_log(job_name: &str, line: &LogLine) -> Result<(), kraken_utils::FetchError> {
+    let token = require_token()?;
+    run_blocking(send_log_async(&token, job_name, line))
+}
+
+// Where a streaming upload should resume from. None when the server can't stream.
+pub fn stream_ack(job_name: &str) -> Result<Option<u64>, kraken_utils::FetchError> {
+    let token = require_token()?;
+    run_blocking(fetch_stream_ack(&token, job_name))
+}
+
+// Streams newline-delimited lines until `lines` ends, returning the last seq the server stored
+pub fn stream_logs(job_name: &str, lines: mpsc::UnboundedReceiver<String>) -> Result<u64, kraken_utils::FetchError> {
+    let token = require_token()?;
+    run_blocking(stream_logs_async(&token, job_name, lines))
+}
+
+// Tell the server how the job ended
+pub fn finish_job(job_name: &str, exit: &protocol::JobExit) -> Result<(), kraken_utils::FetchError> {
+    let token = require_token()?;
+    run_blocking(finish_job_async(&token, job_name, exit))
+}
+
+// Replaces the job's progress on the server with a newer report
+pub fn set_progress(job_name: &str, progress: &protocol::JobProgress) -> Result<(), kraken_utils::FetchError> {
+    let token = require_token()?;
+    run_blocking(set_progress_async(&token, job_name, progress))
+}
+
+// The latest progress the job reported, if it ever has
+pub fn progress(job_name: &str) -> Result<Option<protocol::JobProgress>, kraken_utils::FetchError> {
+    let token = require_token()?;
+    run_blocking(fetch_progress(&token, job_name))
+}
+
+pub fn set_resources(job_name: &str, resources: &protocol::ResourceSummary) -> Result<(), kraken_utils::FetchError> {
+    let token = require_token()?;
+    run_blocking(set_resources_async(&token, job_name, resources))
+}
+
+// What the job used, once it has exited
+pub fn resources(job_name: &str) -> Result<Option<protocol::ResourceSummary>, kraken_utils::FetchError> {
+    let token = require_token()?;
+    run_blocking(fetch_resources(&token, job_name))
+}
+
+// What the job ran, if the client that started it sent its spec
+pub fn spec(job_name: &str) -> Result<Option<protocol::JobSpec>, kraken_utils::FetchError> {
+    let token = require_token()?;
+    run_blocking(fetch_spec(&token, job_name))
+}
+
+pub fn send_metrics(job_name: &str, metrics: &[protocol::Metric]) -> Result<(), kraken_utils::FetchError> {
+    let token = require_token()?;
+    run_blocking(send_metrics_async(&token, job_name, metrics))
+}
+
+pub fn metrics(job_name: &str) -> Result<Vec<protocol::Metric>, kraken_utils::FetchError> {
+    let token = require_token()?;
+    run_blocking(fetch_metrics(&token, job_name))
+}
+
+pub fn send_alert(job_name: &str, alert: &protocol::Alert) -> Result<(), kraken_utils::FetchError> {
+    let token = require_token()?;
+    run_blocking(send_alert_async(&token, job_name, alert))
+}
+
+// Artifacts uploaded to the server
+pub fn artifacts(job_name: &str) -> Result<Vec<protocol::Artifact>, kraken_utils::FetchError> {
+    let token = require_token()?;
+    run_blocking(fetch_artifacts(&token, job_name))
+}
+
+pub fn upload_artifact(job_name: &str, artifact: &protocol::Artifact) -> Result<(), kraken_utils::FetchError> {
+    let token = require_token()?;
+
+    let path = artifact.path.clone().unwrap_or_default();
+    let contents = fs::read(&path).map_err(|e| {
+        kraken_utils::FetchError::Other(format!("couldn't read {}: {}", path, e))
+    })?;
+
+    run_blocking(upload_artifact_async(&token, job_name, &artifact.name, contents))
+}
+
+// POST a JSON body to some other server, like a job's `--notify webhook:` target
+pub fn post_webhook(url: &str, body: String) -> Result<(), kraken_utils::FetchError> {
+    let url: hyper::Uri = url.parse().map_err(|e| {
+        kraken_utils::FetchError::Other(format!("invalid webhook URL {}: {}", url, e))
+    })?;
+
+    run_blocking(post_webhook_async(url, body))
+}
+
+pub fn log_request(job_name: &str, line: &LogLine) -> protocol::NewLogRequest {
+    protocol::NewLogRequest {
+        job_name: job_name.to_string(),
+        line: line.text.clone(),
+        seq: Some(line.seq),
+        timestamp: Some(log_line::format_timestamp(&line.timestamp)),
+        stream: Some(line.stream.as_str().to_string()),
+    }
+}
+
+fn require_token() -> Result<String, kraken_utils::FetchError> {
+    kraken_utils::retrieve_token().map_err(|e| {
+        kraken_utils::FetchError::Other(format!("Run `kraken login` to authenticate this machine. {}", e))
+    })
+}
+
+fn print_fetch_error(e: kraken_utils::FetchError) {
+    match e {
+        kraken_utils::FetchError::Http(e) => {
+            eprintln!("http error: {}", e);
+        },
+        kraken_utils::FetchError::Json(e) => {
+            eprintln!("json parsing error: {}", e);
+        },
+        kraken_utils::FetchError::KrakenServerError(e) => {
+            eprintln!("Server error: {}", e.message);
+        },
+        kraken_utils::FetchError::Other(e) => {
+            eprintln!("Error: {}", e);
         }
+    }
+}
+
+// Warn when the server speaks an API version this kraken doesn't understand
+fn check_api_version(res: &Response<Body>) {
+    let version = res.headers().get(protocol::API_VERSION_HEADER).and_then(|v| v.to_str().ok());
 
+    if !protocol::compatible(version) {
+        eprintln!("Warning: the server speaks API version {} but this kraken speaks {}", version.unwrap_or("?"), protocol::API_VERSION);
     }
 }
 
+// Drive a future on its own runtime and hand its result back to the caller
+fn run_blocking<F, T>(fut: F) -> Result<T, kraken_utils::FetchError>
+    where F: Future<Item = T, Error = kraken_utils::FetchError> + Send + 'static,
+          T: Send + 'static
+{
+    let result = Arc::new(Mutex::new(None));
+    let slot = result.clone();
+
+    rt::run(fut.then(move |r| {
+        *slot.lock().unwrap() = Some(r);
+        Ok::<(), ()>(())
+    }));
+
+    let r = result.lock().unwrap().take();
+    r.unwrap_or(Err(kraken_utils::FetchError::Other("request never completed".to_string())))
+}
+
 // Futures functions
-fn fetch_jobs(token: &str) -> impl Future<Item = Vec<kraken_utils::JobJSON>, Error = kraken_utils::FetchError> {
+fn fetch_jobs(token: &str) -> impl Future<Item = Vec<protocol::Job>, Error = kraken_utils::FetchError> {
     
     let https_connector = HttpsConnector::new(4).unwrap();
     let client = Client::builder().build(https_connector);
     let mut req = Request::new(Body::empty());
 
-    let url: hyper::Uri = format!("{}/jobs", BASE_URL).parse().unwrap();    
+    let url: hyper::Uri = format!("{}/jobs", *BASE_URL).parse().unwrap();    
     let mut headers = HeaderMap::new();
     let method = hyper::Method::GET;
     
     headers.insert("x-access-token", HeaderValue::from_str(&token).unwrap());
+    headers.insert(protocol::API_VERSION_HEADER, HeaderValue::from_static(protocol::API_VERSION));
 
     *req.method_mut() = method;
     *req.uri_mut() = url;
     *req.headers_mut() = headers;
 
     client.request(req).and_then(|res| {
+        check_api_version(&res);
         
         let status = res.status();
         res.into_body().concat2().and_then(move |body| Ok((status, body)))
@@ -320,11 +529,11 @@ fn fetch_jobs(token: &str) -> impl Future<Item = Vec<kraken_utils::JobJSON>, Err
     }).from_err::<kraken_utils::FetchError>()
     .and_then(|(status, body)| {
 
-        if status == 500 {
-            let error_json: kraken_utils::ErrorJSON = serde_json::from_slice(&body)?;
+        if !status.is_success() {
+            let error_json: protocol::ErrorResponse = serde_json::from_slice(&body)?;
             Err(kraken_utils::FetchError::KrakenServerError(error_json))
         } else {
-            let json_response: Vec<kraken_utils::JobJSON> = serde_json::from_slice(&body)?;
+            let json_response: Vec<protocol::Job> = serde_json::from_slice(&body)?;
             Ok(json_response)
         }
 
@@ -333,23 +542,34 @@ fn fetch_jobs(token: &str) -> impl Future<Item = Vec<kraken_utils::JobJSON>, Err
     
 }
 
-fn fetch_logs(token: &str, job_id: &str, line_limit: &str) -> impl Future<Item = Vec<kraken_utils::LogJSON>, Error = kraken_utils::FetchError> {
+fn fetch_logs(token: &str, job_id: &str, line_limit: &str, since: Option<DateTime<Utc>>, until: Option<DateTime<Utc>>) -> impl Future<Item = Vec<protocol::Log>, Error = kraken_utils::FetchError> {
     
     let https_connector = HttpsConnector::new(4).unwrap();
     let client = Client::builder().build(https_connector);
     let mut req = Request::new(Body::empty());
-    let url: hyper::Uri = format!("{}/jobs/{}", BASE_URL, job_id).parse().unwrap();    
+    let url: hyper::Uri = format!("{}/jobs/{}", *BASE_URL, protocol::encode_path_segment(job_id)).parse().unwrap();    
     let mut headers = HeaderMap::new();
     let method = hyper::Method::GET;
 
     headers.insert("x-access-token", HeaderValue::from_str(token).unwrap());
     headers.insert("x-line-limit", HeaderValue::from_str(line_limit).unwrap());
 
+    if let Some(since) = since {
+        headers.insert("x-since", HeaderValue::from_str(&log_line::format_timestamp(&since)).unwrap());
+    }
+
+    if let Some(until) = until {
+        headers.insert("x-until", HeaderValue::from_str(&log_line::format_timestamp(&until)).unwrap());
+    }
+
+    headers.insert(protocol::API_VERSION_HEADER, HeaderValue::from_static(protocol::API_VERSION));
+
     *req.method_mut() = method;
     *req.uri_mut() = url;
     *req.headers_mut() = headers;
 
     client.request(req).and_then(|res| {
+        check_api_version(&res);
         
         let status = res.status();
         res.into_body().concat2().and_then(move |body| Ok((status, body)))
@@ -357,11 +577,11 @@ fn fetch_logs(token: &str, job_id: &str, line_limit: &str) -> impl Future<Item =
     }).from_err::<kraken_utils::FetchError>()
     .and_then(|(status, body)| {
         
-        if status == 500 {
-            let error_json: kraken_utils::ErrorJSON = serde_json::from_slice(&body)?;
+        if !status.is_success() {
+            let error_json: protocol::ErrorResponse = serde_json::from_slice(&body)?;
             Err(kraken_utils::FetchError::KrakenServerError(error_json))
         } else {
-            let json_response: Vec<kraken_utils::LogJSON> = serde_json::from_slice(&body)?;
+            let json_response: Vec<protocol::Log> = serde_json::from_slice(&body)?;
             Ok(json_response)
         }
 
@@ -370,111 +590,117 @@ fn fetch_logs(token: &str, job_id: &str, line_limit: &str) -> impl Future<Item =
 
 }
 
-fn fetch_token(email: String, password: String) -> impl Future<Item = kraken_utils::TokenJSON, Error = kraken_utils::FetchError> {
+fn fetch_token(email: String, password: String) -> impl Future<Item = protocol::TokenResponse, Error = kraken_utils::FetchError> {
     
-    let url: hyper::Uri = format!("{}/auth/login", BASE_URL).parse().unwrap();
+    let url: hyper::Uri = format!("{}/auth/login", *BASE_URL).parse().unwrap();
     let https_connector = HttpsConnector::new(4).unwrap();
     let client = Client::builder().build(https_connector);
     let method = hyper::Method::POST;
     let mut headers = HeaderMap::new();
 
-    let json_payload = json!({
-        "email": email,
-        "password": password
-    });
+    let payload = protocol::LoginRequest {
+        email: email,
+        password: password,
+    };
 
-    let mut req = Request::new(Body::from(json_payload.to_string()));
+    let mut req = Request::new(Body::from(serde_json::to_string(&payload).unwrap()));
 
     headers.insert(
         hyper::header::CONTENT_TYPE,
         HeaderValue::from_static("application/json")
     );
+    headers.insert(protocol::API_VERSION_HEADER, HeaderValue::from_static(protocol::API_VERSION));
 
     *req.method_mut() = method;
     *req.uri_mut() = url;
     *req.headers_mut() = headers;
 
     client.request(req).and_then(|res| {
+        check_api_version(&res);
        let status = res.status();
         res.into_body().concat2().and_then(move |body| Ok((status, body)))
 
     }).from_err::<kraken_utils::FetchError>()
     .and_then(|(status, body)| {
 
-        if status == 500 {
-            let error_json: kraken_utils::ErrorJSON = serde_json::from_slice(&body)?;
+        if !status.is_success() {
+            let error_json: protocol::ErrorResponse = serde_json::from_slice(&body)?;
             Err(kraken_utils::FetchError::KrakenServerError(error_json))
         } else {
-            let json_response: kraken_utils::TokenJSON = serde_json::from_slice(&body)?;
+            let json_response: protocol::TokenResponse = serde_json::from_slice(&body)?;
             Ok(json_response)
         }
     }).from_err()
 
 }
 
-fn create_job_async(token: &str, machine_name: &str, job_name: &str, description: &str) -> impl Future<Item = StatusCode, Error = kraken_utils::FetchError> {
+fn create_job_async(token: &str, machine_name: &str, job_name: &str, description: &str, group: Option<&str>, spec: &protocol::JobSpec, labels: &BTreeMap<String, String>) -> impl Future<Item = StatusCode, Error = kraken_utils::FetchError> {
     
-    let url: hyper::Uri = format!("{}/jobs/new", BASE_URL).parse().unwrap();
+    let url: hyper::Uri = format!("{}/jobs/new", *BASE_URL).parse().unwrap();
     let https_connector = HttpsConnector::new(4).unwrap();
     let client = Client::builder().build(https_connector);
     let method = hyper::Method::POST;
     let mut headers = HeaderMap::new();
 
-    let json_payload = json!({
-        "machine": machine_name,
-        "name": job_name,
-        "description": description
-    });
+    let payload = protocol::NewJobRequest {
+        machine: machine_name.to_string(),
+        name: job_name.to_string(),
+        description: Some(description.to_string()),
+        group: group.map(|g| g.to_string()),
+        spec: Some(spec.clone()),
+        labels: labels.clone(),
+    };
 
-    let mut req = Request::new(Body::from(json_payload.to_string()));
+    let mut req = Request::new(Body::from(serde_json::to_string(&payload).unwrap()));
 
     headers.insert("x-access-token", HeaderValue::from_str(&token).unwrap());
     headers.insert(hyper::header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert(protocol::API_VERSION_HEADER, HeaderValue::from_static(protocol::API_VERSION));
 
     *req.method_mut() = method;
     *req.uri_mut() = url;
     *req.headers_mut() = headers;
 
     client.request(req).and_then(|res| {
+        check_api_version(&res);
         Ok(res.status())
     }).from_err::<kraken_utils::FetchError>().from_err()
 
 }
 
-fn create_log_async(token: &str, job_name: &str, line: &str) -> impl Future<Item = StatusCode, Error = kraken_utils::FetchError> {
+fn create_log_async(token: &str, job_name: &str, line: &LogLine) -> impl Future<Item = StatusCode, Error = kraken_utils::FetchError> {
 
-    let url: hyper::Uri = format!("{}/logs/new", BASE_URL).parse().unwrap();
+    let url: hyper::Uri = format!("{}/logs/new", *BASE_URL).parse().unwrap();
    
     let https_connector = HttpsConnector::new(4).unwrap();
     let client = Client::builder().build(https_connector);
     let method = hyper::Method::POST;
     let mut headers = HeaderMap::new();
 
-    let json_payload = json!({
-        "jobName": job_name,
-        "line": line,
-    });
+    let payload = log_request(job_name, line);
 
-    let mut req = Request::new(Body::from(json_payload.to_string()));
+    let mut req = Request::new(Body::from(serde_json::to_string(&payload).unwrap()));
 
     headers.insert("x-access-token", HeaderValue::from_str(&token).unwrap());
     headers.insert(
         hyper::header::CONTENT_TYPE,
         HeaderValue::from_static("application/json")
     );
+    headers.insert(protocol::API_VERSION_HEADER, HeaderValue::from_static(protocol::API_VERSION));
 
     *req.method_mut() = method;
     *req.uri_mut() = url;
     *req.headers_mut() = headers;
 
     client.request(req).and_then(|res| {
+        check_api_version(&res);
         Ok(res.status())
     }).from_err::<kraken_utils::FetchError>().from_err()
 
 }
 
 fn remove_job_async(token: &str, job_name: &str) -> impl Future<Item = StatusCode, Error = kraken_utils::FetchError> {
-    let url: hyper::Uri = format!("{}/jobs/{}", BASE_URL, job_name).parse().unwrap();
+    let url: hyper::Uri = format!("{}/jobs/{}", *BASE_URL, protocol::encode_path_segment(job_name)).parse().unwrap();
    
     let https_connector = HttpsConnector::new(4).unwrap();
     let client = Client::builder().build(https_connector);
@@ -485,21 +711,23 @@ fn remove_job_async(token: &str, job_name: &str) -> impl Future<Item = StatusCod
 
     headers.insert("x-access-token", HeaderValue::from_str(&token).unwrap());
     headers.insert(hyper::header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert(protocol::API_VERSION_HEADER, HeaderValue::from_static(protocol::API_VERSION));
 
     *req.method_mut() = method;
     *req.uri_mut() = url;
     *req.headers_mut() = headers;
 
     client.request(req).and_then(|res| {
+        check_api_version(&res);
         Ok(res.status())
     }).from_err::<kraken_utils::FetchError>().from_err()
 
 }
 
-fn remove_all_jobs_async(token: &str) -> impl Future<Item = StatusCode, Error = kraken_utils::FetchError> {
+fn restore_job_async(token: &str, job_name: &str) -> impl Future<Item = StatusCode, Error = kraken_utils::FetchError> {
+
+    let url: hyper::Uri = format!("{}/jobs/{}/restore", *BASE_URL, protocol::encode_path_segment(job_name)).parse().unwrap();
 
-   let url: hyper::Uri = format!("{}/jobs/remove-all", BASE_URL).parse().unwrap();
-   
     let https_connector = HttpsConnector::new(4).unwrap();
     let client = Client::builder().build(https_connector);
     let method = hyper::Method::POST;
@@ -509,13 +737,548 @@ fn remove_all_jobs_async(token: &str) -> impl Future<Item = StatusCode, Error =
 
     headers.insert("x-access-token", HeaderValue::from_str(&token).unwrap());
     headers.insert(hyper::header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert(protocol::API_VERSION_HEADER, HeaderValue::from_static(protocol::API_VERSION));
 
     *req.method_mut() = method;
     *req.uri_mut() = url;
     *req.headers_mut() = headers;
 
     client.request(req).and_then(|res| {
+        check_api_version(&res);
         Ok(res.status())
     }).from_err::<kraken_utils::FetchError>().from_err()
 
-}
\ No newline at end of file
+}
+
+fn send_log_async(token: &str, job_name: &str, line: &LogLine) -> impl Future<Item = (), Error = kraken_utils::FetchError> {
+
+    create_log_async(token, job_name, line).and_then(|status| {
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(kraken_utils::FetchError::KrakenServerError(protocol::ErrorResponse {
+                auth: None,
+                message: format!("couldn't store log line ({})", status),
+            }))
+        }
+    })
+
+}
+
+fn fetch_stream_ack(token: &str, job_name: &str) -> impl Future<Item = Option<u64>, Error = kraken_utils::FetchError> {
+
+    let url: hyper::Uri = format!("{}/logs/stream/{}", *BASE_URL, protocol::encode_path_segment(job_name)).parse().unwrap();
+    let https_connector = HttpsConnector::new(4).unwrap();
+    let client = Client::builder().build(https_connector);
+    let method = hyper::Method::GET;
+    let mut headers = HeaderMap::new();
+
+    let mut req = Request::new(Body::empty());
+
+    headers.insert("x-access-token", HeaderValue::from_str(&token).unwrap());
+    headers.insert(protocol::API_VERSION_HEADER, HeaderValue::from_static(protocol::API_VERSION));
+
+    *req.method_mut() = method;
+    *req.uri_mut() = url;
+    *req.headers_mut() = headers;
+
+    client.request(req).and_then(|res| {
+        check_api_version(&res);
+
+        let status = res.status();
+        res.into_body().concat2().and_then(move |body| Ok((status, body)))
+
+    }).from_err::<kraken_utils::FetchError>()
+    .and_then(|(status, body)| {
+
+        // Servers from before streaming don't know the endpoint at all, but one
+        // that does answers a 404 for a missing job with a JSON error
+        let missing_route = status == StatusCode::NOT_FOUND || status == StatusCode::METHOD_NOT_ALLOWED;
+
+        if missing_route && serde_json::from_slice::<protocol::ErrorResponse>(&body).is_err() {
+            Ok(None)
+        } else if !status.is_success() {
+            let error_json: protocol::ErrorResponse = serde_json::from_slice(&body)?;
+            Err(kraken_utils::FetchError::KrakenServerError(error_json))
+        } else {
+            let json_response: protocol::StreamAck = serde_json::from_slice(&body)?;
+            Ok(Some(json_response.acked))
+        }
+    }).from_err()
+
+}
+
+fn stream_logs_async(token: &str, job_name: &str, lines: mpsc::UnboundedReceiver<String>) -> impl Future<Item = u64, Error = kraken_utils::FetchError> {
+
+    let url: hyper::Uri = format!("{}/logs/stream/{}", *BASE_URL, protocol::encode_path_segment(job_name)).parse().unwrap();
+    let https_connector = HttpsConnector::new(4).unwrap();
+    let client = Client::builder().build(https_connector);
+    let method = hyper::Method::POST;
+    let mut headers = HeaderMap::new();
+
+    // No content length, so hyper sends the body chunked as lines arrive
+    let body = lines.map_err(|_| io::Error::new(io::ErrorKind::Other, "log channel closed"));
+    let mut req = Request::new(Body::wrap_stream(body));
+
+    headers.insert("x-access-token", HeaderValue::from_str(&token).unwrap());
+    headers.insert(hyper::header::CONTENT_TYPE, HeaderValue::from_static("application/x-ndjson"));
+    headers.insert(protocol::API_VERSION_HEADER, HeaderValue::from_static(protocol::API_VERSION));
+
+    *req.method_mut() = method;
+    *req.uri_mut() = url;
+    *req.headers_mut() = headers;
+
+    client.request(req).and_then(|res| {
+        check_api_version(&res);
+
+        let status = res.status();
+        res.into_body().concat2().and_then(move |body| Ok((status, body)))
+
+    }).from_err::<kraken_utils::FetchError>()
+    .and_then(|(status, body)| {
+
+        if !status.is_success() {
+            let error_json: protocol::ErrorResponse = serde_json::from_slice(&body)?;
+            Err(kraken_utils::FetchError::KrakenServerError(error_json))
+        } else {
+            let json_response: protocol::StreamAck = serde_json::from_slice(&body)?;
+            Ok(json_response.acked)
+        }
+    }).from_err()
+
+}
+
+fn set_progress_async(token: &str, job_name: &str, progress: &protocol::JobProgress) -> impl Future<Item = (), Error = kraken_utils::FetchError> {
+
+    let url: hyper::Uri = format!("{}/jobs/{}/progress", *BASE_URL, protocol::encode_path_segment(job_name)).parse().unwrap();
+    let https_connector = HttpsConnector::new(4).unwrap();
+    let client = Client::builder().build(https_connector);
+    let method = hyper::Method::POST;
+    let mut headers = HeaderMap::new();
+
+    let mut req = Request::new(Body::from(serde_json::to_string(progress).unwrap()));
+
+    headers.insert("x-access-token", HeaderValue::from_str(&token).unwrap());
+    headers.insert(hyper::header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert(protocol::API_VERSION_HEADER, HeaderValue::from_static(protocol::API_VERSION));
+
+    *req.method_mut() = method;
+    *req.uri_mut() = url;
+    *req.headers_mut() = headers;
+
+    client.request(req).and_then(|res| {
+        check_api_version(&res);
+
+        let status = res.status();
+        res.into_body().concat2().and_then(move |body| Ok((status, body)))
+
+    }).from_err::<kraken_utils::FetchError>()
+    .and_then(|(status, body)| {
+
+        if !status.is_success() {
+            let error_json: protocol::ErrorResponse = serde_json::from_slice(&body)?;
+            Err(kraken_utils::FetchError::KrakenServerError(error_json))
+        } else {
+            Ok(())
+        }
+    }).from_err()
+
+}
+
+fn fetch_progress(token: &str, job_name: &str) -> impl Future<Item = Option<protocol::JobProgress>, Error = kraken_utils::FetchError> {
+
+    let url: hyper::Uri = format!("{}/jobs/{}/progress", *BASE_URL, protocol::encode_path_segment(job_name)).parse().unwrap();
+    let https_connector = HttpsConnector::new(4).unwrap();
+    let client = Client::builder().build(https_connector);
+    let method = hyper::Method::GET;
+    let mut headers = HeaderMap::new();
+
+    let mut req = Request::new(Body::empty());
+
+    headers.insert("x-access-token", HeaderValue::from_str(&token).unwrap());
+    headers.insert(protocol::API_VERSION_HEADER, HeaderValue::from_static(protocol::API_VERSION));
+
+    *req.method_mut() = method;
+    *req.uri_mut() = url;
+    *req.headers_mut() = headers;
+
+    client.request(req).and_then(|res| {
+        check_api_version(&res);
+
+        let status = res.status();
+        res.into_body().concat2().and_then(move |body| Ok((status, body)))
+
+    }).from_err::<kraken_utils::FetchError>()
+    .and_then(|(status, body)| {
+
+        if !status.is_success() {
+            let error_json: protocol::ErrorResponse = serde_json::from_slice(&body)?;
+            Err(kraken_utils::FetchError::KrakenServerError(error_json))
+        } else {
+            let json_response: Option<protocol::JobProgress> = serde_json::from_slice(&body)?;
+            Ok(json_response)
+        }
+    }).from_err()
+
+}
+
+fn send_metrics_async(token: &str, job_name: &str, metrics: &[protocol::Metric]) -> impl Future<Item = (), Error = kraken_utils::FetchError> {
+
+    let url: hyper::Uri = format!("{}/jobs/{}/metrics", *BASE_URL, protocol::encode_path_segment(job_name)).parse().unwrap();
+    let https_connector = HttpsConnector::new(4).unwrap();
+    let client = Client::builder().build(https_connector);
+    let method = hyper::Method::POST;
+    let mut headers = HeaderMap::new();
+
+    let mut req = Request::new(Body::from(serde_json::to_string(metrics).unwrap()));
+
+    headers.insert("x-access-token", HeaderValue::from_str(&token).unwrap());
+    headers.insert(hyper::header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert(protocol::API_VERSION_HEADER, HeaderValue::from_static(protocol::API_VERSION));
+
+    *req.method_mut() = method;
+    *req.uri_mut() = url;
+    *req.headers_mut() = headers;
+
+    client.request(req).and_then(|res| {
+        check_api_version(&res);
+
+        let status = res.status();
+        res.into_body().concat2().and_then(move |body| Ok((status, body)))
+
+    }).from_err::<kraken_utils::FetchError>()
+    .and_then(|(status, body)| {
+
+        if !status.is_success() {
+            let error_json: protocol::ErrorResponse = serde_json::from_slice(&body)?;
+            Err(kraken_utils::FetchError::KrakenServerError(error_json))
+        } else {
+            Ok(())
+        }
+    }).from_err()
+
+}
+
+fn fetch_metrics(token: &str, job_name: &str) -> impl Future<Item = Vec<protocol::Metric>, Error = kraken_utils::FetchError> {
+
+    let url: hyper::Uri = format!("{}/jobs/{}/metrics", *BASE_URL, protocol::encode_path_segment(job_name)).parse().unwrap();
+    let https_connector = HttpsConnector::new(4).unwrap();
+    let client = Client::builder().build(https_connector);
+    let method = hyper::Method::GET;
+    let mut headers = HeaderMap::new();
+
+    let mut req = Request::new(Body::empty());
+
+    headers.insert("x-access-token", HeaderValue::from_str(&token).unwrap());
+    headers.insert(protocol::API_VERSION_HEADER, HeaderValue::from_static(protocol::API_VERSION));
+
+    *req.method_mut() = method;
+    *req.uri_mut() = url;
+    *req.headers_mut() = headers;
+
+    client.request(req).and_then(|res| {
+        check_api_version(&res);
+
+        let status = res.status();
+        res.into_body().concat2().and_then(move |body| Ok((status, body)))
+
+    }).from_err::<kraken_utils::FetchError>()
+    .and_then(|(status, body)| {
+
+        if !status.is_success() {
+            let error_json: protocol::ErrorResponse = serde_json::from_slice(&body)?;
+            Err(kraken_utils::FetchError::KrakenServerError(error_json))
+        } else {
+            let json_response: Vec<protocol::Metric> = serde_json::from_slice(&body)?;
+            Ok(json_response)
+        }
+    }).from_err()
+
+}
+
+fn fetch_artifacts(token: &str, job_name: &str) -> impl Future<Item = Vec<protocol::Artifact>, Error = kraken_utils::FetchError> {
+
+    let url: hyper::Uri = format!("{}/jobs/{}/artifacts", *BASE_URL, protocol::encode_path_segment(job_name)).parse().unwrap();
+    let https_connector = HttpsConnector::new(4).unwrap();
+    let client = Client::builder().build(https_connector);
+    let method = hyper::Method::GET;
+    let mut headers = HeaderMap::new();
+
+    let mut req = Request::new(Body::empty());
+
+    headers.insert("x-access-token", HeaderValue::from_str(&token).unwrap());
+    headers.insert(protocol::API_VERSION_HEADER, HeaderValue::from_static(protocol::API_VERSION));
+
+    *req.method_mut() = method;
+    *req.uri_mut() = url;
+    *req.headers_mut() = headers;
+
+    client.request(req).and_then(|res| {
+        check_api_version(&res);
+
+        let status = res.status();
+        res.into_body().concat2().and_then(move |body| Ok((status, body)))
+
+    }).from_err::<kraken_utils::FetchError>()
+    .and_then(|(status, body)| {
+
+        if !status.is_success() {
+            let error_json: protocol::ErrorResponse = serde_json::from_slice(&body)?;
+            Err(kraken_utils::FetchError::KrakenServerError(error_json))
+        } else {
+            let json_response: Vec<protocol::Artifact> = serde_json::from_slice(&body)?;
+            Ok(json_response)
+        }
+    }).from_err()
+
+}
+
+fn upload_artifact_async(token: &str, job_name: &str, artifact_name: &str, contents: Vec<u8>) -> impl Future<Item = (), Error = kraken_utils::FetchError> {
+
+    let url: hyper::Uri = format!("{}/jobs/{}/artifacts/{}", *BASE_URL, protocol::encode_path_segment(job_name), protocol::encode_path_segment(artifact_name)).parse().unwrap();
+    let https_connector = HttpsConnector::new(4).unwrap();
+    let client = Client::builder().build(https_connector);
+    let method = hyper::Method::POST;
+    let mut headers = HeaderMap::new();
+
+    let mut req = Request::new(Body::from(contents));
+
+    headers.insert("x-access-token", HeaderValue::from_str(&token).unwrap());
+    headers.insert(hyper::header::CONTENT_TYPE, HeaderValue::from_static("application/octet-stream"));
+    headers.insert(protocol::API_VERSION_HEADER, HeaderValue::from_static(protocol::API_VERSION));
+
+    *req.method_mut() = method;
+    *req.uri_mut() = url;
+    *req.headers_mut() = headers;
+
+    client.request(req).and_then(|res| {
+        check_api_version(&res);
+
+        let status = res.status();
+        res.into_body().concat2().and_then(move |body| Ok((status, body)))
+
+    }).from_err::<kraken_utils::FetchError>()
+    .and_then(|(status, body)| {
+
+        if !status.is_success() {
+            let error_json: protocol::ErrorResponse = serde_json::from_slice(&body)?;
+            Err(kraken_utils::FetchError::KrakenServerError(error_json))
+        } else {
+            Ok(())
+        }
+    }).from_err()
+
+}
+
+fn set_resources_async(token: &str, job_name: &str, resources: &protocol::ResourceSummary) -> impl Future<Item = (), Error = kraken_utils::FetchError> {
+
+    let url: hyper::Uri = format!("{}/jobs/{}/resources", *BASE_URL, protocol::encode_path_segment(job_name)).parse().unwrap();
+    let https_connector = HttpsConnector::new(4).unwrap();
+    let client = Client::builder().build(https_connector);
+    let method = hyper::Method::POST;
+    let mut headers = HeaderMap::new();
+
+    let mut req = Request::new(Body::from(serde_json::to_string(resources).unwrap()));
+
+    headers.insert("x-access-token", HeaderValue::from_str(&token).unwrap());
+    headers.insert(hyper::header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert(protocol::API_VERSION_HEADER, HeaderValue::from_static(protocol::API_VERSION));
+
+    *req.method_mut() = method;
+    *req.uri_mut() = url;
+    *req.headers_mut() = headers;
+
+    client.request(req).and_then(|res| {
+        check_api_version(&res);
+
+        let status = res.status();
+        res.into_body().concat2().and_then(move |body| Ok((status, body)))
+
+    }).from_err::<kraken_utils::FetchError>()
+    .and_then(|(status, body)| {
+
+        if !status.is_success() {
+            let error_json: protocol::ErrorResponse = serde_json::from_slice(&body)?;
+            Err(kraken_utils::FetchError::KrakenServerError(error_json))
+        } else {
+            Ok(())
+        }
+    }).from_err()
+
+}
+
+fn send_alert_async(token: &str, job_name: &str, alert: &protocol::Alert) -> impl Future<Item = (), Error = kraken_utils::FetchError> {
+
+    let url: hyper::Uri = format!("{}/jobs/{}/alerts", *BASE_URL, protocol::encode_path_segment(job_name)).parse().unwrap();
+    let https_connector = HttpsConnector::new(4).unwrap();
+    let client = Client::builder().build(https_connector);
+    let method = hyper::Method::POST;
+    let mut headers = HeaderMap::new();
+
+    let mut req = Request::new(Body::from(serde_json::to_string(alert).unwrap()));
+
+    headers.insert("x-access-token", HeaderValue::from_str(&token).unwrap());
+    headers.insert(hyper::header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert(protocol::API_VERSION_HEADER, HeaderValue::from_static(protocol::API_VERSION));
+
+    *req.method_mut() = method;
+    *req.uri_mut() = url;
+    *req.headers_mut() = headers;
+
+    client.request(req).and_then(|res| {
+        check_api_version(&res);
+
+        let status = res.status();
+        res.into_body().concat2().and_then(move |body| Ok((status, body)))
+
+    }).from_err::<kraken_utils::FetchError>()
+    .and_then(|(status, body)| {
+
+        if !status.is_success() {
+            let error_json: protocol::ErrorResponse = serde_json::from_slice(&body)?;
+            Err(kraken_utils::FetchError::KrakenServerError(error_json))
+        } else {
+            Ok(())
+        }
+    }).from_err()
+
+}
+
+fn finish_job_async(token: &str, job_name: &str, exit: &protocol::JobExit) -> impl Future<Item = (), Error = kraken_utils::FetchError> {
+
+    let url: hyper::Uri = format!("{}/jobs/{}/exit", *BASE_URL, protocol::encode_path_segment(job_name)).parse().unwrap();
+    let https_connector = HttpsConnector::new(4).unwrap();
+    let client = Client::builder().build(https_connector);
+    let method = hyper::Method::POST;
+    let mut headers = HeaderMap::new();
+
+    let mut req = Request::new(Body::from(serde_json::to_string(exit).unwrap()));
+
+    headers.insert("x-access-token", HeaderValue::from_str(&token).unwrap());
+    headers.insert(hyper::header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert(protocol::API_VERSION_HEADER, HeaderValue::from_static(protocol::API_VERSION));
+
+    *req.method_mut() = method;
+    *req.uri_mut() = url;
+    *req.headers_mut() = headers;
+
+    client.request(req).and_then(|res| {
+        check_api_version(&res);
+
+        let status = res.status();
+        res.into_body().concat2().and_then(move |body| Ok((status, body)))
+
+    }).from_err::<kraken_utils::FetchError>()
+    .and_then(|(status, body)| {
+
+        if !status.is_success() {
+            let error_json: protocol::ErrorResponse = serde_json::from_slice(&body)?;
+            Err(kraken_utils::FetchError::KrakenServerError(error_json))
+        } else {
+            Ok(())
+        }
+    }).from_err()
+
+}
+
+fn fetch_resources(token: &str, job_name: &str) -> impl Future<Item = Option<protocol::ResourceSummary>, Error = kraken_utils::FetchError> {
+
+    let url: hyper::Uri = format!("{}/jobs/{}/resources", *BASE_URL, protocol::encode_path_segment(job_name)).parse().unwrap();
+    let https_connector = HttpsConnector::new(4).unwrap();
+    let client = Client::builder().build(https_connector);
+    let method = hyper::Method::GET;
+    let mut headers = HeaderMap::new();
+
+    let mut req = Request::new(Body::empty());
+
+    headers.insert("x-access-token", HeaderValue::from_str(&token).unwrap());
+    headers.insert(protocol::API_VERSION_HEADER, HeaderValue::from_static(protocol::API_VERSION));
+
+    *req.method_mut() = method;
+    *req.uri_mut() = url;
+    *req.headers_mut() = headers;
+
+    client.request(req).and_then(|res| {
+        check_api_version(&res);
+
+        let status = res.status();
+        res.into_body().concat2().and_then(move |body| Ok((status, body)))
+
+    }).from_err::<kraken_utils::FetchError>()
+    .and_then(|(status, body)| {
+
+        if !status.is_success() {
+            let error_json: protocol::ErrorResponse = serde_json::from_slice(&body)?;
+            Err(kraken_utils::FetchError::KrakenServerError(error_json))
+        } else {
+            let json_response: Option<protocol::ResourceSummary> = serde_json::from_slice(&body)?;
+            Ok(json_response)
+        }
+    }).from_err()
+
+}
+
+fn fetch_spec(token: &str, job_name: &str) -> impl Future<Item = Option<protocol::JobSpec>, Error = kraken_utils::FetchError> {
+
+    let url: hyper::Uri = format!("{}/jobs/{}/spec", *BASE_URL, protocol::encode_path_segment(job_name)).parse().unwrap();
+    let https_connector = HttpsConnector::new(4).unwrap();
+    let client = Client::builder().build(https_connector);
+    let method = hyper::Method::GET;
+    let mut headers = HeaderMap::new();
+
+    let mut req = Request::new(Body::empty());
+
+    headers.insert("x-access-token", HeaderValue::from_str(&token).unwrap());
+    headers.insert(protocol::API_VERSION_HEADER, HeaderValue::from_static(protocol::API_VERSION));
+
+    *req.method_mut() = method;
+    *req.uri_mut() = url;
+    *req.headers_mut() = headers;
+
+    client.request(req).and_then(|res| {
+        check_api_version(&res);
+
+        let status = res.status();
+        res.into_body().concat2().and_then(move |body| Ok((status, body)))
+
+    }).from_err::<kraken_utils::FetchError>()
+    .and_then(|(status, body)| {
+
+        if !status.is_success() {
+            let error_json: protocol::ErrorResponse = serde_json::from_slice(&body)?;
+            Err(kraken_utils::FetchError::KrakenServerError(error_json))
+        } else {
+            let json_response: Option<protocol::JobSpec> = serde_json::from_slice(&body)?;
+            Ok(json_response)
+        }
+    }).from_err()
+
+}
+
+fn post_webhook_async(url: hyper::Uri, body: String) -> impl Future<Item = (), Error = kraken_utils::FetchError> {
+
+    let https_connector = HttpsConnector::new(4).unwrap();
+    let client = Client::builder().build(https_connector);
+    let method = hyper::Method::POST;
+    let mut headers = HeaderMap::new();
+
+    let mut req = Request::new(Body::from(body));
+
+    headers.insert(hyper::header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+    *req.method_mut() = method;
+    *req.uri_mut() = url;
+    *req.headers_mut() = headers;
+
+    // Not a kraken server, so its errors aren't ErrorResponse JSON
+    client.request(req).from_err::<kraken_utils::FetchError>()
+    .and_then(|res| {
+
+        if !res.status().is_success() {
+            Err(kraken_utils::FetchError::Other(format!("webhook answered {}", res.status())))
+        } else {
+            Ok(())
+        }
+    })
+
+}
@@ -0,0 +1,125 @@
+// A single captured line of job output
+// Every line is tagged with a sequence number, the time it was captured and the stream it
+// came from. Locally they're stored one per line as `<seq>\t<timestamp>\t<stream>\t<text>`.
+
+use chrono::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+    // Lines kraken writes itself, like the job header and `exit`
+    Kraken,
+}
+
+impl Stream {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+            Stream::Kraken => "kraken",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Stream> {
+        match name {
+            "stdout" => Some(Stream::Stdout),
+            "stderr" => Some(Stream::Stderr),
+            "kraken" => Some(Stream::Kraken),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LogLine {
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    pub stream: Stream,
+    pub text: String,
+}
+
+impl LogLine {
+    pub fn to_record(&self) -> String {
+        format!("{}\t{}\t{}\t{}", self.seq, format_timestamp(&self.timestamp), self.stream.as_str(), self.text)
+    }
+
+    /// Parses a line written by `to_record`. Output from older versions of kraken won't parse.
+    pub fn parse_record(record: &str) -> Option<LogLine> {
+        let mut fields = record.splitn(4, '\t');
+
+        let seq = fields.next()?.parse().ok()?;
+        let timestamp = parse_timestamp(fields.next()?)?;
+        let stream = Stream::parse(fields.next()?)?;
+        let text = fields.next()?.to_string();
+
+        Some(LogLine {
+            seq: seq,
+            timestamp: timestamp,
+            stream: stream,
+            text: text,
+        })
+    }
+}
+
+/// Hands out monotonically increasing sequence numbers, starting at 1
+pub struct Sequence {
+    last: u64,
+}
+
+impl Sequence {
+    pub fn new() -> Sequence {
+        Sequence { last: 0 }
+    }
+
+    pub fn next(&mut self, stream: Stream, text: &str) -> LogLine {
+        self.next_at(stream, text, Utc::now())
+    }
+
+    /// Numbers a line captured at `timestamp`, which is when its reader split it off
+    pub fn next_at(&mut self, stream: Stream, text: &str, timestamp: DateTime<Utc>) -> LogLine {
+        self.last += 1;
+
+        LogLine {
+            seq: self.last,
+            timestamp: timestamp,
+            stream: stream,
+            text: text.to_string(),
+        }
+    }
+}
+
+pub fn format_timestamp(timestamp: &DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+pub fn parse_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(timestamp).ok().map(|t| t.with_timezone(&Utc))
+}
+
+/// How `show job` filters and prints lines
+#[derive(Debug, Clone)]
+pub struct LogFilter {
+    pub timestamps: bool,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl LogFilter {
+    /// Lines without a timestamp only pass when no time range was asked for
+    pub fn matches(&self, timestamp: Option<DateTime<Utc>>) -> bool {
+        match timestamp {
+            Some(t) => self.since.map_or(true, |since| t >= since) && self.until.map_or(true, |until| t <= until),
+            None => self.since.is_none() && self.until.is_none(),
+        }
+    }
+
+    pub fn format(&self, timestamp: Option<DateTime<Utc>>, stream: Option<&str>, text: &str) -> String {
+        if !self.timestamps {
+            return text.to_string();
+        }
+
+        let timestamp = timestamp.map(|t| format_timestamp(&t)).unwrap_or("-".to_string());
+        format!("{} [{}] {}", timestamp, stream.unwrap_or("-"), text)
+    }
+}
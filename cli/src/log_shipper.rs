@@ -0,0 +1,414 @@
+// Ships a job's log lines, progress and metrics to the server from background threads
+// The `stream` transport keeps one chunked `POST /logs/stream/<job>` open at a time. After a
+// dropped connection it asks the server for the last seq it stored and resends everything after
+// it. The `http` transport posts each line to `/logs/new`, and is what `stream` falls back to when
+// the server can't stream or streaming keeps failing. Progress and metrics go on their own thread
+// so an open stream never holds them up. Only the latest progress report is sent, while metrics
+// are sent in batches and retried like lines.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use futures::sync::mpsc;
+
+use kraken_utils::FetchError;
+use krephis;
+use log_line::LogLine;
+use protocol::{JobProgress, Metric};
+
+// Lines per streaming request. Ending a request is how the server acknowledges lines, so this
+// also bounds how many unacknowledged lines are held in memory.
+const STREAM_BATCH_LINES: usize = 1000;
+// Consecutive streaming failures before falling back to plain HTTP
+const MAX_STREAM_FAILURES: u32 = 3;
+const MAX_BACKOFF_SECS: u64 = 30;
+// How long a finished job keeps retrying lines the server hasn't stored yet
+const FINISH_TIMEOUT_SECS: u64 = 60;
+// Oldest lines are dropped past this while the server is unreachable
+const MAX_PENDING_LINES: usize = 100_000;
+// Progress reports arriving faster than this replace each other, and metrics are batched
+const REPORT_INTERVAL_SECS: u64 = 1;
+const MAX_PENDING_METRICS: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transport {
+    Stream,
+    Http,
+}
+
+impl Transport {
+    pub fn parse(name: &str) -> Option<Transport> {
+        match name {
+            "stream" => Some(Transport::Stream),
+            "http" => Some(Transport::Http),
+            _ => None,
+        }
+    }
+}
+
+struct Shared {
+    // Lines the server hasn't acknowledged yet, oldest first
+    pending: VecDeque<LogLine>,
+    // Body of the open streaming request, if there is one
+    stream: Option<mpsc::UnboundedSender<String>>,
+    // Lines written to the open streaming request
+    streamed: usize,
+    dropped: usize,
+    // Latest progress report not sent yet
+    progress: Option<JobProgress>,
+    metrics: VecDeque<Metric>,
+    dropped_metrics: usize,
+    finished_at: Option<Instant>,
+    // How long after finishing the workers keep trying
+    finish_timeout: Duration,
+    // Workers that haven't returned yet
+    running: usize,
+}
+
+type State = Arc<(Mutex<Shared>, Condvar)>;
+
+pub struct LogShipper {
+    job_name: String,
+    state: State,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl LogShipper {
+    pub fn start(job_name: &str, transport: Transport) -> LogShipper {
+        let state: State = Arc::new((Mutex::new(Shared {
+            pending: VecDeque::new(),
+            stream: None,
+            streamed: 0,
+            dropped: 0,
+            progress: None,
+            metrics: VecDeque::new(),
+            dropped_metrics: 0,
+            finished_at: None,
+            finish_timeout: Duration::from_secs(FINISH_TIMEOUT_SECS),
+            running: 2,
+        }), Condvar::new()));
+
+        let mut workers = Vec::new();
+
+        let worker_job_name = job_name.to_string();
+        let worker_state = state.clone();
+        workers.push(thread::spawn(move || {
+            run(&worker_job_name, transport, &worker_state);
+            worker_done(&worker_state);
+        }));
+
+        let worker_job_name = job_name.to_string();
+        let worker_state = state.clone();
+        workers.push(thread::spawn(move || {
+            run_reports(&worker_job_name, &worker_state);
+            worker_done(&worker_state);
+        }));
+
+        LogShipper { job_name: job_name.to_string(), state: state, workers: workers }
+    }
+
+    /// Queues a line. Never blocks on the network.
+    pub fn send(&self, line: &LogLine) {
+        let (ref lock, ref cvar) = *self.state;
+        let mut shared = lock.lock().unwrap();
+
+        if shared.pending.len() >= MAX_PENDING_LINES {
+            shared.pending.pop_front();
+            shared.dropped += 1;
+        }
+        shared.pending.push_back(line.clone());
+
+        let mut end_stream = false;
+        if let Some(ref stream) = shared.stream {
+            end_stream = stream.unbounded_send(payload(&self.job_name, line)).is_err();
+        }
+
+        if shared.stream.is_some() {
+            shared.streamed += 1;
+            if end_stream || shared.streamed >= STREAM_BATCH_LINES {
+                shared.stream = None;
+            }
+        }
+
+        cvar.notify_all();
+    }
+
+    /// Queues a progress report, replacing any that wasn't sent yet
+    pub fn send_progress(&self, progress: &JobProgress) {
+        let (ref lock, ref cvar) = *self.state;
+        lock.lock().unwrap().progress = Some(progress.clone());
+        cvar.notify_all();
+    }
+
+    /// Queues metrics to be sent with the next batch
+    pub fn send_metrics(&self, metrics: &[Metric]) {
+        let (ref lock, ref cvar) = *self.state;
+        let mut shared = lock.lock().unwrap();
+
+        for metric in metrics {
+            if shared.metrics.len() >= MAX_PENDING_METRICS {
+                shared.metrics.pop_front();
+                shared.dropped_metrics += 1;
+            }
+            shared.metrics.push_back(metric.clone());
+        }
+
+        cvar.notify_all();
+    }
+
+    /// Ships whatever is left and waits for the workers, giving up after `FINISH_TIMEOUT_SECS`
+    pub fn finish(self) {
+        self.finish_within(Duration::from_secs(FINISH_TIMEOUT_SECS));
+    }
+
+    /// Like `finish`, giving up after `timeout`. A worker still waiting on the server by then is
+    /// left behind instead of joined.
+    pub fn finish_within(mut self, timeout: Duration) {
+        let state = self.state.clone();
+        let (ref lock, ref cvar) = *state;
+        let deadline = Instant::now() + timeout;
+
+        let mut shared = lock.lock().unwrap();
+        shared.finished_at = Some(Instant::now());
+        shared.finish_timeout = timeout;
+        shared.stream = None;
+        cvar.notify_all();
+
+        while shared.running > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                return;
+            }
+            shared = cvar.wait_timeout(shared, deadline - now).unwrap().0;
+        }
+        drop(shared);
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn worker_done(state: &State) {
+    let (ref lock, ref cvar) = **state;
+    lock.lock().unwrap().running -= 1;
+    cvar.notify_all();
+}
+
+fn payload(job_name: &str, line: &LogLine) -> String {
+    format!("{}\n", serde_json::to_string(&krephis::log_request(job_name, line)).unwrap())
+}
+
+fn run(job_name: &str, transport: Transport, state: &State) {
+    let mut transport = transport;
+    let mut failures = 0;
+    let mut stream_failures = 0;
+
+    loop {
+        {
+            let (ref lock, ref cvar) = **state;
+            let mut shared = lock.lock().unwrap();
+
+            while shared.pending.is_empty() && shared.finished_at.is_none() {
+                shared = cvar.wait(shared).unwrap();
+            }
+
+            if shared.dropped > 0 {
+                eprintln!("Dropped {} log lines the server couldn't take in time", shared.dropped);
+                shared.dropped = 0;
+            }
+
+            if shared.pending.is_empty() {
+                return;
+            }
+
+            if shared.finished_at.map_or(false, |t| t.elapsed() > shared.finish_timeout) {
+                eprintln!("Giving up on {} log lines the server never stored", shared.pending.len());
+                return;
+            }
+        }
+
+        let result = match transport {
+            Transport::Stream => stream_pending(job_name, state),
+            Transport::Http => post_pending(job_name, state),
+        };
+
+        match result {
+            Ok(true) => {
+                failures = 0;
+                stream_failures = 0;
+            },
+            Ok(false) => {
+                eprintln!("The server can't stream logs, sending them over HTTP instead");
+                transport = Transport::Http;
+            },
+            Err(e) => {
+                failures += 1;
+                eprintln!("Couldn't ship logs ({}), retrying", e);
+
+                // The server answered, so it can stream and HTTP wouldn't fare any better
+                match e {
+                    FetchError::KrakenServerError(_) => (),
+                    _ => stream_failures += 1,
+                }
+
+                if transport == Transport::Stream && stream_failures >= MAX_STREAM_FAILURES {
+                    eprintln!("Streaming keeps failing, sending logs over HTTP instead");
+                    transport = Transport::Http;
+                }
+
+                wait_before_retry(state, failures);
+            },
+        }
+    }
+}
+
+fn run_reports(job_name: &str, state: &State) {
+    loop {
+        let (progress, metrics) = {
+            let (ref lock, ref cvar) = **state;
+            let mut shared = lock.lock().unwrap();
+
+            while shared.progress.is_none() && shared.metrics.is_empty() && shared.finished_at.is_none() {
+                shared = cvar.wait(shared).unwrap();
+            }
+
+            if shared.dropped_metrics > 0 {
+                eprintln!("Dropped {} metrics the server couldn't take in time", shared.dropped_metrics);
+                shared.dropped_metrics = 0;
+            }
+
+            if shared.finished_at.map_or(false, |t| t.elapsed() > shared.finish_timeout) {
+                if !shared.metrics.is_empty() {
+                    eprintln!("Giving up on {} metrics the server never stored", shared.metrics.len());
+                }
+                return;
+            }
+
+            if shared.progress.is_none() && shared.metrics.is_empty() {
+                return;
+            }
+
+            (shared.progress.take(), shared.metrics.drain(..).collect::<Vec<_>>())
+        };
+
+        // A newer report replaces this one, so failures aren't retried
+        if let Some(progress) = progress {
+            if let Err(e) = krephis::set_progress(job_name, &progress) {
+                eprintln!("Couldn't send progress ({})", e);
+            }
+        }
+
+        if !metrics.is_empty() {
+            match krephis::send_metrics(job_name, &metrics) {
+                Ok(()) => {},
+                Err(FetchError::KrakenServerError(e)) => eprintln!("Server error: {}", e.message),
+                Err(FetchError::Json(e)) => eprintln!("json parsing error: {}", e),
+                Err(e) => {
+                    eprintln!("Couldn't ship metrics ({}), retrying", e);
+
+                    let (ref lock, _) = **state;
+                    let mut shared = lock.lock().unwrap();
+                    for metric in metrics.into_iter().rev() {
+                        shared.metrics.push_front(metric);
+                    }
+                },
+            }
+        }
+
+        thread::sleep(Duration::from_secs(REPORT_INTERVAL_SECS));
+    }
+}
+
+// One streaming request: resend everything after the server's last stored seq, then keep
+// writing new lines until the batch fills up or the job finishes. Ok(false) means the server
+// can't stream at all.
+fn stream_pending(job_name: &str, state: &State) -> Result<bool, FetchError> {
+    let acked = match krephis::stream_ack(job_name)? {
+        Some(acked) => acked,
+        None => return Ok(false),
+    };
+
+    let (receiver, sent_up_to) = {
+        let (sender, receiver) = mpsc::unbounded();
+        let (ref lock, _) = **state;
+        let mut shared = lock.lock().unwrap();
+        drop_acknowledged(&mut shared.pending, acked);
+
+        for line in &shared.pending {
+            let _ = sender.unbounded_send(payload(job_name, line));
+        }
+
+        shared.streamed = shared.pending.len();
+        let sent_up_to = shared.pending.back().map(|line| line.seq);
+
+        // Otherwise the sender is dropped here, which ends the request after what's pending
+        if shared.finished_at.is_none() && shared.streamed < STREAM_BATCH_LINES {
+            shared.stream = Some(sender);
+        }
+
+        (receiver, sent_up_to)
+    };
+
+    let result = krephis::stream_logs(job_name, receiver);
+
+    let (ref lock, _) = **state;
+    let mut shared = lock.lock().unwrap();
+    shared.stream = None;
+
+    let acked = result?;
+    drop_acknowledged(&mut shared.pending, acked);
+
+    // Everything written before the request started must be stored by the time it ends
+    match sent_up_to {
+        Some(seq) if acked < seq => Err(FetchError::Other(format!("the server stored up to line {} of {}", acked, seq))),
+        _ => Ok(true),
+    }
+}
+
+// Posts lines one at a time until none are left. Lines the server rejects are dropped rather
+// than retried forever.
+fn post_pending(job_name: &str, state: &State) -> Result<bool, FetchError> {
+    loop {
+        let line = {
+            let (ref lock, _) = **state;
+            match lock.lock().unwrap().pending.front() {
+                Some(line) => line.clone(),
+                None => return Ok(true),
+            }
+        };
+
+        match krephis::send_log(job_name, &line) {
+            Ok(()) => {},
+            Err(FetchError::KrakenServerError(e)) => eprintln!("Server error: {}", e.message),
+            Err(FetchError::Json(e)) => eprintln!("json parsing error: {}", e),
+            Err(e) => return Err(e),
+        }
+
+        let (ref lock, _) = **state;
+        lock.lock().unwrap().pending.pop_front();
+    }
+}
+
+fn drop_acknowledged(pending: &mut VecDeque<LogLine>, acked: u64) {
+    while pending.front().map_or(false, |line| line.seq <= acked) {
+        pending.pop_front();
+    }
+}
+
+// Backs off exponentially, but stops waiting once the job finishes so it isn't kept waiting
+fn wait_before_retry(state: &State, failures: u32) {
+    let backoff = Duration::from_secs((1u64 << failures.min(5)).min(MAX_BACKOFF_SECS));
+    let start = Instant::now();
+
+    loop {
+        thread::sleep(Duration::from_millis(250));
+
+        let (ref lock, _) = **state;
+        if start.elapsed() >= backoff || lock.lock().unwrap().finished_at.is_some() {
+            return;
+        }
+    }
+}
@@ -10,170 +10,1319 @@ extern crate dirs;
 extern crate futures;
 extern crate serde;
 extern crate hyper_tls;
+extern crate flate2;
+extern crate zstd;
+extern crate libc;
+extern crate indicatif;
+extern crate termion;
 
 #[macro_use] extern crate hyper;
 #[macro_use] extern crate serde_derive;
 #[macro_use] extern crate serde_json;
 #[macro_use] extern crate prettytable;
+#[macro_use] extern crate lazy_static;
 
-use std::fs::File;
-use std::io::{Write, BufRead, BufReader};
-use std::fs::OpenOptions;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::process::{Child, Command, Stdio};
 
-use clap::{Arg, App, ArgMatches, SubCommand};
+use clap::{Arg, App, ArgGroup, ArgMatches, SubCommand};
 use chrono::prelude::*;
 use daemonize::Daemonize;
 use haikunator::{Haikunator};
 use hostname::get_hostname;
-use subprocess::Exec;
 use std::sync::atomic::{AtomicBool, Ordering};
 
-use std::time;
+use std::time::{Duration, Instant};
 
-use std::io::prelude::*;
+use std::sync::mpsc;
 use std::thread;
 
+use prettytable::Table;
+
+pub mod capture;
+pub mod config;
+pub mod dashboard;
+pub mod invocation;
+pub mod job_input;
+pub mod job_output;
+pub mod job_names;
+pub mod job_registry;
+pub mod job_spec;
+pub mod job_terminal;
 pub mod kraken_utils;
 pub mod krephis;
+pub mod log_line;
+pub mod log_shipper;
+pub mod metrics;
+pub mod notifications;
+pub mod progress;
+pub mod protocol;
+pub mod remote;
+pub mod resources;
+pub mod shutdown;
+pub mod triggers;
+pub mod top;
+
+use capture::{CaptureOptions, Decoding, LineSplitter};
+use job_output::{Compression, JobOutput, RotationPolicy};
+use log_line::{LogFilter, LogLine, Sequence, Stream};
+use log_shipper::LogShipper;
+
+// Lines `tail job` fetches per refresh
+const TAIL_LINES: usize = 1000;
 
 // Atomic variable used by both the command execution and monitoring threads
 static ATOMIC_COMMAND_DONE: AtomicBool = AtomicBool::new(false);
 
 // Run once a new job is created
 fn new(matches: &ArgMatches) {
+    match matches.subcommand() {
+        ("job", Some(command)) => new_job(command),
+        _ => println!("Use `kraken new -h` for help"),
+    }
+}
+
+// Rerun
+fn rerun(matches: &ArgMatches) {
+    match matches.subcommand() {
+        ("job", Some(command)) => relaunch(command.value_of("FROM").unwrap(), command),
+        _ => println!("Use `kraken rerun -h` for help"),
+    }
+}
+
+// Start interrupted jobs again, each as a new job named after it. Each is started by its own
+// `kraken new job --from`, since starting a job's daemon ends the kraken that starts it.
+fn resume(matches: &ArgMatches) {
+    let mut config = config::load();
+    if let Some(dir) = matches.value_of("OUTPUT_DIR") {
+        config.output_dir = dir.to_string();
+    }
+    let dir = config.output_dir.clone();
+
+    let job_names: Vec<String> = match matches.values_of("JOB_NAME") {
+        Some(names) => names.map(|n| n.to_string()).collect(),
+        None => job_registry::list(&dir).unwrap_or_default().into_iter()
+            .filter(|r| r.status == "interrupted" && r.resumed_as.is_none())
+            .map(|r| r.name)
+            .collect(),
+    };
+
+    if job_names.is_empty() {
+        println!("No interrupted jobs to resume");
+        return;
+    }
+
+    let kraken = match env::current_exe() {
+        Ok(kraken) => kraken,
+        Err(e) => {
+            eprintln!("Couldn't find this kraken binary: {}", e);
+            return;
+        }
+    };
+
+    for job_name in &job_names {
+        let record = match job_registry::load(&dir, job_name) {
+            Ok(record) => record,
+            Err(_) => {
+                eprintln!("{} didn't run on this machine", job_name);
+                continue;
+            }
+        };
+
+        if record.status != "interrupted" {
+            eprintln!("{} wasn't interrupted, it's {}", job_name, record.status);
+            continue;
+        }
+
+        if let Some(ref resumed_as) = record.resumed_as {
+            eprintln!("{} was already resumed as {}", job_name, resumed_as);
+            continue;
+        }
+
+        let spec = match record.spec {
+            Some(spec) => spec,
+            None => {
+                eprintln!("{} was started by a kraken that didn't keep its spec, so it can't be resumed", job_name);
+                continue;
+            }
+        };
+
+        let resume_command = match matches.value_of("RESUME_CMD") {
+            Some(command) => Some(command.to_string()),
+            None => match job_spec::resume_command(&spec, new_job_command()) {
+                Ok(command) => command,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    continue;
+                }
+            },
+        };
+
+        let new_name = match job_names::resolve(job_name, Some(&dir), job_names::OnCollision::Suffix) {
+            Ok(name) => name,
+            Err(e) => {
+                eprintln!("{}", e);
+                continue;
+            }
+        };
+
+        // Next to the interrupted job, which is where this looks for the new one
+        let mut args = vec!["new".to_string(), "job".to_string(), "--from".to_string(), job_name.to_string(), "--name".to_string(), new_name.clone()];
+        args.push("--output-dir".to_string());
+        args.push(dir.clone());
+        if let Some(command) = resume_command {
+            args.push(command);
+        }
+
+        // Left alone, the new kraken would feed the job whatever is piped into this one
+        let started = Command::new(&kraken).args(&args).stdin(Stdio::null()).status();
+        if let Err(e) = started {
+            eprintln!("Couldn't resume {}: {}", job_name, e);
+            continue;
+        }
+
+        if job_registry::load(&dir, &new_name).is_err() {
+            eprintln!("Couldn't resume {}", job_name);
+            continue;
+        }
+
+        if let Err(e) = job_registry::set_resumed(&dir, job_name, &new_name) {
+            eprintln!("Couldn't update the record of {}: {}", job_name, e);
+        }
+        println!("Resumed {} as {}", job_name, new_name);
+    }
+}
+
+// Send
+fn send(matches: &ArgMatches) {
+    match matches.subcommand() {
+        ("job", Some(command)) => {
+            let job_name = command.value_of("JOB_NAME").unwrap();
+
+            let mut config = config::load();
+            if let Some(dir) = command.value_of("OUTPUT_DIR") {
+                config.output_dir = dir.to_string();
+            }
+
+            let sent = match command.value_of("TEXT") {
+                Some(text) => job_input::send(&config.output_dir, job_name, &mut format!("{}\n", text).as_bytes()),
+                None if command.is_present("EOF") => Ok(()),
+                None => job_input::send(&config.output_dir, job_name, &mut io::stdin()),
+            };
+
+            let sent = sent.and_then(|_| if command.is_present("EOF") {
+                job_input::close(&config.output_dir, job_name)
+            } else {
+                Ok(())
+            });
+
+            if let Err(e) = sent {
+                eprintln!("{}", e);
+            }
+        },
+        _ => println!("Use `kraken send -h` for help"),
+    }
+}
+
+// Connect this terminal to a job running with --tty
+fn attach(matches: &ArgMatches) {
+    match matches.subcommand() {
+        ("job", Some(command)) => {
+            let job_name = command.value_of("JOB_NAME").unwrap();
+
+            let mut config = config::load();
+            if let Some(dir) = command.value_of("OUTPUT_DIR") {
+                config.output_dir = dir.to_string();
+            }
+
+            if let Some(host) = command.value_of("HOST") {
+                if let Err(e) = remote::attach(host, job_name, &config) {
+                    eprintln!("{}", e);
+                }
+                return;
+            }
+
+            println!("Attached to {}. {} detaches and leaves it running.", job_name, job_terminal::DETACH_KEYS_NAME);
+
+            match job_terminal::attach(&config.output_dir, job_name) {
+                Ok(job_terminal::Ending::Detached) => println!("\nDetached from {}, which is still running", job_name),
+                Ok(job_terminal::Ending::Exited) => println!("\n{} exited", job_name),
+                Err(e) => eprintln!("{}", e),
+            }
+        },
+        _ => println!("Use `kraken attach -h` for help"),
+    }
+}
+
+// Start a job from an earlier job's spec, with the options in `overrides` replacing its own
+fn relaunch(job_name: &str, overrides: &ArgMatches) {
+
+    // The job's record is looked up where the new one is going
+    let mut config = config::load();
+    if let Some(dir) = overrides.value_of("OUTPUT_DIR") {
+        config.output_dir = dir.to_string();
+    }
+
+    let spec = match job_spec::load(job_name, &config) {
+        Ok(spec) => spec,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let args = match job_spec::relaunch_args(&spec, overrides, new_job_command()) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    match new_job_command().get_matches_from_safe(args) {
+        Ok(command) => new_job(&command),
+        Err(e) => eprintln!("{}", e.message),
+    }
+
+    // The new job has its own copy of a saved script
+    let _ = fs::remove_dir_all(job_spec::relaunch_dir());
+}
+
+fn new_job(command: &ArgMatches) {
+
+    if let Some(from) = command.value_of("FROM") {
+        relaunch(from, command);
+        return;
+    }
 
     let hostname = get_hostname().unwrap();
     let haikunator = Haikunator::default();
     let mut unique_job_name = format!("{}-{}", hostname, haikunator.haikunate());
     let mut job_description = "";
-    let job_daemon_output_file_path = format!("/tmp/kraken-job-{}.out", unique_job_name);
-    let job_daemon_error_file_path = format!("/tmp/kraken-job-{}.err", unique_job_name);
-    let job_daemon_pid_file_path = format!("/tmp/kraken-job-{}.pid", unique_job_name);
+    let mut config = config::load();
 
-    println!("Local output/error files below:\n{}\n{}", job_daemon_output_file_path, job_daemon_error_file_path);
+    // Check if the user supplied their own job name
 
-    match matches.subcommand() {
+    if let Some(name) = command.value_of("JOB_NAME") {
+        unique_job_name = name.to_string();
+    }
 
-        ("job", Some(command)) => {
+    if let Some(desc) = command.value_of("DESCRIPTION") {
+        job_description = desc;
+    }
+
+    let invocation = match invocation::Invocation::from_matches(command) {
+        Ok(invocation) => invocation,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    // A generated name that's taken just gets a suffix
+    let on_collision = match command.value_of("JOB_NAME") {
+        Some(_) => job_names::OnCollision::from_matches(command),
+        None => job_names::OnCollision::Suffix,
+    };
+
+    // Everything else happens on the server, which checks the options itself
+    if let Some(host) = command.value_of("HOST") {
+        if command.value_of("JOB_NAME").is_none() {
+            unique_job_name = format!("{}-{}", remote::host_name(host), haikunator.haikunate());
+        }
+
+        // Its local files are the server's to check
+        unique_job_name = match job_names::resolve(&unique_job_name, None, on_collision) {
+            Ok(name) => name,
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        };
+
+        let input = match job_input::read(command) {
+            Ok(input) => input,
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        };
+
+        match remote::launch(host, &unique_job_name, &remote::forwarded_args(command), invocation.script(), input.as_ref().map(|i| i.as_slice()), &config) {
+            Ok(output) => {
+                print!("{}", output);
+                println!("Started {} on {}", unique_job_name, host);
+            },
+            Err(e) => eprintln!("{}", e),
+        }
+        return;
+    }
+
+    if command.is_present("HOSTS") || command.is_present("HOSTS_FILE") {
+        let hosts = match command.value_of("HOSTS_FILE") {
+            Some(path) => match remote::read_hosts_file(path) {
+                Ok(hosts) => hosts,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            },
+            None => command.value_of("HOSTS").unwrap().split(',').map(|h| h.trim().to_string()).filter(|h| !h.is_empty()).collect(),
+        };
+
+        if hosts.is_empty() {
+            eprintln!("No hosts to start the job on");
+            return;
+        }
+
+        let input = match job_input::read(command) {
+            Ok(input) => input,
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        };
+
+        let group = haikunator.haikunate();
+        let launches = remote::launch_group(&hosts, &group, &remote::forwarded_args(command), invocation.script(), input.as_ref().map(|i| i.as_slice()), &config);
+
+        for (host, &(ref job_name, ref result)) in hosts.iter().zip(&launches) {
+            match *result {
+                Ok(()) => println!("Started {} on {}", job_name, host),
+                Err(ref e) => eprintln!("Couldn't start a job on {}: {}", host, e),
+            }
+        }
+
+        let started = launches.iter().filter(|&&(_, ref r)| r.is_ok()).count();
+        println!("Started {} of {} jobs in group {}. Run `kraken show group {}` to follow them.", started, hosts.len(), group, group);
+        return;
+    }
+
+    if let Some(dir) = command.value_of("OUTPUT_DIR") {
+        config.output_dir = dir.to_string();
+    }
+
+    // Kept absolute, since the daemon runs in the job's working directory
+    config.output_dir = match fs::create_dir_all(&config.output_dir).and_then(|_| fs::canonicalize(&config.output_dir)) {
+        Ok(dir) => dir.to_string_lossy().into_owned(),
+        Err(e) => {
+            eprintln!("Invalid output directory {}: {}", config.output_dir, e);
+            return;
+        }
+    };
+
+    unique_job_name = match job_names::resolve(&unique_job_name, Some(&config.output_dir), on_collision) {
+        Ok(name) => name,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let policy = match rotation_policy(command, &config) {
+        Ok(policy) => policy,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let capture_options = match Decoding::parse(&config.decoding) {
+        Some(decoding) => CaptureOptions { max_line_bytes: config.max_line_bytes, decoding: decoding },
+        None => {
+            eprintln!("Unknown decoding `{}` in ~/.kraken.json, expected lossy or escaped", config.decoding);
+            return;
+        }
+    };
+
+    let transport = match log_shipper::Transport::parse(&config.log_transport) {
+        Some(transport) => transport,
+        None => {
+            eprintln!("Unknown log_transport `{}` in ~/.kraken.json, expected stream or http", config.log_transport);
+            return;
+        }
+    };
 
-            // Check if the user supplied their own job name
+    let upload_artifacts = command.is_present("UPLOAD_ARTIFACTS");
 
-            if let Some(name) = command.value_of("JOB_NAME") {
-                unique_job_name = name.to_string();
+    let sample_interval = match command.value_of("SAMPLE_INTERVAL") {
+        Some(secs) => match secs.parse::<u64>() {
+            Ok(secs) => secs,
+            Err(_) => {
+                eprintln!("Invalid --sample-interval: {}", secs);
+                return;
             }
+        },
+        None => config.sample_interval_secs,
+    };
 
-            if let Some(desc) = command.value_of("DESCRIPTION") {
-                job_description = desc;
+    let grace_period = match command.value_of("GRACE_PERIOD") {
+        Some(secs) => match secs.parse::<u64>() {
+            Ok(secs) => Duration::from_secs(secs),
+            Err(_) => {
+                eprintln!("Invalid --grace-period: {}", secs);
+                return;
             }
+        },
+        None => Duration::from_secs(config.grace_period_secs),
+    };
 
-            krephis::new_job(&hostname, &unique_job_name, &job_description);
+    let mut notify_targets = Vec::new();
+    for spec in command.values_of("NOTIFY").into_iter().flatten() {
+        match notifications::Target::parse(spec) {
+            Some(target) => notify_targets.push(target),
+            None => {
+                eprintln!("Invalid --notify {}, expected cmd:COMMAND, webhook:URL or email:ADDRESS", spec);
+                return;
+            }
+        }
+    }
 
-            let c = command.value_of("COMMAND").unwrap().to_string();
+    let triggers = match triggers::Triggers::new(
+        command.values_of("ALERT_ON").into_iter().flatten(),
+        command.values_of("FAIL_ON").into_iter().flatten(),
+    ) {
+        Ok(triggers) => triggers,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
 
-            // Create a daemon for this job
-            let job_daemon = Daemonize::new()
-                .pid_file(job_daemon_pid_file_path) // Every method except `new` and `start`
-                .chown_pid_file(true)      // is optional, see `Daemonize` documentation
-                .working_directory("/tmp") // for default behaviour.
-                .privileged_action(move || {
-                    // This action gets run inside the daemon
-                    let jdofp = job_daemon_output_file_path.to_string();
+    let env_vars = match job_spec::parse_env(command.values_of("ENV").into_iter().flatten()) {
+        Ok(env_vars) => env_vars,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
 
-                    let _job_stdout = File::create(&jdofp).unwrap();
-                    let _job_stderr = File::create(&jdofp).unwrap();
+    let labels = match kraken_utils::parse_labels(command.values_of("TAG").into_iter().flatten()) {
+        Ok(labels) => labels,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
 
-                    let cmd = c.to_string();
-                    let ujn = unique_job_name.to_string();
+    // Kept absolute so the job can be run again from anywhere
+    let workdir_arg = command.value_of("WORKDIR").unwrap_or(".");
+    let workdir = match fs::canonicalize(workdir_arg) {
+        Ok(dir) if dir.is_dir() => dir.to_string_lossy().into_owned(),
+        Ok(_) => {
+            eprintln!("Invalid --workdir {}: not a directory", workdir_arg);
+            return;
+        },
+        Err(e) => {
+            eprintln!("Invalid --workdir {}: {}", workdir_arg, e);
+            return;
+        }
+    };
+
+    let output_dir = config.output_dir.clone();
+    let job_daemon_output_file_path = job_output::job_file_path(&output_dir, &unique_job_name, "out");
+    let job_daemon_error_file_path = job_output::job_file_path(&output_dir, &unique_job_name, "err");
+    let job_daemon_pid_file_path = job_output::job_file_path(&output_dir, &unique_job_name, "pid");
 
-                    let mut threads = Vec::new();
+    println!("Local output/error files below:\n{}\n{}", job_daemon_output_file_path.display(), job_daemon_error_file_path.display());
 
-                    // Run the command
-                    threads.push(thread::spawn(move || {
+    // A script runs from its own copy, so the file can change while the job runs
+    let script_path = job_output::job_file_path(&output_dir, &unique_job_name, "script");
+    if let Some(script) = invocation.script() {
+        if let Err(e) = script.save(&script_path) {
+            eprintln!("{}", e);
+            return;
+        }
+    }
 
-                        let stream_object = Exec::shell(&cmd).stream_stdout().unwrap();
-                        let reader = BufReader::new(stream_object);
+    let argv = invocation.argv(&script_path);
+    if !invocation::find_program(&argv[0], &workdir, &env_vars) {
+        eprintln!("Couldn't find {} to run", argv[0]);
+        return;
+    }
+
+    // Piped input has to be read before kraken detaches from it
+    let job_stdin = match job_input::prepare(command, &output_dir, &unique_job_name) {
+        Ok(input) => input,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let job_pipe = if command.is_present("STDIN_PIPE") {
+        let path = job_input::pipe_path(&output_dir, &unique_job_name);
+        if let Err(e) = job_input::create_pipe(&path) {
+            eprintln!("{}", e);
+            return;
+        }
+        Some(path)
+    } else {
+        None
+    };
+
+    let tty = command.is_present("TTY");
+    let group = command.value_of("GROUP");
+
+    let mut record = job_registry::JobRecord::new(&unique_job_name, &hostname, job_description, &invocation.display());
+    record.group = group.map(|g| g.to_string());
+    record.spec = Some(job_spec::from_matches(command, &workdir, &invocation, &argv));
+    record.labels = labels.clone();
+    if let Err(e) = job_registry::save(&output_dir, &record) {
+        eprintln!("Couldn't write local job record: {}", e);
+    }
 
-                        let mut file = OpenOptions::new()
-                            .write(true)
-                            .append(true)
-                            .open(&jdofp)
-                            .unwrap();
+    let server_spec = job_spec::for_server(record.spec.as_ref().unwrap());
+    krephis::new_job(&hostname, &unique_job_name, &job_description, group, &server_spec, &labels);
 
-                        let s = format!("Kraken - Job - {}\n======\n$> {}\n", Utc::now(), &cmd);
-                        let _ = writeln!(file, "{}", s);
+    // Create a daemon for this job
+    let job_daemon = Daemonize::new()
+        .pid_file(job_daemon_pid_file_path) // Every method except `new` and `start`
+        .chown_pid_file(true)      // is optional, see `Daemonize` documentation
+        .working_directory(&workdir) // for default behaviour.
+        .privileged_action(move || {
+            // This action gets run inside the daemon
+            shutdown::block_signals();
 
-                        krephis::new_log(&ujn, &s);
+            let mut job_stderr = File::create(&job_daemon_error_file_path).unwrap();
 
-                        // Write output stream to the file
-                        for line in reader.lines() {
+            let job_argv = argv.clone();
+            let ujn = unique_job_name.to_string();
+            let od = output_dir.to_string();
+            let rp = policy.clone();
+            let machine = hostname.to_string();
+            let targets = notify_targets.clone();
+            let mut triggers = triggers.clone();
+            let notify_config = config.clone();
+            let job_env = env_vars.clone();
+            let input = job_stdin.clone();
+            let pipe = job_pipe.clone();
 
-                            file = OpenOptions::new()
-                                .write(true)
-                                .append(true)
-                                .open(&jdofp)
-                                .unwrap();
+            let mut threads = Vec::new();
+            let (pid_sender, pid_receiver) = mpsc::channel();
+            let (resources_sender, resources_receiver) = mpsc::channel();
 
-                            let x = format!("{}", line.unwrap());
-                            let _ = writeln!(file, "{}", x);
-                            krephis::new_log(&ujn, &x);
+            // Run the command
+            threads.push(thread::spawn(move || {
+
+                let mut exec = Command::new(&job_argv[0]);
+                exec.args(&job_argv[1..]);
+                for &(ref name, ref value) in &job_env {
+                    exec.env(name, value);
+                }
+
+                // Created before the job starts, so a job whose output can't be written never runs
+                let mut output = match JobOutput::create(&od, &ujn, rp) {
+                    Ok(output) => output,
+                    Err(e) => {
+                        let _ = writeln!(job_stderr, "Couldn't create the output file in {}: {}", od, e);
+                        let _ = pid_sender.send(None);
+
+                        if let Some(ref pipe) = pipe {
+                            let _ = fs::remove_file(pipe);
+                        }
+
+                        let _ = job_registry::finish(&od, &ujn, None, None);
+                        let _ = fs::remove_file(job_output::job_file_path(&od, &ujn, "pid"));
+                        let exit = protocol::JobExit { status: "failed".to_string(), exit_code: None };
+                        if let Err(e) = krephis::finish_job(&ujn, &exit) {
+                            eprintln!("Couldn't send the exit code: {}", e);
+                        }
+                        return;
+                    }
+                };
+
+                let has_input = input.is_some() || pipe.is_some();
+                let (mut process, job_stdin, pipes) = match spawn_job(&mut exec, tty, has_input, &od, &ujn) {
+                    Ok(spawned) => spawned,
+                    Err(e) => {
+                        // The job never ran, so it's failed without an exit code. Its program
+                        // was there when it was checked for, but a script's interpreter may not be.
+                        let _ = match e.kind() {
+                            io::ErrorKind::NotFound => writeln!(job_stderr, "Couldn't find {} to run", job_argv[0]),
+                            _ => writeln!(job_stderr, "Couldn't start {}: {}", job_argv[0], e),
+                        };
+                        let _ = pid_sender.send(None);
+
+                        if let Some(ref pipe) = pipe {
+                            let _ = fs::remove_file(pipe);
+                        }
+
+                        if tty {
+                            let _ = fs::remove_file(job_terminal::socket_path(&od, &ujn));
+                        }
 
+                        let _ = job_registry::finish(&od, &ujn, None, None);
+                        let _ = fs::remove_file(job_output::job_file_path(&od, &ujn, "pid"));
+                        let exit = protocol::JobExit { status: "failed".to_string(), exit_code: None };
+                        if let Err(e) = krephis::finish_job(&ujn, &exit) {
+                            eprintln!("Couldn't send the exit code: {}", e);
                         }
+                        return;
+                    }
+                };
+
+                // Holds the job's end of its terminal, which has to close for its output to end
+                drop(exec);
 
-                        let _ = writeln!(file, "exit");
-                        krephis::new_log(&ujn, "exit");
+                let _ = pid_sender.send(Some(process.id()));
+                let job_process = triggers::JobProcess::new(process.id());
+                // A marker left by an earlier job with this name would make a shutdown look like a kill
+                job_registry::take_killed(&od, &ujn);
+                let interrupted = shutdown::forward(job_process.clone(), grace_period);
+
+                // Not joined, since it can be waiting on the pipe for a sender when the job exits
+                if let Some(stdin) = job_stdin {
+                    if input.is_some() || pipe.is_some() {
+                        let input = input.clone();
+                        let pipe = pipe.clone();
+                        thread::spawn(move || job_input::feed(stdin, input, pipe));
+                    }
+                }
 
-                        // Kill the watcher thread by updating the atomic variable
-                        ATOMIC_COMMAND_DONE.store(true, Ordering::Relaxed);
+                // One reader per stream, funnelled into a single channel so every line gets its
+                // sequence number in capture order. Lines are timestamped as they're split off,
+                // before the runner gets to them.
+                let (sender, receiver) = mpsc::channel();
 
+                let mut readers = Vec::new();
+                for (stream, pipe) in pipes {
+                    let sender = sender.clone();
+                    readers.push(thread::spawn(move || {
+                        for line in LineSplitter::new(pipe, capture_options) {
+                            match line {
+                                Ok(text) => {
+                                    let _ = sender.send((stream, text, Utc::now()));
+                                },
+                                Err(e) => {
+                                    let _ = sender.send((Stream::Kraken, format!("Couldn't read {}: {}", stream.as_str(), e), Utc::now()));
+                                    break;
+                                }
+                            }
+                        }
                     }));
+                }
+                drop(sender);
+
+                let mut sequence = Sequence::new();
+                let shipper = LogShipper::start(&ujn, transport);
+
+                let header = format!("Kraken - Job - {}\n======\n$> {}", Utc::now(), invocation::quote(&job_argv));
+                for text in header.lines() {
+                    let line = sequence.next(Stream::Kraken, text);
+                    let _ = output.write_line(&line.to_record());
+                    shipper.send(&line);
+                }
 
-                    for t in threads {
-                        t.join().unwrap();
+                let mut alert_threads = Vec::new();
+                let mut tracker = progress::Tracker::new();
+                let mut artifact_paths = Vec::new();
+                let mut progress_saved_at: Option<Instant> = None;
+
+                // Write output streams to the file
+                loop {
+                    let (stream, text, captured_at) = match receiver.recv_timeout(Duration::from_secs(1)) {
+                        Ok(received) => received,
+                        Err(mpsc::RecvTimeoutError::Timeout) => {
+                            let _ = output.tick();
+                            continue;
+                        },
+                        Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                    };
+
+                    // Progress, metric and artifact reports go to the job record and
+                    // server instead of the output
+                    if stream == Stream::Stdout {
+                        if let Some(report) = progress::parse(&text) {
+                            let job_progress = tracker.update(report);
+                            shipper.send_progress(&job_progress);
+
+                            if progress_saved_at.map_or(true, |t| t.elapsed() >= Duration::from_secs(1)) {
+                                let _ = job_registry::set_progress(&od, &ujn, &job_progress);
+                                progress_saved_at = Some(Instant::now());
+                            }
+                            continue;
+                        }
+
+                        match metrics::parse(&text) {
+                            Some(metrics::Report::Metrics(points)) => {
+                                let _ = metrics::append(&od, &ujn, &points);
+                                shipper.send_metrics(&points);
+                                continue;
+                            },
+                            Some(metrics::Report::Artifact(path)) => {
+                                let artifact = metrics::artifact(&path);
+                                if let Ok(registered) = job_registry::add_artifact(&od, &ujn, &artifact) {
+                                    if registered.name != artifact.name {
+                                        eprintln!("Another artifact is already named {}, so {} is registered as {}", artifact.name, path.display(), registered.name);
+                                    }
+                                }
+                                artifact_paths.push(path);
+                                continue;
+                            },
+                            None => {},
+                        }
                     }
 
-                });
+                    // Alerts go out on their own thread so the job's output keeps flowing
+                    if stream != Stream::Kraken {
+                        for alert in triggers.check(&text) {
+                            let _ = job_registry::add_alert(&od, &ujn, &alert);
+
+                            if alert.action == triggers::Action::Fail.as_str() {
+                                job_process.terminate();
+                            }
+
+                            let alert_ujn = ujn.clone();
+                            let alert_machine = machine.clone();
+                            let alert_targets = targets.clone();
+                            let alert_config = notify_config.clone();
+
+                            alert_threads.push(thread::spawn(move || {
+                                if let Err(e) = krephis::send_alert(&alert_ujn, &alert) {
+                                    eprintln!("Couldn't send alert: {}", e);
+                                }
+
+                                // A failed job is reported once it has exited
+                                if alert.action == triggers::Action::Alert.as_str() {
+                                    let notification = notifications::Notification::new(&alert_ujn, &alert_machine, "alert", None, Some(alert));
+                                    for e in notifications::send_all(&alert_targets, &notification, &alert_config) {
+                                        eprintln!("Couldn't notify {}", e);
+                                    }
+                                }
+                            }));
+                        }
+                    }
+
+                    let line = sequence.next_at(stream, &text, captured_at);
+                    let _ = output.write_line(&line.to_record());
+                    shipper.send(&line);
+                }
+
+                if let Some(job_progress) = tracker.finish() {
+                    shipper.send_progress(&job_progress);
+                    let _ = job_registry::set_progress(&od, &ujn, &job_progress);
+                }
+
+                for r in readers {
+                    let _ = r.join();
+                }
+
+                // `kraken top` kills a job with the same SIGTERM a shutdown sends, marking it first
+                let signalled = interrupted.load(Ordering::SeqCst);
+                let killed = signalled && job_registry::take_killed(&od, &ujn);
+                let interrupted = signalled && !killed;
+                if signalled {
+                    let line = sequence.next(Stream::Kraken, if killed { "killed" } else { "interrupted" });
+                    let _ = output.write_line(&line.to_record());
+                    shipper.send(&line);
+                }
+
+                let line = sequence.next(Stream::Kraken, "exit");
+                let _ = output.write_line(&line.to_record());
+                let _ = output.flush();
+                shipper.send(&line);
+
+                let exit_code = match job_process.wait(&mut process) {
+                    Ok(status) => status.code().map(|code| code as i64),
+                    Err(_) => None,
+                };
 
-            match job_daemon.start() {
-                Ok(_) => {
+                // Stop the sampler, whose summary goes in the same record update as the exit,
+                // so the runner is the only one writing the record
+                ATOMIC_COMMAND_DONE.store(true, Ordering::Relaxed);
+                let resources = resources_receiver.recv().ok();
 
-                },
-                Err(e) => eprintln!("Kraken - Job - Error - {}\n======\n{}\n", Utc::now(), e),
+                if let Some(ref pipe) = pipe {
+                    let _ = fs::remove_file(pipe);
+                }
+
+                if tty {
+                    let _ = fs::remove_file(job_terminal::socket_path(&od, &ujn));
+                }
+
+                // Recorded before anything goes over the network, so a shutdown that kills the
+                // daemon before the server hears of it still leaves the job to resume
+                let _ = if interrupted {
+                    job_registry::interrupt(&od, &ujn, exit_code, resources.clone())
+                } else if killed {
+                    job_registry::kill(&od, &ujn, exit_code, resources.clone())
+                } else {
+                    job_registry::finish(&od, &ujn, exit_code, resources.clone())
+                };
+
+                if interrupted {
+                    shipper.finish_within(Duration::from_secs(shutdown::SHIP_WITHIN_SECS));
+                } else {
+                    shipper.finish();
+                }
+
+                if let Some(ref summary) = resources {
+                    if let Err(e) = krephis::set_resources(&ujn, summary) {
+                        eprintln!("Couldn't send resource usage: {}", e);
+                    }
+                }
+
+                if let Ok(record) = job_registry::load(&od, &ujn) {
+                    let exit = protocol::JobExit { status: record.status, exit_code: exit_code };
+                    if let Err(e) = krephis::finish_job(&ujn, &exit) {
+                        eprintln!("Couldn't send the exit code: {}", e);
+                    }
+                }
+
+                // Sizes are only final now the job has exited
+                for path in &artifact_paths {
+                    let artifact = metrics::artifact(path);
+                    let artifact = job_registry::add_artifact(&od, &ujn, &artifact).unwrap_or(artifact);
+
+                    if upload_artifacts {
+                        if let Err(e) = krephis::upload_artifact(&ujn, &artifact) {
+                            eprintln!("Couldn't upload {}: {}", artifact.name, e);
+                        }
+                    }
+                }
+
+                for t in alert_threads {
+                    let _ = t.join();
+                }
+
+                if !targets.is_empty() {
+                    let record = job_registry::load(&od, &ujn).ok();
+                    let status = record.as_ref().map(|r| r.status.clone()).unwrap_or("failed".to_string());
+                    let alert = record.and_then(|r| r.alerts.into_iter().find(|a| a.action == triggers::Action::Fail.as_str()));
+                    let notification = notifications::Notification::new(&ujn, &machine, &status, exit_code, alert);
+
+                    for e in notifications::send_all(&targets, &notification, &notify_config) {
+                        eprintln!("Couldn't notify {}", e);
+                    }
+                }
+
+                // The pid can belong to something else once the daemon exits
+                let _ = fs::remove_file(job_output::job_file_path(&od, &ujn, "pid"));
+            }));
+
+            // Sample the job's resource usage until it exits, then hand the runner the summary
+            let ujn = unique_job_name.to_string();
+            let od = output_dir.to_string();
+
+            threads.push(thread::spawn(move || {
+
+                let pid = match pid_receiver.recv() {
+                    Ok(Some(pid)) if sample_interval > 0 => pid,
+                    _ => return,
+                };
+
+                let mut sampler = resources::Sampler::new(pid);
+                let mut samples = Vec::new();
+
+                while !ATOMIC_COMMAND_DONE.load(Ordering::Relaxed) {
+                    match sampler.sample() {
+                        Some(sample) => {
+                            let _ = resources::append(&od, &ujn, &sample);
+                            samples.push(sample);
+                        },
+                        None => break,
+                    }
+
+                    // Check often so the summary isn't held up by a long interval
+                    let started = Instant::now();
+                    while started.elapsed() < Duration::from_secs(sample_interval) && !ATOMIC_COMMAND_DONE.load(Ordering::Relaxed) {
+                        thread::sleep(Duration::from_millis(100));
+                    }
+                }
+
+                if let Some(summary) = resources::summarize(&samples) {
+                    let _ = resources_sender.send(summary);
+                }
+
+            }));
+
+            for t in threads {
+                t.join().unwrap();
             }
 
+        });
+
+    match job_daemon.start() {
+        Ok(_) => {
+
         },
-        
-        _ => println!("Use `kraken new -h` for help"),
+        Err(e) => eprintln!("Kraken - Job - Error - {}\n======\n{}\n", Utc::now(), e),
     }
 }
 
+// A started job, with its stdin if it takes input and the output streams to read
+type SpawnedJob = (Child, Option<Box<dyn Write + Send>>, Vec<(Stream, Box<dyn Read + Send>)>);
+
+// Starts the job, on a terminal shared for `attach job` if it's run with `--tty`
+fn spawn_job(exec: &mut Command, tty: bool, has_input: bool, dir: &str, job_name: &str) -> io::Result<SpawnedJob> {
+
+    // On a terminal the job's stdout and stderr arrive together, as they would on screen
+    if tty {
+        let master = job_terminal::open()?.run_on(exec)?;
+        let typed = master.try_clone()?;
+        let output = job_terminal::share(dir, job_name, master)?;
+        let process = exec.spawn()?;
+        return Ok((process, Some(Box::new(typed)), vec![(Stream::Stdout, Box::new(output))]));
+    }
+
+    // Nothing is read from kraken's own stdin, which the job isn't meant to have
+    exec.stdin(if has_input { Stdio::piped() } else { Stdio::null() });
+
+    let mut process = exec
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let job_stdin = process.stdin.take().map(|stdin| Box::new(stdin) as Box<dyn Write + Send>);
+    let pipes = vec![
+        (Stream::Stdout, Box::new(process.stdout.take().unwrap()) as Box<dyn Read + Send>),
+        (Stream::Stderr, Box::new(process.stderr.take().unwrap()) as Box<dyn Read + Send>),
+    ];
+    Ok((process, job_stdin, pipes))
+}
+
+// Build the output rotation policy from the command line, falling back to the config
+fn rotation_policy(command: &ArgMatches, config: &config::Config) -> Result<RotationPolicy, String> {
+
+    let max_bytes = match command.value_of("ROTATE_SIZE") {
+        Some(size) => size.parse::<u64>().map_err(|_| format!("Invalid --rotate-size: {}", size))?,
+        None => config.rotate_bytes,
+    };
+
+    let max_seconds = match command.value_of("ROTATE_INTERVAL") {
+        Some(secs) => secs.parse::<u64>().map_err(|_| format!("Invalid --rotate-interval: {}", secs))?,
+        None => config.rotate_seconds,
+    };
+
+    let compression_name = command.value_of("COMPRESS").unwrap_or(&config.compression);
+    let compression = Compression::parse(compression_name)
+        .ok_or(format!("Unknown compression `{}`, expected none, gzip or zstd", compression_name))?;
+
+    Ok(RotationPolicy {
+        max_bytes: max_bytes,
+        max_age: if max_seconds > 0 { Some(Duration::from_secs(max_seconds)) } else { None },
+        compression: compression,
+    })
+}
+
 // Show
 fn show(matches: &ArgMatches) {
     match matches.subcommand() {
-        ("jobs", Some(_command)) => {
-            krephis::show_jobs();
+        ("jobs", Some(command)) => {
+            let tags: Vec<&str> = command.values_of("TAG").into_iter().flatten().collect();
+            krephis::show_jobs(&tags);
+        },
+
+        ("group", Some(command)) => {
+            krephis::show_group(command.value_of("GROUP_ID").unwrap());
         },
 
         ("job", Some(command)) => {
             let job_name = command.value_of("JOB_NAME").unwrap().to_string();
             let line_limit = command.value_of("LINE_LIMIT").unwrap().to_string();
 
-            krephis::show_job(&job_name, &line_limit);
+            if command.is_present("METRICS") {
+                show_metrics(command, &job_name);
+                return;
+            }
+
+            if command.is_present("RESOURCES") {
+                show_resources(command, &job_name);
+                return;
+            }
+
+            let filter = match log_filter(command) {
+                Ok(filter) => filter,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            };
+
+            if command.is_present("LOCAL") {
+                let mut config = config::load();
+                if let Some(dir) = command.value_of("OUTPUT_DIR") {
+                    config.output_dir = dir.to_string();
+                }
+
+                show_local_job(&config.output_dir, &job_name, &line_limit, &filter);
+
+                if let Some(job_progress) = job_registry::load(&config.output_dir, &job_name).ok().and_then(|r| r.progress) {
+                    progress::ProgressView::new().show(&job_progress);
+                }
+            } else {
+                krephis::show_job(&job_name, &line_limit, &filter);
+
+                if let Ok(Some(job_progress)) = krephis::progress(&job_name) {
+                    progress::ProgressView::new().show(&job_progress);
+                }
+            }
         },
         _ => println!("Use `kraken show -h` for help"),
     }
 }
 
+// `show job --metrics`, from this machine's files with `--local` or from the server
+fn show_metrics(command: &ArgMatches, job_name: &str) {
+
+    let (job_metrics, artifacts) = if command.is_present("LOCAL") {
+        let mut config = config::load();
+        if let Some(dir) = command.value_of("OUTPUT_DIR") {
+            config.output_dir = dir.to_string();
+        }
+
+        let job_metrics = match metrics::read(&config.output_dir, job_name) {
+            Ok(job_metrics) => job_metrics,
+            Err(e) => {
+                eprintln!("Couldn't read local metrics for {}: {}", job_name, e);
+                return;
+            }
+        };
+
+        let artifacts = job_registry::load(&config.output_dir, job_name).map(|r| r.artifacts).unwrap_or_default();
+        (job_metrics, artifacts)
+    } else {
+        let job_metrics = match krephis::metrics(job_name) {
+            Ok(job_metrics) => job_metrics,
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        };
+
+        (job_metrics, krephis::artifacts(job_name).unwrap_or_default())
+    };
+
+    if command.is_present("CSV") {
+        metrics::print_csv(&job_metrics);
+    } else {
+        metrics::print_summary(&job_metrics, &artifacts);
+    }
+}
+
+// `show job --resources`. Locally a running job is summarized from the samples so far.
+fn show_resources(command: &ArgMatches, job_name: &str) {
+
+    let summary = if command.is_present("LOCAL") {
+        let mut config = config::load();
+        if let Some(dir) = command.value_of("OUTPUT_DIR") {
+            config.output_dir = dir.to_string();
+        }
+
+        match job_registry::load(&config.output_dir, job_name).ok().and_then(|r| r.resources) {
+            Some(summary) => Some(summary),
+            None => match resources::read(&config.output_dir, job_name) {
+                Ok(samples) => resources::summarize(&samples),
+                Err(e) => {
+                    eprintln!("Couldn't read local resource samples for {}: {}", job_name, e);
+                    return;
+                }
+            },
+        }
+    } else {
+        match krephis::resources(job_name) {
+            Ok(summary) => summary,
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        }
+    };
+
+    match summary {
+        Some(summary) => resources::print_summary(&summary),
+        None => println!("No resource usage recorded for {} yet.", job_name),
+    }
+}
+
+// Build the `--timestamps`/`--since`/`--until` options for `show job`
+fn log_filter(command: &ArgMatches) -> Result<LogFilter, String> {
+
+    let since = match command.value_of("SINCE") {
+        Some(t) => Some(kraken_utils::parse_time(t).ok_or(format!("Invalid --since: {}", t))?),
+        None => None,
+    };
+
+    let until = match command.value_of("UNTIL") {
+        Some(t) => Some(kraken_utils::parse_time(t).ok_or(format!("Invalid --until: {}", t))?),
+        None => None,
+    };
+
+    Ok(LogFilter {
+        timestamps: command.is_present("TIMESTAMPS"),
+        since: since,
+        until: until,
+    })
+}
+
+// Print the last `line_limit` lines of a job's local output, including rotated segments
+fn show_local_job(output_dir: &str, job_name: &str, line_limit: &str, filter: &LogFilter) {
+
+    let limit = match line_limit.parse::<usize>() {
+        Ok(limit) => limit,
+        Err(_) => {
+            eprintln!("Invalid line limit: {}", line_limit);
+            return;
+        }
+    };
+
+    match job_output::read_lines(output_dir, job_name) {
+        Ok(lines) => {
+            let mut shown = Vec::new();
+
+            for record in &lines {
+                match LogLine::parse_record(record) {
+                    Some(line) => {
+                        if filter.matches(Some(line.timestamp)) {
+                            shown.push(filter.format(Some(line.timestamp), Some(line.stream.as_str()), &line.text));
+                        }
+                    },
+                    // Written before lines were tagged
+                    None => {
+                        if filter.matches(None) {
+                            shown.push(filter.format(None, None, record));
+                        }
+                    }
+                }
+            }
+
+            let start = shown.len().saturating_sub(limit);
+            for line in &shown[start..] {
+                println!("{}", line);
+            }
+        },
+        Err(e) => eprintln!("Couldn't read local output for {}: {}", job_name, e),
+    }
+}
+
+// Follow a job's output until it exits, with its progress drawn below
+fn tail(matches: &ArgMatches) {
+    match matches.subcommand() {
+        ("job", Some(command)) => {
+            let job_name = command.value_of("JOB_NAME").unwrap();
+
+            let local_dir = if command.is_present("LOCAL") {
+                let mut config = config::load();
+                if let Some(dir) = command.value_of("OUTPUT_DIR") {
+                    config.output_dir = dir.to_string();
+                }
+                Some(config.output_dir)
+            } else {
+                None
+            };
+
+            let mut view = progress::ProgressView::new();
+            let mut last_seq = None;
+
+            loop {
+                let (lines, job_progress) = match local_dir {
+                    Some(ref dir) => tail_local(dir, job_name),
+                    None => tail_remote(job_name),
+                };
+
+                let lines = match lines {
+                    Ok(lines) => lines,
+                    Err(e) => {
+                        view.clear();
+                        eprintln!("{}", e);
+                        return;
+                    }
+                };
+
+                // Lines from before seqs existed can't be told apart, so they're only shown once
+                let new_lines: Vec<&(u64, String, String)> = lines.iter()
+                    .filter(|line| last_seq.map_or(true, |seq| line.0 > seq))
+                    .collect();
+
+                if !new_lines.is_empty() {
+                    view.clear();
+                }
+
+                let mut exited = false;
+                for &&(_, ref stream, ref text) in &new_lines {
+                    if stream == "kraken" && text == "exit" {
+                        exited = true;
+                    }
+                    println!("{}", text);
+                }
+
+                last_seq = lines.iter().map(|line| line.0).max().or(last_seq);
+
+                if let Some(ref job_progress) = job_progress {
+                    view.show(job_progress);
+                }
+
+                let stopped = match local_dir {
+                    Some(ref dir) => !job_registry::is_running(dir, job_name),
+                    None => false,
+                };
+
+                if exited || stopped {
+                    return;
+                }
+
+                thread::sleep(Duration::from_secs(1));
+            }
+        },
+        _ => println!("Use `kraken tail -h` for help"),
+    }
+}
+
+// A job's newest lines as (seq, stream, text), and its progress
+type Tail = (Result<Vec<(u64, String, String)>, String>, Option<protocol::JobProgress>);
+
+// Newest lines of a local job, and its progress
+fn tail_local(dir: &str, job_name: &str) -> Tail {
+    let lines = job_output::tail_lines(dir, job_name, TAIL_LINES)
+        .map(|records| records.iter()
+            .map(|record| match LogLine::parse_record(record) {
+                Some(line) => (line.seq, line.stream.as_str().to_string(), line.text),
+                None => (0, String::new(), record.clone()),
+            })
+            .collect())
+        .map_err(|e| format!("Couldn't read local output for {}: {}", job_name, e));
+
+    let job_progress = job_registry::load(dir, job_name).ok().and_then(|r| r.progress);
+    (lines, job_progress)
+}
+
+fn tail_remote(job_name: &str) -> Tail {
+    let lines = krephis::logs(job_name, TAIL_LINES)
+        .map(|logs| logs.into_iter()
+            .map(|log| (log.seq.unwrap_or(0), log.stream.unwrap_or_default(), log.line))
+            .collect())
+        .map_err(|e| e.to_string());
+
+    (lines, krephis::progress(job_name).unwrap_or(None))
+}
+
 // Remove
 fn remove(matches: &ArgMatches) {
     match matches.subcommand() {
 
         ("job", Some(command)) => {
 
-            if command.is_present("ALL") {
-                krephis::remove_all_jobs();
+            if command.is_present("ALL") || command.is_present("TAG") {
+                remove_all(command);
             } else {
                 let job_name = command.value_of("JOB_NAME").unwrap().to_string();
-                krephis::remove_job(&job_name);
+
+                if !command.is_present("FORCE") {
+                    let jobs = match krephis::list_jobs() {
+                        Some(jobs) => jobs,
+                        None => return,
+                    };
+
+                    if jobs.iter().any(|job| job.name == job_name && is_job_running(job)) {
+                        eprintln!("{} is still running. Use --force to remove it anyway.", job_name);
+                        return;
+                    }
+                }
+
+                let _ = krephis::remove_job(&job_name);
             }
 
         },
@@ -182,6 +1331,211 @@ fn remove(matches: &ArgMatches) {
     }
 }
 
+// Remove every job matching `--machine`/`--status`/`--tag`, after listing them and asking first
+fn remove_all(command: &ArgMatches) {
+
+    let jobs = match krephis::list_jobs() {
+        Some(jobs) => jobs,
+        None => return,
+    };
+
+    let machine = command.value_of("MACHINE");
+    let status = command.value_of("STATUS");
+    let tags: Vec<&str> = command.values_of("TAG").into_iter().flatten().collect();
+    let force = command.is_present("FORCE");
+
+    let (running, targets): (Vec<_>, Vec<_>) = jobs.into_iter()
+        .filter(|job| machine.map_or(true, |m| job.machine == m))
+        .filter(|job| status.map_or(true, |s| job.status.as_ref().map(|x| x.as_str()) == Some(s)))
+        .filter(|job| kraken_utils::has_tags(&job.labels, &tags))
+        .partition(|job| !force && is_job_running(job));
+
+    for job in &running {
+        println!("Skipping {}: still running. Use --force to remove it anyway.", job.name);
+    }
+
+    if targets.is_empty() {
+        println!("No jobs to remove.");
+        return;
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![b->"Job Name", b->"Machine", b->"Status"]);
+    for job in &targets {
+        table.add_row(row![job.name, job.machine, job.status.clone().unwrap_or(String::new())]);
+    }
+    table.printstd();
+
+    if !command.is_present("YES") && !kraken_utils::confirm(&format!("Remove these {} jobs?", targets.len())) {
+        println!("Nothing removed.");
+        return;
+    }
+
+    // Only the jobs that were listed, since others may have been started since
+    let removed = targets.iter().filter(|job| krephis::remove_job(&job.name).is_ok()).count();
+
+    println!("Removed {} of {} jobs. Use `kraken restore job <name>` to undo.", removed, targets.len());
+}
+
+// A job counts as running if the server says so, or if it was started here and its daemon is alive
+fn is_job_running(job: &protocol::Job) -> bool {
+
+    if job.status.as_ref().map(|s| s.as_str()) == Some("running") {
+        return true;
+    }
+
+    match get_hostname() {
+        Some(hostname) if hostname == job.machine => job_registry::is_running(&config::load().output_dir, &job.name),
+        _ => false,
+    }
+}
+
+// Restore
+fn restore(matches: &ArgMatches) {
+    match matches.subcommand() {
+        ("job", Some(command)) => {
+            let job_name = command.value_of("JOB_NAME").unwrap();
+            krephis::restore_job(job_name);
+        },
+        _ => println!("Use `kraken restore -h` for help"),
+    }
+}
+
+// Prune
+fn prune(matches: &ArgMatches) {
+
+    let mut config = config::load();
+    if let Some(dir) = matches.value_of("OUTPUT_DIR") {
+        config.output_dir = dir.to_string();
+    }
+    let dir = config.output_dir;
+
+    let older_than = match matches.value_of("OLDER_THAN") {
+        Some(age) => match kraken_utils::parse_age(age) {
+            Some(secs) => Some(secs),
+            None => {
+                eprintln!("Invalid --older-than: {}. Use something like 30m, 12h or 7d.", age);
+                return;
+            }
+        },
+        None => None,
+    };
+
+    let keep_last = match matches.value_of("KEEP_LAST").unwrap_or("0").parse::<usize>() {
+        Ok(n) => n,
+        Err(_) => {
+            eprintln!("Invalid --keep-last: {}", matches.value_of("KEEP_LAST").unwrap());
+            return;
+        }
+    };
+
+    let status = matches.value_of("STATUS");
+    let dry_run = matches.is_present("DRY_RUN");
+    let remote = matches.is_present("REMOTE");
+
+    let names = match job_output::job_names(&dir) {
+        Ok(names) => names,
+        Err(e) => {
+            eprintln!("Couldn't read {}: {}", dir, e);
+            return;
+        }
+    };
+
+    // Newest first, so `--keep-last` keeps the most recent jobs
+    let mut jobs: Vec<(String, i64)> = names.into_iter().map(|name| {
+        let modified = job_output::last_modified(&dir, &name).unwrap_or(0);
+        (name, modified)
+    }).collect();
+    jobs.sort_by(|a, b| b.1.cmp(&a.1));
+
+    let now = Utc::now().timestamp();
+
+    // Filtered first, so `--keep-last` keeps the newest of the jobs that would otherwise go
+    let candidates: Vec<String> = jobs.into_iter().filter(|&(ref name, modified)| {
+        if let Some(age) = older_than {
+            if now - modified < age {
+                return false;
+            }
+        }
+
+        if let Some(wanted) = status {
+            let job_status = job_registry::load(&dir, name).map(|r| r.status).unwrap_or("unknown".to_string());
+            if job_status != wanted {
+                return false;
+            }
+        }
+
+        if job_registry::is_running(&dir, name) {
+            println!("Skipping {}: still running", name);
+            return false;
+        }
+
+        true
+    }).map(|(name, _)| name).collect();
+
+    for name in candidates.into_iter().skip(keep_last) {
+
+        for path in job_output::artifacts(&dir, &name).unwrap_or(Vec::new()) {
+            if dry_run {
+                println!("Would remove {}", path.display());
+            } else if let Err(e) = fs::remove_file(&path) {
+                eprintln!("Couldn't remove {}: {}", path.display(), e);
+            } else {
+                println!("Removed {}", path.display());
+            }
+        }
+
+        if remote {
+            if dry_run {
+                println!("Would remove {} from the server", name);
+            } else {
+                let _ = krephis::remove_job(&name);
+            }
+        }
+    }
+}
+
+// Serve the local dashboard
+fn serve(matches: &ArgMatches) {
+
+    let mut config = config::load();
+    if let Some(dir) = matches.value_of("OUTPUT_DIR") {
+        config.output_dir = dir.to_string();
+    }
+
+    let bind = matches.value_of("BIND").unwrap();
+    let port = matches.value_of("PORT").unwrap();
+
+    match format!("{}:{}", bind, port).parse() {
+        Ok(addr) => dashboard::serve(addr, config.output_dir),
+        Err(e) => eprintln!("Invalid address {}:{}: {}", bind, port, e),
+    }
+}
+
+// Live view of running jobs
+fn top(matches: &ArgMatches) {
+
+    let mut config = config::load();
+    if let Some(dir) = matches.value_of("OUTPUT_DIR") {
+        config.output_dir = dir.to_string();
+    }
+
+    let interval = match matches.value_of("INTERVAL").unwrap().parse::<u64>() {
+        Ok(secs) if secs > 0 => secs,
+        _ => {
+            eprintln!("--interval takes a whole number of seconds");
+            return;
+        }
+    };
+
+    top::run(top::TopOptions {
+        output_dir: config.output_dir,
+        local_only: matches.is_present("LOCAL"),
+        machine: matches.value_of("MACHINE").map(|m| m.to_string()),
+        refresh: Duration::from_secs(interval),
+    });
+}
+
 // Authentication
 fn login(matches: &ArgMatches) {
 
@@ -197,6 +1551,151 @@ fn logout() {
     krephis::logout();
 }
 
+// `new job`, which `rerun job` and `new job --from` also use to read back a job's saved options
+fn new_job_command<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("job")
+        .arg(Arg::with_name("COMMAND")
+            .required_unless_one(&["FROM", "ARGV", "SCRIPT"])
+            .conflicts_with("SCRIPT")
+            .help("Command line to run with the shell"))
+        .arg(Arg::with_name("FROM")
+            .long("from")
+            .takes_value(true)
+            .help("Start from an earlier job's spec. Options and a command given here replace its own."))
+        .args(&job_args())
+}
+
+// Options for the jobs `new job` and `rerun job` start
+fn job_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("DESCRIPTION")
+            .required(false)
+            .short("d")
+            .long("description")
+            .takes_value(true),
+        Arg::with_name("JOB_NAME")
+            .required(false)
+            .short("n")
+            .long("name")
+            .takes_value(true),
+        Arg::with_name("REPLACE")
+            .long("replace")
+            .conflicts_with("AUTO_SUFFIX")
+            .help("If a job with this name exists and has finished, remove it first"),
+        Arg::with_name("AUTO_SUFFIX")
+            .long("auto-suffix")
+            .help("If a job with this name exists, add -2, -3 and so on until the name is free"),
+        Arg::with_name("HOST")
+            .long("host")
+            .takes_value(true)
+            .conflicts_with_all(&["HOSTS", "HOSTS_FILE"])
+            .help("Run the job on another machine over ssh, like user@server"),
+        Arg::with_name("HOSTS")
+            .long("hosts")
+            .takes_value(true)
+            .conflicts_with_all(&["HOSTS_FILE", "JOB_NAME", "GROUP"])
+            .help("Run the job on each of these comma-separated hosts, as one group"),
+        Arg::with_name("HOSTS_FILE")
+            .long("hosts-file")
+            .takes_value(true)
+            .conflicts_with_all(&["JOB_NAME", "GROUP"])
+            .help("Like --hosts, with one host per line of a file"),
+        Arg::with_name("GROUP")
+            .long("group")
+            .takes_value(true)
+            .help("Put the job in a group, which `show group` reports on"),
+        Arg::with_name("TAG")
+            .long("tag")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("Label the job, as NAME=VALUE, to find it with `show jobs --tag`. Can be repeated."),
+        Arg::with_name("ENV")
+            .long("env")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("Set an environment variable for the job, as NAME=VALUE. Can be repeated. Values stay on this machine: the server only keeps the names, so starting the job again elsewhere reads them from that machine's environment."),
+        Arg::with_name("WORKDIR")
+            .long("workdir")
+            .takes_value(true)
+            .help("Directory to run the job in. Defaults to the current one."),
+        Arg::with_name("ARGV")
+            .multiple(true)
+            .last(true)
+            .help("Program and arguments to run without a shell, or arguments for --script"),
+        Arg::with_name("SHELL")
+            .long("shell")
+            .takes_value(true)
+            .help("Shell to run the command or script with, like bash, zsh or fish. Defaults to /bin/sh."),
+        Arg::with_name("SCRIPT")
+            .long("script")
+            .takes_value(true)
+            .help("Script file to run. It's copied, so changing it doesn't change the job."),
+        Arg::with_name("STDIN_FILE")
+            .long("stdin-file")
+            .takes_value(true)
+            .help("File to feed the job as its input, or - for the input piped into kraken"),
+        Arg::with_name("STDIN_PIPE")
+            .long("stdin-pipe")
+            .help("Keep the job's stdin open after its input for `kraken send job`"),
+        Arg::with_name("TTY")
+            .long("tty")
+            .conflicts_with("STDIN_PIPE")
+            .help("Run the job on a terminal of its own, which `kraken attach job` connects to"),
+        Arg::with_name("OUTPUT_DIR")
+            .long("output-dir")
+            .takes_value(true)
+            .help("Directory for output, error and pid files. Defaults to /tmp."),
+        Arg::with_name("ROTATE_SIZE")
+            .long("rotate-size")
+            .takes_value(true)
+            .help("Rotate the output file after this many bytes. 0 disables."),
+        Arg::with_name("ROTATE_INTERVAL")
+            .long("rotate-interval")
+            .takes_value(true)
+            .help("Rotate the output file after this many seconds. 0 disables."),
+        Arg::with_name("COMPRESS")
+            .long("compress")
+            .takes_value(true)
+            .possible_values(&["none", "gzip", "zstd"])
+            .help("Compression for rotated output segments"),
+        Arg::with_name("SAMPLE_INTERVAL")
+            .long("sample-interval")
+            .takes_value(true)
+            .help("Seconds between samples of the job's CPU, memory and I/O use. 0 disables."),
+        Arg::with_name("GRACE_PERIOD")
+            .long("grace-period")
+            .takes_value(true)
+            .help("Seconds the job gets to exit after SIGTERM when kraken is stopped, e.g. by a shutdown. Defaults to 30."),
+        Arg::with_name("RESUME_CMD")
+            .long("resume-cmd")
+            .takes_value(true)
+            .help("Command `kraken resume` runs instead of this one if the job is interrupted, e.g. to load a checkpoint"),
+        Arg::with_name("NOTIFY")
+            .long("notify")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("Notify when the job finishes: cmd:COMMAND, webhook:URL or email:ADDRESS. Can be repeated."),
+        Arg::with_name("ALERT_ON")
+            .long("alert-on")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("Flag the job and send --notify targets an alert when an output line matches this regex. Can be repeated."),
+        Arg::with_name("FAIL_ON")
+            .long("fail-on")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .help("Terminate the job and mark it failed when an output line matches this regex. Can be repeated."),
+        Arg::with_name("UPLOAD_ARTIFACTS")
+            .long("upload-artifacts")
+            .help("Upload files the job registers with `##kraken artifact` to the server when it exits"),
+    ]
+}
+
 fn main() {
 
     let app = App::new("Kraken")
@@ -207,7 +1706,20 @@ fn main() {
         // Show Commands
         .subcommand(
             SubCommand::with_name("show")
-                .subcommand(SubCommand::with_name("jobs"))
+                .subcommand(
+                    SubCommand::with_name("jobs")
+                        .arg(Arg::with_name("TAG")
+                            .long("tag")
+                            .takes_value(true)
+                            .multiple(true)
+                            .number_of_values(1)
+                            .help("Only show jobs with this label, as NAME=VALUE or just NAME. Can be repeated."))
+                )
+                .subcommand(
+                    SubCommand::with_name("group")
+                        .about("Status and exit codes of the jobs one `new job --hosts` started")
+                        .arg(Arg::with_name("GROUP_ID").required(true))
+                )
                 
                 .subcommand(
                     SubCommand::with_name("job")
@@ -215,41 +1727,254 @@ fn main() {
                         .arg(Arg::with_name("LINE_LIMIT")
                             .default_value("10")
                             .hide_default_value(false))
+                        .arg(Arg::with_name("LOCAL")
+                            .short("l")
+                            .long("local")
+                            .help("Read output from this machine's output files instead of the server"))
+                        .arg(Arg::with_name("OUTPUT_DIR")
+                            .long("output-dir")
+                            .takes_value(true)
+                            .requires("LOCAL"))
+                        .arg(Arg::with_name("TIMESTAMPS")
+                            .short("t")
+                            .long("timestamps")
+                            .help("Prefix each line with its capture time and stream"))
+                        .arg(Arg::with_name("SINCE")
+                            .long("since")
+                            .takes_value(true)
+                            .help("Only show lines captured after this time (RFC 3339, or an age like 10m)"))
+                        .arg(Arg::with_name("UNTIL")
+                            .long("until")
+                            .takes_value(true)
+                            .help("Only show lines captured before this time (RFC 3339, or an age like 10m)"))
+                        .arg(Arg::with_name("METRICS")
+                            .short("m")
+                            .long("metrics")
+                            .help("Summarize the job's metrics and artifacts instead of showing its output"))
+                        .arg(Arg::with_name("CSV")
+                            .long("csv")
+                            .requires("METRICS")
+                            .help("Print every metric as CSV"))
+                        .arg(Arg::with_name("RESOURCES")
+                            .short("r")
+                            .long("resources")
+                            .conflicts_with("METRICS")
+                            .help("Show the job's peak and average CPU, memory and I/O use"))
                 )
         )
 
         // New Commands
         .subcommand(
             SubCommand::with_name("new")
+                .subcommand(new_job_command())
+        )
+
+        // Rerun Commands
+        .subcommand(
+            SubCommand::with_name("rerun")
                 .subcommand(
                     SubCommand::with_name("job")
-                        .arg(Arg::with_name("COMMAND").required(true))
-                        .arg(Arg::with_name("DESCRIPTION")
-                            .required(false)
-                            .short("d")
-                            .long("description")
+                        .about("Start a job again from its spec. Options given here replace its own.")
+                        .arg(Arg::with_name("FROM").required(true))
+                        .args(&job_args())
+                )
+        )
+
+        // Resume Command
+        .subcommand(
+            SubCommand::with_name("resume")
+                .about("Start interrupted jobs again, e.g. from cron's @reboot")
+                .arg(Arg::with_name("JOB_NAME")
+                    .multiple(true)
+                    .help("Jobs to resume. Defaults to every interrupted job on this machine."))
+                .arg(Arg::with_name("RESUME_CMD")
+                    .long("resume-cmd")
+                    .takes_value(true)
+                    .help("Command to run instead of the jobs' own --resume-cmd or command"))
+                .arg(Arg::with_name("OUTPUT_DIR")
+                    .long("output-dir")
+                    .takes_value(true))
+        )
+
+        // Send Commands
+        .subcommand(
+            SubCommand::with_name("send")
+                .subcommand(
+                    SubCommand::with_name("job")
+                        .about("Write to the stdin of a job started on this machine with --stdin-pipe")
+                        .arg(Arg::with_name("JOB_NAME").required(true))
+                        .arg(Arg::with_name("TEXT")
+                            .help("Line to send. Without it, kraken's own input is sent."))
+                        .arg(Arg::with_name("EOF")
+                            .long("eof")
+                            .help("Close the job's stdin after sending"))
+                        .arg(Arg::with_name("OUTPUT_DIR")
+                            .long("output-dir")
                             .takes_value(true))
-                        .arg(Arg::with_name("JOB_NAME")
-                            .required(false)
-                            .short("n")
-                            .long("name")
+                )
+        )
+
+        // Attach Commands
+        .subcommand(
+            SubCommand::with_name("attach")
+                .subcommand(
+                    SubCommand::with_name("job")
+                        .about("Watch and type into a job started with --tty until it exits or Ctrl-p Ctrl-q detaches")
+                        .arg(Arg::with_name("JOB_NAME").required(true))
+                        .arg(Arg::with_name("HOST")
+                            .long("host")
+                            .takes_value(true)
+                            .help("Attach to a job running on this host, over ssh"))
+                        .arg(Arg::with_name("OUTPUT_DIR")
+                            .long("output-dir")
                             .takes_value(true))
                 )
         )
 
+        // Tail Commands
+        .subcommand(
+            SubCommand::with_name("tail")
+                .subcommand(
+                    SubCommand::with_name("job")
+                        .about("Follow a job's output and progress until it exits")
+                        .arg(Arg::with_name("JOB_NAME").required(true))
+                        .arg(Arg::with_name("LOCAL")
+                            .short("l")
+                            .long("local")
+                            .help("Follow this machine's output files instead of the server"))
+                        .arg(Arg::with_name("OUTPUT_DIR")
+                            .long("output-dir")
+                            .takes_value(true)
+                            .requires("LOCAL"))
+                )
+        )
+
         // Remove Commands
         .subcommand(
             SubCommand::with_name("remove")
                 .subcommand(SubCommand::with_name("job")
-                    .arg(Arg::with_name("JOB_NAME"))
+                    .arg(Arg::with_name("JOB_NAME")
+                        .required_unless_one(&["ALL", "TAG"]))
                     .arg(Arg::with_name("ALL")
                         .short("a")
                         .long("all")
                         .help("Removes all jobs. Any provided job name will be ignored.")
                     )
+                    .arg(Arg::with_name("TAG")
+                        .long("tag")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Removes all jobs with this label, as NAME=VALUE or just NAME. Can be repeated.")
+                    )
+                    .group(ArgGroup::with_name("BULK")
+                        .args(&["ALL", "TAG"])
+                        .multiple(true)
+                    )
+                    .arg(Arg::with_name("YES")
+                        .short("y")
+                        .long("yes")
+                        .requires("BULK")
+                        .help("Don't ask for confirmation before removing jobs")
+                    )
+                    .arg(Arg::with_name("MACHINE")
+                        .long("machine")
+                        .takes_value(true)
+                        .requires("BULK")
+                        .help("Only remove jobs that ran on this machine")
+                    )
+                    .arg(Arg::with_name("STATUS")
+                        .long("status")
+                        .takes_value(true)
+                        .requires("BULK")
+                        .help("Only remove jobs with this status")
+                    )
+                    .arg(Arg::with_name("FORCE")
+                        .short("f")
+                        .long("force")
+                        .help("Remove jobs even if they are still running")
+                    )
+                )
+        )
+
+        // Restore Commands
+        .subcommand(
+            SubCommand::with_name("restore")
+                .subcommand(SubCommand::with_name("job")
+                    .arg(Arg::with_name("JOB_NAME").required(true))
+                    .about("Bring back a removed job while the server's undo window is open")
                 )
         )
     
+        // Prune Command
+        .subcommand(
+            SubCommand::with_name("prune")
+                .about("Delete local output, error and pid files of finished jobs")
+                .arg(Arg::with_name("OLDER_THAN")
+                    .long("older-than")
+                    .takes_value(true)
+                    .help("Only prune jobs untouched for this long, e.g. 12h or 7d"))
+                .arg(Arg::with_name("STATUS")
+                    .long("status")
+                    .takes_value(true)
+                    .possible_values(&["done", "failed", "interrupted", "unknown"])
+                    .help("Only prune jobs with this local status"))
+                .arg(Arg::with_name("KEEP_LAST")
+                    .long("keep-last")
+                    .takes_value(true)
+                    .help("Always keep the N most recent jobs"))
+                .arg(Arg::with_name("DRY_RUN")
+                    .long("dry-run")
+                    .help("Print what would be removed without removing anything"))
+                .arg(Arg::with_name("REMOTE")
+                    .long("remote")
+                    .help("Also remove the pruned jobs from the server"))
+                .arg(Arg::with_name("OUTPUT_DIR")
+                    .long("output-dir")
+                    .takes_value(true))
+        )
+
+        // Serve Command
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("Serve a web dashboard of the jobs on this machine")
+                .arg(Arg::with_name("PORT")
+                    .short("p")
+                    .long("port")
+                    .takes_value(true)
+                    .default_value("8080"))
+                .arg(Arg::with_name("BIND")
+                    .long("bind")
+                    .takes_value(true)
+                    .default_value("127.0.0.1")
+                    .help("Address to listen on. Use 0.0.0.0 to allow other machines."))
+                .arg(Arg::with_name("OUTPUT_DIR")
+                    .long("output-dir")
+                    .takes_value(true))
+        )
+
+        // Top Command
+        .subcommand(
+            SubCommand::with_name("top")
+                .about("Watch jobs live in the terminal")
+                .arg(Arg::with_name("LOCAL")
+                    .short("l")
+                    .long("local")
+                    .help("Only show jobs started on this machine, without asking the server"))
+                .arg(Arg::with_name("MACHINE")
+                    .long("machine")
+                    .takes_value(true)
+                    .help("Only show jobs that ran on this machine. Press m to change it."))
+                .arg(Arg::with_name("INTERVAL")
+                    .long("interval")
+                    .takes_value(true)
+                    .default_value("2")
+                    .help("Seconds between refreshes"))
+                .arg(Arg::with_name("OUTPUT_DIR")
+                    .long("output-dir")
+                    .takes_value(true))
+        )
+
         // Auth Commands
         // Login
         .subcommand(
@@ -270,10 +1995,19 @@ fn main() {
     // Parse out commands
     match matches.subcommand() {
         ("new", Some(m)) => new(m),
+        ("rerun", Some(m)) => rerun(m),
+        ("resume", Some(m)) => resume(m),
+        ("send", Some(m)) => send(m),
+        ("attach", Some(m)) => attach(m),
         ("login", Some(m)) => login(m),
         ("logout", Some(_m)) => logout(),
         ("show", Some(m)) => show(m),
+        ("tail", Some(m)) => tail(m),
         ("remove", Some(m)) => remove(m),
+        ("restore", Some(m)) => restore(m),
+        ("prune", Some(m)) => prune(m),
+        ("serve", Some(m)) => serve(m),
+        ("top", Some(m)) => top(m),
         _ => println!("Use `kraken -h` for help"),
     }
 }
\ No newline at end of file
@@ -0,0 +1,186 @@
+// Metrics and artifacts reported by jobs
+// A job reports metrics and registers files it produced by printing lines like
+//
+//   ##kraken metric loss=0.12 acc=0.91
+//   ##kraken metric step=3 loss=0.12
+//   ##kraken artifact checkpoints/model.pt
+//
+// `step` isn't a metric itself, it numbers the others on its line. Like progress reports, these
+// lines are kept out of the job's output. Metrics are appended to `kraken-job-<name>.metrics`
+// and shipped to the server; artifacts are listed in the job record and uploaded when the job
+// exits if it was started with `--upload-artifacts`.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::prelude::*;
+use prettytable::Table;
+
+use job_output;
+use log_line;
+use protocol::{Artifact, Metric};
+
+pub enum Report {
+    Metrics(Vec<Metric>),
+    Artifact(PathBuf),
+}
+
+pub fn parse(text: &str) -> Option<Report> {
+    let text = text.trim_end();
+
+    if text.starts_with("##kraken metric ") {
+        parse_metrics(&text["##kraken metric ".len()..]).map(Report::Metrics)
+    } else if text.starts_with("##kraken artifact ") {
+        let path = text["##kraken artifact ".len()..].trim().trim_matches('"');
+        if path.is_empty() {
+            None
+        } else {
+            Some(Report::Artifact(PathBuf::from(path)))
+        }
+    } else {
+        None
+    }
+}
+
+// Every pair has to parse, otherwise the line is left in the output for the user to see
+fn parse_metrics(pairs: &str) -> Option<Vec<Metric>> {
+    let timestamp = log_line::format_timestamp(&Utc::now());
+    let mut step = None;
+    let mut metrics = Vec::new();
+
+    for pair in pairs.split_whitespace() {
+        let mut parts = pair.splitn(2, '=');
+        let name = parts.next()?;
+        let value = parts.next()?;
+
+        if name.is_empty() {
+            return None;
+        }
+
+        if name == "step" {
+            step = Some(value.parse().ok()?);
+        } else {
+            // NaN and infinities have no JSON, so they'd be lost from the metrics file
+            let value: f64 = value.parse().ok()?;
+            if !value.is_finite() {
+                return None;
+            }
+
+            metrics.push(Metric {
+                name: name.to_string(),
+                value: value,
+                step: None,
+                timestamp: timestamp.clone(),
+            });
+        }
+    }
+
+    if metrics.is_empty() {
+        return None;
+    }
+
+    for metric in &mut metrics {
+        metric.step = step;
+    }
+
+    Some(metrics)
+}
+
+// Relative artifact paths are relative to where the job runs
+pub fn artifact(path: &Path) -> Artifact {
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        env::current_dir().map(|dir| dir.join(path)).unwrap_or(path.to_path_buf())
+    };
+
+    Artifact {
+        name: path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
+        size: fs::metadata(&path).map(|m| m.len()).unwrap_or(0),
+        path: Some(path.to_string_lossy().into_owned()),
+    }
+}
+
+// Local metrics files
+
+pub fn append(dir: &str, job_name: &str, metrics: &[Metric]) -> io::Result<()> {
+    let path = job_output::job_file_path(dir, job_name, "metrics");
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+
+    for metric in metrics {
+        writeln!(file, "{}", serde_json::to_string(metric)?)?;
+    }
+
+    Ok(())
+}
+
+pub fn read(dir: &str, job_name: &str) -> io::Result<Vec<Metric>> {
+    let file = match File::open(job_output::job_file_path(dir, job_name, "metrics")) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut metrics = Vec::new();
+    for line in BufReader::new(file).lines() {
+        metrics.push(serde_json::from_str(&line?)?);
+    }
+
+    Ok(metrics)
+}
+
+// Output for `show job --metrics`
+
+// One row per metric with its last, min and max values
+pub fn print_summary(metrics: &[Metric], artifacts: &[Artifact]) {
+    let mut by_name: BTreeMap<&str, Vec<f64>> = BTreeMap::new();
+    for metric in metrics {
+        by_name.entry(&metric.name).or_insert_with(Vec::new).push(metric.value);
+    }
+
+    if by_name.is_empty() {
+        println!("No metrics reported.");
+    } else {
+        let mut table = Table::new();
+        table.add_row(row![b->"Metric", b->"Last", b->"Min", b->"Max", b->"Count"]);
+
+        for (name, values) in &by_name {
+            let min = values.iter().cloned().fold(std::f64::INFINITY, f64::min);
+            let max = values.iter().cloned().fold(std::f64::NEG_INFINITY, f64::max);
+            table.add_row(row![name, values[values.len() - 1], min, max, values.len()]);
+        }
+
+        table.printstd();
+    }
+
+    if !artifacts.is_empty() {
+        let mut table = Table::new();
+        table.add_row(row![b->"Artifact", b->"Size", b->"Path"]);
+
+        for artifact in artifacts {
+            table.add_row(row![artifact.name, artifact.size, artifact.path.clone().unwrap_or_default()]);
+        }
+
+        table.printstd();
+    }
+}
+
+pub fn print_csv(metrics: &[Metric]) {
+    println!("timestamp,step,name,value");
+
+    for metric in metrics {
+        let step = metric.step.map(|s| s.to_string()).unwrap_or_default();
+        println!("{},{},{},{}", metric.timestamp, step, csv_field(&metric.name), metric.value);
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains(',') || field.contains('"') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
@@ -0,0 +1,230 @@
+// Notifications when a job finishes
+// `new job --notify` takes any number of targets:
+//
+//   cmd:./on-done.sh              runs a shell command with the job in its environment
+//   webhook:https://example/hook  POSTs a JSON payload
+//   email:me@example.com          sends a plain text email through `smtp_server`
+//
+// The runner sends them once the command has exited, and as soon as an `--alert-on` pattern
+// matches, retrying each target a few times before giving up on it and noting the failure in the
+// job's error file.
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use subprocess::{Exec, ExitStatus, NullFile};
+
+use config::Config;
+use krephis;
+use log_line;
+use protocol::Alert;
+
+// Attempts per target, waiting twice as long after each failure
+const ATTEMPTS: u32 = 3;
+const FIRST_RETRY_SECS: u64 = 1;
+
+const SMTP_TIMEOUT_SECS: u64 = 30;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    Command(String),
+    Webhook(String),
+    Email(String),
+}
+
+impl Target {
+    pub fn parse(spec: &str) -> Option<Target> {
+        let mut parts = spec.splitn(2, ':');
+        let kind = parts.next()?;
+        let value = parts.next().map(|v| v.trim()).filter(|v| !v.is_empty())?;
+
+        match kind {
+            "cmd" => Some(Target::Command(value.to_string())),
+            "webhook" if value.starts_with("http://") || value.starts_with("https://") => Some(Target::Webhook(value.to_string())),
+            "email" if value.contains('@') => Some(Target::Email(value.to_string())),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Target::Command(ref command) => write!(f, "cmd:{}", command),
+            Target::Webhook(ref url) => write!(f, "webhook:{}", url),
+            Target::Email(ref address) => write!(f, "email:{}", address),
+        }
+    }
+}
+
+// The webhook payload, and what the other targets are told
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Notification {
+    pub job_name: String,
+    pub machine: String,
+    // "done", "failed" or "interrupted", as in the local job record, or "alert" while the job is still running
+    pub status: String,
+    pub exit_code: Option<i64>,
+    pub finished_at: String,
+    // The line that set off the alert, or that `--fail-on` stopped the job for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alert: Option<Alert>,
+}
+
+impl Notification {
+    pub fn new(job_name: &str, machine: &str, status: &str, exit_code: Option<i64>, alert: Option<Alert>) -> Notification {
+        Notification {
+            job_name: job_name.to_string(),
+            machine: machine.to_string(),
+            status: status.to_string(),
+            exit_code: exit_code,
+            finished_at: log_line::format_timestamp(&Utc::now()),
+            alert: alert,
+        }
+    }
+
+    fn summary(&self) -> String {
+        match (self.status.as_str(), &self.alert, self.exit_code) {
+            ("alert", Some(alert), _) => format!("Job {} on {} printed a line matching {}", self.job_name, self.machine, alert.pattern),
+            (_, Some(alert), _) => format!("Job {} on {} {} (stopped for matching {})", self.job_name, self.machine, self.status, alert.pattern),
+            (_, None, Some(code)) => format!("Job {} on {} {} (exit code {})", self.job_name, self.machine, self.status, code),
+            (_, None, None) => format!("Job {} on {} {} (killed by a signal)", self.job_name, self.machine, self.status),
+        }
+    }
+}
+
+/// Sends to every target, returning the errors of those that never succeeded
+pub fn send_all(targets: &[Target], notification: &Notification, config: &Config) -> Vec<String> {
+    targets.iter()
+        .filter_map(|target| send(target, notification, config).err())
+        .collect()
+}
+
+pub fn send(target: &Target, notification: &Notification, config: &Config) -> Result<(), String> {
+    let mut wait = FIRST_RETRY_SECS;
+    let mut attempt = 1;
+
+    loop {
+        let result = match *target {
+            Target::Command(ref command) => run_command(command, notification),
+            Target::Webhook(ref url) => post_webhook(url, notification),
+            Target::Email(ref address) => send_email(address, notification, config),
+        };
+
+        match result {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt >= ATTEMPTS => return Err(format!("{}: {}", target, e)),
+            Err(_) => {
+                thread::sleep(Duration::from_secs(wait));
+                wait *= 2;
+                attempt += 1;
+            },
+        }
+    }
+}
+
+fn run_command(command: &str, notification: &Notification) -> Result<(), String> {
+    let exit_code = notification.exit_code.map(|c| c.to_string()).unwrap_or_default();
+    let (pattern, line) = match notification.alert {
+        Some(ref alert) => (alert.pattern.as_str(), alert.line.as_str()),
+        None => ("", ""),
+    };
+
+    let status = Exec::shell(command)
+        .env("KRAKEN_JOB_NAME", &notification.job_name)
+        .env("KRAKEN_JOB_STATUS", &notification.status)
+        .env("KRAKEN_EXIT_CODE", &exit_code)
+        .env("KRAKEN_MACHINE", &notification.machine)
+        .env("KRAKEN_ALERT_PATTERN", pattern)
+        .env("KRAKEN_ALERT_LINE", line)
+        .stdin(NullFile)
+        .join()
+        .map_err(|e| e.to_string())?;
+
+    match status {
+        ExitStatus::Exited(0) => Ok(()),
+        status => Err(format!("exited with {:?}", status)),
+    }
+}
+
+fn post_webhook(url: &str, notification: &Notification) -> Result<(), String> {
+    let body = serde_json::to_string(notification).map_err(|e| e.to_string())?;
+    krephis::post_webhook(url, body).map_err(|e| e.to_string())
+}
+
+// Plain SMTP without authentication, for a local relay like postfix or an internal server
+fn send_email(address: &str, notification: &Notification, config: &Config) -> Result<(), String> {
+    smtp_send(&config.smtp_server, &config.smtp_from, address, notification).map_err(|e| e.to_string())
+}
+
+fn smtp_send(server: &str, from: &str, to: &str, notification: &Notification) -> io::Result<()> {
+    let addr = server.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("couldn't resolve {}", server))
+    })?;
+
+    let timeout = Duration::from_secs(SMTP_TIMEOUT_SECS);
+    let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    expect_reply(&mut reader, &[220])?;
+    smtp_command(&mut stream, &mut reader, &format!("HELO {}", notification.machine), &[250])?;
+    smtp_command(&mut stream, &mut reader, &format!("MAIL FROM:<{}>", from), &[250])?;
+    smtp_command(&mut stream, &mut reader, &format!("RCPT TO:<{}>", to), &[250, 251])?;
+    smtp_command(&mut stream, &mut reader, "DATA", &[354])?;
+
+    let subject = notification.summary();
+    let mut body = format!("{}.\r\n\r\n", subject);
+    if let Some(ref alert) = notification.alert {
+        body.push_str(&format!("{}\r\n\r\n", alert.line));
+    }
+    body.push_str(&format!(
+        "At {}.\r\nRun `kraken show job {}` for its output.\r\n",
+        notification.finished_at, notification.job_name
+    ));
+
+    write!(stream, "From: <{}>\r\nTo: <{}>\r\nSubject: {}\r\n\r\n", from, to, subject)?;
+    for line in body.lines() {
+        // Lines starting with a dot would otherwise end the message early
+        if line.starts_with('.') {
+            write!(stream, ".")?;
+        }
+        write!(stream, "{}\r\n", line)?;
+    }
+
+    smtp_command(&mut stream, &mut reader, ".", &[250])?;
+    smtp_command(&mut stream, &mut reader, "QUIT", &[221])?;
+
+    Ok(())
+}
+
+fn smtp_command<R: BufRead>(stream: &mut TcpStream, reader: &mut R, command: &str, expected: &[u16]) -> io::Result<()> {
+    write!(stream, "{}\r\n", command)?;
+    expect_reply(reader, expected)
+}
+
+// Replies can span lines like `250-first` ... `250 last`
+fn expect_reply<R: BufRead>(reader: &mut R, expected: &[u16]) -> io::Result<()> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "SMTP server closed the connection"));
+        }
+
+        let code = line.get(..3).and_then(|c| c.parse::<u16>().ok());
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+
+        return match code {
+            Some(code) if expected.contains(&code) => Ok(()),
+            _ => Err(io::Error::new(io::ErrorKind::Other, format!("SMTP server answered {}", line.trim_end()))),
+        };
+    }
+}
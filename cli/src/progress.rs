@@ -0,0 +1,178 @@
+// Progress reports from jobs
+// A job reports progress by printing a line like
+//
+//   ##kraken progress 42/100 "epoch 3"
+//
+// The total and message are optional, and `42%` is short for `42/100`. The runner takes these
+// lines out of the job's output, works out an ETA, and keeps the latest report in the local job
+// record and on the server, where `show job` and `tail` draw it as a progress bar.
+
+use std::time::Instant;
+
+use chrono::prelude::*;
+use indicatif::{ProgressBar, ProgressStyle};
+use regex::Regex;
+
+use kraken_utils;
+use log_line;
+use protocol::JobProgress;
+
+lazy_static! {
+    static ref PROGRESS_LINE: Regex = Regex::new(r#"^##kraken progress (\d+)(?:/(\d+)|(%))?(?:\s+(.*))?$"#).unwrap();
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub current: u64,
+    pub total: Option<u64>,
+    pub message: Option<String>,
+}
+
+pub fn parse(text: &str) -> Option<Report> {
+    let captures = PROGRESS_LINE.captures(text.trim_end())?;
+
+    let current = captures.get(1)?.as_str().parse().ok()?;
+    let total = match (captures.get(2), captures.get(3)) {
+        (Some(total), _) => Some(total.as_str().parse().ok()?),
+        (None, Some(_)) => Some(100),
+        (None, None) => None,
+    };
+
+    let message = captures.get(4)
+        .map(|m| m.as_str().trim().trim_matches('"').to_string())
+        .filter(|m| !m.is_empty());
+
+    Some(Report { current: current, total: total, message: message })
+}
+
+// Turns reports into progress with an ETA, from the average rate since the first report
+pub struct Tracker {
+    first: Option<(Instant, u64)>,
+    last: Option<JobProgress>,
+}
+
+impl Tracker {
+    pub fn new() -> Tracker {
+        Tracker { first: None, last: None }
+    }
+
+    pub fn update(&mut self, report: Report) -> JobProgress {
+        let now = Instant::now();
+
+        // Counting went backwards, so start measuring the rate over again
+        let restarted = self.first.map_or(true, |(_, first)| report.current < first);
+        if restarted {
+            self.first = Some((now, report.current));
+        }
+
+        let eta_secs = match (self.first, report.total) {
+            (Some((started, first)), Some(total)) if report.current > first && total >= report.current => {
+                let elapsed = now.duration_since(started);
+                let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+                let rate = (report.current - first) as f64 / elapsed;
+                Some(((total - report.current) as f64 / rate).round() as u64)
+            },
+            _ => None,
+        };
+
+        let progress = JobProgress {
+            current: report.current,
+            total: report.total,
+            message: report.message,
+            updated_at: log_line::format_timestamp(&Utc::now()),
+            eta_secs: eta_secs,
+        };
+
+        self.last = Some(progress.clone());
+        progress
+    }
+
+    /// The last report without an ETA, for when the job has exited
+    pub fn finish(&mut self) -> Option<JobProgress> {
+        let mut progress = self.last.take()?;
+        progress.eta_secs = None;
+        Some(progress)
+    }
+}
+
+// Seconds left now, counting down from the ETA given when the job last reported. The runner
+// drops the ETA once the job exits.
+pub fn remaining_secs(progress: &JobProgress) -> Option<i64> {
+    let eta = progress.eta_secs? as i64;
+    let updated_at = log_line::parse_timestamp(&progress.updated_at)?;
+    Some((eta - (Utc::now() - updated_at).num_seconds()).max(0))
+}
+
+// Like `42/100 (42%) epoch 3, 1m30s left`
+pub fn summary(progress: &JobProgress) -> String {
+    let mut summary = match progress.total {
+        Some(total) if total > 0 => format!("{}/{} ({}%)", progress.current, total, progress.current.min(total) * 100 / total),
+        Some(total) => format!("{}/{}", progress.current, total),
+        None => progress.current.to_string(),
+    };
+
+    if let Some(ref message) = progress.message {
+        summary.push(' ');
+        summary.push_str(message);
+    }
+
+    if let Some(secs) = remaining_secs(progress) {
+        summary.push_str(&format!(", {} left", kraken_utils::format_duration(secs)));
+    }
+
+    summary
+}
+
+// Draws progress on the terminal, redrawing it below any lines printed in between. When stderr
+// isn't a terminal it prints a plain `Progress:` line instead, once per report.
+pub struct ProgressView {
+    bar: Option<ProgressBar>,
+    // `updated_at` of the last report printed without a terminal
+    last: Option<String>,
+}
+
+impl ProgressView {
+    pub fn new() -> ProgressView {
+        ProgressView { bar: None, last: None }
+    }
+
+    pub fn show(&mut self, progress: &JobProgress) {
+        let summary = summary(progress);
+
+        if unsafe { libc::isatty(libc::STDERR_FILENO) } == 0 {
+            if self.last.as_ref() != Some(&progress.updated_at) {
+                println!("Progress: {}", summary);
+            }
+            self.last = Some(progress.updated_at.clone());
+            return;
+        }
+
+        self.clear();
+
+        let bar = match progress.total {
+            Some(total) => {
+                let bar = ProgressBar::new(total);
+                bar.set_style(ProgressStyle::default_bar().template("[{bar:40}] {msg}").progress_chars("=> "));
+                bar
+            },
+            None => {
+                let bar = ProgressBar::new_spinner();
+                bar.set_style(ProgressStyle::default_spinner().template("{msg}"));
+                bar
+            },
+        };
+
+        bar.set_position(progress.current);
+        bar.set_message(&summary);
+        bar.finish_at_current_pos();
+
+        self.bar = Some(bar);
+    }
+
+    // Takes the bar off the screen so more lines can be printed above where it was
+    pub fn clear(&mut self) {
+        if let Some(bar) = self.bar.take() {
+            bar.finish_and_clear();
+        }
+    }
+}
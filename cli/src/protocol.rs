@@ -0,0 +1,282 @@
+// Wire types for the Kraken server API, as described in `api/openapi.yaml`
+// Shared by the CLI and kraken-server, so this file must only depend on serde.
+// Field names are snake_case in Rust and camelCase on the wire (`jobName`, `jobId`).
+
+use std::collections::BTreeMap;
+
+// Bump the major version for changes old clients or servers can't understand
+pub static API_VERSION: &str = "1.0";
+pub static API_VERSION_HEADER: &str = "x-kraken-api-version";
+
+/// Whether a peer speaking `version` understands us. Peers that don't send a version predate
+/// versioning and speak 1.x.
+pub fn compatible(version: Option<&str>) -> bool {
+    let major = |v: &str| v.split('.').next().unwrap_or("").to_string();
+    major(version.unwrap_or("1")) == major(API_VERSION)
+}
+
+// Longest job name in bytes, so the files named after a job stay under the usual 255 byte limit
+pub const MAX_JOB_NAME_BYTES: usize = 200;
+
+/// Why `name` can't be a job name, if it can't. Jobs' local files are named after them, so
+/// names can't contain `/`, and they can't start with `-` so they're never taken for a flag.
+pub fn check_job_name(name: &str) -> Result<(), String> {
+    let problem = if name.is_empty() {
+        "it's empty".to_string()
+    } else if name.len() > MAX_JOB_NAME_BYTES {
+        format!("it's longer than {} bytes", MAX_JOB_NAME_BYTES)
+    } else if name.contains('/') {
+        "it contains /".to_string()
+    } else if name.chars().any(|c| c.is_control()) {
+        "it contains control characters".to_string()
+    } else if name.starts_with('-') {
+        "it starts with -".to_string()
+    } else {
+        return Ok(());
+    };
+
+    Err(format!("Invalid job name `{}`: {}", name, problem))
+}
+
+/// Percent-encodes a job or artifact name for one segment of a URL path
+pub fn encode_path_segment(segment: &str) -> String {
+    segment.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+        _ => format!("%{:02X}", b),
+    }).collect()
+}
+
+/// Reverses `encode_path_segment`. Anything that isn't a valid escape is kept as it is.
+pub fn decode_path_segment(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escape = if bytes[i] == b'%' && i + 2 < bytes.len() && bytes[i + 1].is_ascii_hexdigit() && bytes[i + 2].is_ascii_hexdigit() {
+            u8::from_str_radix(&segment[i + 1..i + 3], 16).ok()
+        } else {
+            None
+        };
+
+        match escape {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            },
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// POST /auth/login
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LoginRequest {
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TokenResponse {
+    pub auth: bool,
+    pub token: String,
+}
+
+// POST /jobs/new
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NewJobRequest {
+    pub machine: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    // Shared by the jobs one `new job --hosts` started
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spec: Option<JobSpec>,
+    // From `new job --tag NAME=VALUE`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+// GET /jobs/{name}/spec
+// Everything `new job` needs to start the job again, for `rerun job` and `new job --from`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct JobSpec {
+    pub command: String,
+    // KEY=VALUE pairs from `--env`, set on top of the environment kraken ran in. The copy on the
+    // server only has the names, and a job started from it takes the values from its environment.
+    #[serde(default)]
+    pub env: Vec<String>,
+    pub workdir: String,
+    #[serde(default)]
+    pub description: String,
+    // The job's other `new job` options, as command line arguments
+    #[serde(default)]
+    pub options: Vec<String>,
+    // The words after `--`: the program and its arguments, or the arguments to `--script`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub argv: Vec<String>,
+    // The contents of the `--script` file, with `command` its file name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script: Option<String>,
+    // The program and arguments kraken ran
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub invocation: Vec<String>,
+}
+
+// GET /jobs
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Job {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub machine: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress: Option<JobProgress>,
+    // Set once the job's output matches one of its `--alert-on` or `--fail-on` patterns
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    // Known once the job has exited, unless a signal killed it
+    #[serde(default, rename = "exitCode", skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i64>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+// POST /jobs/{name}/exit, sent once the job has exited
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct JobExit {
+    // "done", "failed" or "interrupted"
+    pub status: String,
+    pub exit_code: Option<i64>,
+}
+
+// POST and GET /jobs/{name}/progress
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct JobProgress {
+    pub current: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    // RFC 3339, when the job reported it
+    pub updated_at: String,
+    // Estimated seconds left as of `updated_at`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub eta_secs: Option<u64>,
+}
+
+// POST and GET /jobs/{name}/metrics
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Metric {
+    pub name: String,
+    pub value: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step: Option<u64>,
+    // RFC 3339, when the job reported it
+    pub timestamp: String,
+}
+
+// GET /jobs/{name}/artifacts. Files are uploaded to and downloaded from
+// /jobs/{name}/artifacts/{artifact}.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Artifact {
+    pub name: String,
+    pub size: u64,
+    // Where the file is on the machine that ran the job. Only known locally.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+// POST and GET /jobs/{name}/resources
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceSummary {
+    pub samples: u64,
+    pub duration_secs: u64,
+    // 100 is one core kept busy
+    pub peak_cpu_percent: f64,
+    pub avg_cpu_percent: f64,
+    pub peak_rss_bytes: u64,
+    pub avg_rss_bytes: u64,
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+    pub peak_processes: u32,
+}
+
+// POST and GET /jobs/{name}/alerts
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Alert {
+    pub pattern: String,
+    // The output line that matched
+    pub line: String,
+    // "alert" for `--alert-on`, "fail" for `--fail-on`, which also terminates the job
+    pub action: String,
+    // RFC 3339
+    pub timestamp: String,
+}
+
+// POST /logs/new
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct NewLogRequest {
+    pub job_name: String,
+    pub line: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<String>,
+}
+
+// GET /jobs/{name}
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Log {
+    pub job_id: String,
+    pub line: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<String>,
+}
+
+// GET and POST /logs/stream/{name}
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StreamAck {
+    pub acked: u64,
+}
+
+// DELETE /jobs/{name} and POST /jobs/remove-all
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RemovedResponse {
+    pub removed: usize,
+}
+
+// Any non-2xx response
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ErrorResponse {
+    #[serde(default)]
+    pub auth: Option<bool>,
+    pub message: String,
+}
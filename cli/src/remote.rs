@@ -0,0 +1,244 @@
+// Starting jobs on other machines
+// `new job --host user@server` runs `kraken new job` on the server over ssh, so a job can be
+// started from a laptop and followed with `show job` like any other. `--hosts` does the same on
+// several servers at once, putting the jobs in a group that `show group` reports on.
+//
+// Before launching it makes sure the server has kraken, copying this binary over when it's
+// missing and the server is the same kind of machine, and that it's logged in, by copying this
+// machine's token over when it has none. The ssh command comes from `ssh_command` in
+// `~/.kraken.json`, so it can carry its own options or be swapped for something else that runs a
+// command on a host.
+
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::thread;
+
+use clap::ArgMatches;
+use subprocess::{Exec, ExitStatus, NullFile, Redirection};
+
+use config::Config;
+use invocation::Script;
+use job_spec;
+use kraken_utils;
+
+// Where kraken is installed on servers that don't have it on their PATH
+const REMOTE_INSTALL_PATH: &str = "~/.kraken/bin/kraken";
+
+// `new job` options passed through to the remote kraken besides the ones in the job's spec
+const VALUE_ARGS: &[(&str, &str)] = &[
+    ("DESCRIPTION", "--description"),
+    ("ENV", "--env"),
+    ("WORKDIR", "--workdir"),
+    ("GROUP", "--group"),
+];
+const FLAG_ARGS: &[(&str, &str)] = &[
+    ("REPLACE", "--replace"),
+    ("AUTO_SUFFIX", "--auto-suffix"),
+];
+
+/// The `new job` options and command to run the job with on another machine, besides `--script`,
+/// which `launch` copies over
+pub fn forwarded_args(command: &ArgMatches) -> Vec<String> {
+    let mut args = Vec::new();
+
+    for &(name, flag) in VALUE_ARGS {
+        for value in command.values_of(name).into_iter().flatten() {
+            args.push(flag.to_string());
+            args.push(value.to_string());
+        }
+    }
+
+    for &(name, flag) in FLAG_ARGS {
+        if command.is_present(name) {
+            args.push(flag.to_string());
+        }
+    }
+
+    args.extend(job_spec::option_args(command));
+
+    if let Some(c) = command.value_of("COMMAND") {
+        args.push(c.to_string());
+    }
+
+    if let Some(argv) = command.values_of("ARGV") {
+        args.push("--".to_string());
+        args.extend(argv.map(|a| a.to_string()));
+    }
+
+    args
+}
+
+/// Starts the job on `host` and returns what the remote kraken printed
+pub fn launch(host: &str, job_name: &str, forwarded_args: &[String], script: Option<&Script>, input: Option<&[u8]>, config: &Config) -> Result<String, String> {
+    let kraken = ensure_installed(host, config)?;
+    ensure_logged_in(host, config)?;
+
+    let mut args = vec!["new".to_string(), "job".to_string(), "--name".to_string(), job_name.to_string()];
+    if let Some(script) = script {
+        args.push("--script".to_string());
+        args.push(upload_script(host, job_name, script, config)?);
+    }
+
+    // The remote kraken spools the job's input the same way a local one does
+    if input.is_some() {
+        args.push("--stdin-file".to_string());
+        args.push("-".to_string());
+    }
+    args.extend(forwarded_args.iter().cloned());
+
+    // The job reports to the same server as this machine does
+    let remote_command = format!(
+        "KRAKEN_SERVER_URL={} {} {}",
+        kraken_utils::shell_quote(&config.server_url),
+        kraken,
+        args.iter().map(|a| kraken_utils::shell_quote(a)).collect::<Vec<_>>().join(" ")
+    );
+
+    run(host, &remote_command, input, config)
+}
+
+/// Starts a job on every host at once, named after the host and put in `group`. Returns each
+/// host's job name and whether it started, in the order of `hosts`.
+pub fn launch_group(hosts: &[String], group: &str, forwarded_args: &[String], script: Option<&Script>, input: Option<&[u8]>, config: &Config) -> Vec<(String, Result<(), String>)> {
+    let mut args = vec!["--group".to_string(), group.to_string()];
+    args.extend(forwarded_args.iter().cloned());
+
+    let launches: Vec<_> = hosts.iter().map(|host| {
+        let host = host.clone();
+        let job_name = format!("{}-{}", host_name(&host), group);
+        let args = args.clone();
+        let script = script.cloned();
+        let input = input.map(|i| i.to_vec());
+        let config = config.clone();
+
+        thread::spawn(move || {
+            let result = launch(&host, &job_name, &args, script.as_ref(), input.as_ref().map(|i| i.as_slice()), &config).map(|_| ());
+            (job_name, result)
+        })
+    }).collect();
+
+    launches.into_iter()
+        .map(|t| t.join().unwrap_or(("?".to_string(), Err("the launch thread panicked".to_string()))))
+        .collect()
+}
+
+/// Attaches this terminal to a job running with `--tty` on `host`
+pub fn attach(host: &str, job_name: &str, config: &Config) -> Result<(), String> {
+    let mut words = config.ssh_command.split_whitespace();
+    let program = words.next().ok_or("ssh_command in ~/.kraken.json is empty".to_string())?;
+
+    let remote_command = format!(
+        "$(command -v kraken || echo {}) attach job {}",
+        REMOTE_INSTALL_PATH,
+        kraken_utils::shell_quote(job_name)
+    );
+
+    // `-t` gives the remote kraken a terminal, so keys reach the job as they're typed
+    Exec::cmd(program).args(&words.collect::<Vec<_>>()).arg("-t").arg(host).arg(&remote_command)
+        .join()
+        .map(|_| ())
+        .map_err(|e| format!("Couldn't run {}: {}", program, e))
+}
+
+// `server` for `user@server`, for naming jobs after where they run
+pub fn host_name(host: &str) -> &str {
+    host.rsplit('@').next().unwrap_or(host)
+}
+
+// One host per line, skipping blank lines and `#` comments
+pub fn read_hosts_file(path: &str) -> Result<Vec<String>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+
+    Ok(contents.lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| l.to_string())
+        .collect())
+}
+
+// The remote kraken to run, installing this one if the server has none
+fn ensure_installed(host: &str, config: &Config) -> Result<String, String> {
+    let found = run(host, &format!("command -v kraken || (test -x {0} && echo {0}) || true", REMOTE_INSTALL_PATH), None, config)?;
+    if let Some(path) = found.lines().map(|l| l.trim()).find(|l| !l.is_empty()) {
+        return Ok(path.to_string());
+    }
+
+    // A binary only runs on the same OS and architecture it was built for
+    let remote_platform = run(host, "uname -sm", None, config)?;
+    let local_platform = Exec::cmd("uname").arg("-sm").stdout(Redirection::Pipe).capture()
+        .map(|c| c.stdout_str())
+        .map_err(|e| format!("Couldn't run uname: {}", e))?;
+
+    if remote_platform.trim() != local_platform.trim() {
+        return Err(format!(
+            "kraken isn't installed on {} and this one is built for {}, not {}. Install kraken there first.",
+            host, local_platform.trim(), remote_platform.trim()
+        ));
+    }
+
+    println!("Installing kraken on {}", host);
+
+    let exe = env::current_exe().map_err(|e| format!("Couldn't find this kraken binary: {}", e))?;
+    let binary = fs::read(&exe).map_err(|e| format!("Couldn't read {}: {}", exe.display(), e))?;
+
+    // Hosts in a group can share a home directory, so each writes its own temporary file
+    run(host, &format!(
+        "mkdir -p $(dirname {0}) && cat > {0}.$$ && chmod +x {0}.$$ && mv {0}.$$ {0}",
+        REMOTE_INSTALL_PATH
+    ), Some(&binary), config)?;
+
+    Ok(REMOTE_INSTALL_PATH.to_string())
+}
+
+// Copies the script to the host and returns its path there
+fn upload_script(host: &str, job_name: &str, script: &Script, config: &Config) -> Result<String, String> {
+    let dir = run(host, &format!(
+        "mkdir -p ~/.kraken/scripts/{0} && cd ~/.kraken/scripts/{0} && cat > {1} && pwd",
+        kraken_utils::shell_quote(job_name),
+        kraken_utils::shell_quote(&script.file_name())
+    ), Some(script.contents.as_bytes()), config)?;
+
+    Ok(format!("{}/{}", dir.trim(), script.file_name()))
+}
+
+// Copies this machine's token over unless the server already has one
+fn ensure_logged_in(host: &str, config: &Config) -> Result<(), String> {
+    let token = match kraken_utils::retrieve_token() {
+        Ok(token) => token,
+        Err(_) => return Ok(()),
+    };
+
+    run(host, "test -s ~/.krakenrc || (umask 077 && cat > ~/.krakenrc)", Some(format!("{}\n", token).as_bytes()), config)?;
+    Ok(())
+}
+
+// Runs a shell command on the host and returns its stdout. Its stderr goes to ours.
+fn run(host: &str, remote_command: &str, input: Option<&[u8]>, config: &Config) -> Result<String, String> {
+    let mut words = config.ssh_command.split_whitespace();
+    let program = words.next().ok_or("ssh_command in ~/.kraken.json is empty".to_string())?;
+
+    let exec = Exec::cmd(program).args(&words.collect::<Vec<_>>()).arg(host).arg(remote_command).stdout(Redirection::Pipe);
+    let exec = match input {
+        Some(_) => exec.stdin(Redirection::Pipe),
+        None => exec.stdin(NullFile),
+    };
+
+    let mut process = exec.popen().map_err(|e| format!("Couldn't run {}: {}", program, e))?;
+
+    if let Some(input) = input {
+        let mut stdin = process.stdin.take().unwrap();
+        stdin.write_all(input).map_err(|e| format!("Couldn't send to {}: {}", host, e))?;
+    }
+
+    let mut output = String::new();
+    if let Some(mut stdout) = process.stdout.take() {
+        let _ = stdout.read_to_string(&mut output);
+    }
+
+    match process.wait() {
+        Ok(ExitStatus::Exited(0)) => Ok(output),
+        Ok(status) => Err(format!("`{}` on {} failed: {:?}", remote_command, host, status)),
+        Err(e) => Err(format!("Couldn't run {}: {}", program, e)),
+    }
+}
@@ -0,0 +1,248 @@
+// Resource usage of running jobs
+// The job daemon samples the job's whole process tree from `/proc` every few seconds: CPU use
+// since the last sample, resident memory, bytes read from and written to storage, and how many
+// processes there are. Samples are appended to `kraken-job-<name>.resources`, and a summary of
+// peaks and averages goes in the job record and to the server once the job exits.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::time::Instant;
+
+use chrono::prelude::*;
+use prettytable::Table;
+
+use job_output;
+use kraken_utils;
+use log_line;
+use protocol::ResourceSummary;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub timestamp: String,
+    // 100 is one core kept busy
+    pub cpu_percent: f64,
+    pub rss_bytes: u64,
+    // Totals since the job started
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+    pub processes: u32,
+}
+
+// Counters for one process
+#[derive(Default)]
+struct ProcessStat {
+    parent: u32,
+    // Exited but not yet waited for
+    zombie: bool,
+    // Including children it has waited for, so work done by short-lived children isn't lost
+    cpu_ticks: u64,
+    rss_pages: u64,
+}
+
+pub struct Sampler {
+    root: u32,
+    last: Option<(Instant, u64)>,
+    ticks_per_sec: f64,
+    page_size: u64,
+    // Highest I/O totals seen, since exited processes drop out of the tree
+    read_bytes: u64,
+    write_bytes: u64,
+}
+
+impl Sampler {
+    pub fn new(root: u32) -> Sampler {
+        let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+
+        Sampler {
+            root: root,
+            last: None,
+            ticks_per_sec: if ticks_per_sec > 0 { ticks_per_sec as f64 } else { 100.0 },
+            page_size: if page_size > 0 { page_size as u64 } else { 4096 },
+            read_bytes: 0,
+            write_bytes: 0,
+        }
+    }
+
+    /// Samples the process tree, or returns None once the root process has exited
+    pub fn sample(&mut self) -> Option<Sample> {
+        let stats = read_stats();
+        if stats.get(&self.root)?.zombie {
+            return None;
+        }
+
+        let tree = descendants(self.root, &stats);
+        let now = Instant::now();
+
+        let cpu_ticks: u64 = tree.iter().filter_map(|pid| stats.get(pid)).map(|s| s.cpu_ticks).sum();
+        let rss_pages: u64 = tree.iter().filter_map(|pid| stats.get(pid)).map(|s| s.rss_pages).sum();
+
+        let cpu_percent = match self.last {
+            Some((at, ticks)) => {
+                let elapsed = now.duration_since(at);
+                let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+                if elapsed > 0.0 {
+                    cpu_ticks.saturating_sub(ticks) as f64 / self.ticks_per_sec / elapsed * 100.0
+                } else {
+                    0.0
+                }
+            },
+            None => 0.0,
+        };
+        self.last = Some((now, cpu_ticks));
+
+        let (read_bytes, write_bytes) = tree.iter()
+            .filter_map(|pid| read_io(*pid))
+            .fold((0, 0), |(r, w), (pr, pw)| (r + pr, w + pw));
+        self.read_bytes = self.read_bytes.max(read_bytes);
+        self.write_bytes = self.write_bytes.max(write_bytes);
+
+        Some(Sample {
+            timestamp: log_line::format_timestamp(&Utc::now()),
+            cpu_percent: (cpu_percent * 10.0).round() / 10.0,
+            rss_bytes: rss_pages * self.page_size,
+            read_bytes: self.read_bytes,
+            write_bytes: self.write_bytes,
+            processes: tree.len() as u32,
+        })
+    }
+}
+
+fn read_stats() -> HashMap<u32, ProcessStat> {
+    let mut stats = HashMap::new();
+
+    let entries = match fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return stats,
+    };
+
+    for entry in entries.filter_map(|e| e.ok()) {
+        let pid = match entry.file_name().to_str().and_then(|n| n.parse::<u32>().ok()) {
+            Some(pid) => pid,
+            None => continue,
+        };
+
+        if let Some(stat) = fs::read_to_string(format!("/proc/{}/stat", pid)).ok().and_then(|s| parse_stat(&s)) {
+            stats.insert(pid, stat);
+        }
+    }
+
+    stats
+}
+
+// The command name is in parentheses and can contain spaces, so fields are counted after it
+fn parse_stat(stat: &str) -> Option<ProcessStat> {
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let field = |n: usize| fields.get(n).and_then(|f| f.parse::<u64>().ok());
+
+    // Fields from `state` on, see proc(5): ppid is 4th, utime..cstime 14th-17th, rss 24th
+    Some(ProcessStat {
+        parent: field(1)? as u32,
+        zombie: fields.first() == Some(&"Z"),
+        cpu_ticks: field(11)? + field(12)? + field(13)? + field(14)?,
+        rss_pages: field(21)?,
+    })
+}
+
+/// The process and everything it started that's still running
+pub fn process_tree(root: u32) -> Vec<u32> {
+    let stats = read_stats();
+    match stats.get(&root) {
+        Some(stat) if !stat.zombie => descendants(root, &stats),
+        _ => Vec::new(),
+    }
+}
+
+fn descendants(root: u32, stats: &HashMap<u32, ProcessStat>) -> Vec<u32> {
+    let mut tree = vec![root];
+    let mut i = 0;
+
+    while i < tree.len() {
+        let parent = tree[i];
+        tree.extend(stats.iter().filter(|&(_, s)| s.parent == parent && !s.zombie).map(|(pid, _)| *pid));
+        i += 1;
+    }
+
+    tree
+}
+
+// Only readable for our own processes, which the job's always are
+fn read_io(pid: u32) -> Option<(u64, u64)> {
+    let io = fs::read_to_string(format!("/proc/{}/io", pid)).ok()?;
+    let value = |key: &str| {
+        io.lines()
+            .find(|l| l.starts_with(key))
+            .and_then(|l| l[key.len()..].trim().parse::<u64>().ok())
+            .unwrap_or(0)
+    };
+
+    Some((value("read_bytes:"), value("write_bytes:")))
+}
+
+pub fn summarize(samples: &[Sample]) -> Option<ResourceSummary> {
+    let first = samples.first()?;
+    let last = samples.last()?;
+
+    let duration = match (log_line::parse_timestamp(&first.timestamp), log_line::parse_timestamp(&last.timestamp)) {
+        (Some(first), Some(last)) => (last - first).num_seconds().max(0) as u64,
+        _ => 0,
+    };
+
+    // The first sample has nothing to measure CPU against
+    let cpu: Vec<f64> = samples.iter().skip(1).map(|s| s.cpu_percent).collect();
+    let count = samples.len() as u64;
+
+    Some(ResourceSummary {
+        samples: count,
+        duration_secs: duration,
+        peak_cpu_percent: cpu.iter().cloned().fold(0.0, f64::max),
+        avg_cpu_percent: if cpu.is_empty() { 0.0 } else { (cpu.iter().sum::<f64>() / cpu.len() as f64 * 10.0).round() / 10.0 },
+        peak_rss_bytes: samples.iter().map(|s| s.rss_bytes).max().unwrap_or(0),
+        avg_rss_bytes: samples.iter().map(|s| s.rss_bytes).sum::<u64>() / count,
+        read_bytes: last.read_bytes,
+        write_bytes: last.write_bytes,
+        peak_processes: samples.iter().map(|s| s.processes).max().unwrap_or(0),
+    })
+}
+
+// Local samples files
+
+pub fn append(dir: &str, job_name: &str, sample: &Sample) -> io::Result<()> {
+    let path = job_output::job_file_path(dir, job_name, "resources");
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(sample)?)
+}
+
+pub fn read(dir: &str, job_name: &str) -> io::Result<Vec<Sample>> {
+    let file = match File::open(job_output::job_file_path(dir, job_name, "resources")) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut samples = Vec::new();
+    for line in BufReader::new(file).lines() {
+        samples.push(serde_json::from_str(&line?)?);
+    }
+
+    Ok(samples)
+}
+
+// Output for `show job --resources`
+pub fn print_summary(summary: &ResourceSummary) {
+    let mut table = Table::new();
+    table.add_row(row![b->"Resource", b->"Peak", b->"Average"]);
+    table.add_row(row!["CPU", format!("{}%", summary.peak_cpu_percent), format!("{}%", summary.avg_cpu_percent)]);
+    table.add_row(row!["Memory", kraken_utils::format_bytes(summary.peak_rss_bytes), kraken_utils::format_bytes(summary.avg_rss_bytes)]);
+    table.add_row(row!["Processes", summary.peak_processes, ""]);
+    table.printstd();
+
+    println!(
+        "Read {}, wrote {} over {} ({} samples)",
+        kraken_utils::format_bytes(summary.read_bytes),
+        kraken_utils::format_bytes(summary.write_bytes),
+        kraken_utils::format_duration(summary.duration_secs as i64),
+        summary.samples
+    );
+}
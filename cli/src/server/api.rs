@@ -0,0 +1,501 @@
+// HTTP API for kraken-server
+// Implements `api/openapi.yaml`, the same endpoints the CLI calls in `krephis.rs`. Errors are
+// returned as an `ErrorResponse` with a matching status code.
+
+use std::fs;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+use futures::{future, Future, Stream};
+use serde::Serialize;
+use hyper::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use hyper::{Body, Method, Response, StatusCode};
+
+use protocol::{self, Alert, ErrorResponse, JobExit, JobProgress, LoginRequest, Metric, ResourceSummary, NewJobRequest, NewLogRequest, RemovedResponse, StreamAck, TokenResponse};
+use store::{Job, Log, RestoreError, Store, User};
+
+pub struct Settings {
+    // How long removed jobs can still be restored
+    pub undo_window_secs: i64,
+}
+
+pub type ResponseFuture = Box<dyn Future<Item = Response<Body>, Error = hyper::Error> + Send>;
+
+pub fn handle(store: &Arc<Mutex<Store>>, settings: &Settings, method: &Method, path: &str, headers: &HeaderMap, body: &[u8]) -> Response<Body> {
+    with_version(route(store, settings, method, path, headers, body))
+}
+
+// Requests whose body is handled as it arrives rather than read in full first
+pub fn is_stream(method: &Method, path: &str) -> bool {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    match (method.as_str(), segments.as_slice()) {
+        ("POST", ["logs", "stream", _]) => true,
+        _ => false,
+    }
+}
+
+// POST /logs/stream/{name}: stores newline-delimited lines as they arrive, then answers with
+// the last stored seq once the client ends the body
+pub fn handle_stream(store: &Arc<Mutex<Store>>, path: &str, headers: &HeaderMap, body: Body) -> ResponseFuture {
+
+    let job = match open_stream(store, path, headers) {
+        Ok(job) => job,
+        Err(res) => return Box::new(future::ok(with_version(res))),
+    };
+
+    let chunk_store = store.clone();
+    let chunk_job = job.clone();
+    let store = store.clone();
+
+    Box::new(body.fold(Vec::new(), move |mut pending, chunk| {
+        pending.extend_from_slice(&chunk);
+
+        while let Some(end) = pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = pending.drain(..end + 1).collect();
+            store_streamed_line(&chunk_store, &chunk_job, &line);
+        }
+
+        Ok::<_, hyper::Error>(pending)
+    }).map(move |rest| {
+        store_streamed_line(&store, &job, &rest);
+        with_version(stream_ack(&store, &job))
+    }))
+}
+
+fn with_version(mut res: Response<Body>) -> Response<Body> {
+    res.headers_mut().insert(protocol::API_VERSION_HEADER, HeaderValue::from_static(protocol::API_VERSION));
+    res
+}
+
+fn check_version(headers: &HeaderMap) -> Result<(), Response<Body>> {
+    let client_version = headers.get(protocol::API_VERSION_HEADER).and_then(|v| v.to_str().ok());
+
+    if !protocol::compatible(client_version) {
+        let message = format!("Unsupported API version {}, this server speaks {}", client_version.unwrap_or("?"), protocol::API_VERSION);
+        return Err(error(StatusCode::BAD_REQUEST, None, &message));
+    }
+
+    Ok(())
+}
+
+fn route(store: &Arc<Mutex<Store>>, settings: &Settings, method: &Method, path: &str, headers: &HeaderMap, body: &[u8]) -> Response<Body> {
+
+    if let Err(res) = check_version(headers) {
+        return res;
+    }
+
+    // Names are percent-encoded, so a name can hold anything, even a `/`
+    let decoded: Vec<String> = path.trim_matches('/').split('/').map(protocol::decode_path_segment).collect();
+    let segments: Vec<&str> = decoded.iter().map(|s| s.as_str()).collect();
+
+    if let ("POST", ["auth", "login"]) = (method.as_str(), segments.as_slice()) {
+        return login(store, body);
+    }
+
+    let user = match authenticate(store, headers) {
+        Some(user) => user,
+        None => return error(StatusCode::UNAUTHORIZED, Some(false), "Missing or invalid x-access-token"),
+    };
+
+    match (method.as_str(), segments.as_slice()) {
+        ("GET", ["jobs"]) => list_jobs(store, &user),
+        ("POST", ["jobs", "new"]) => new_job(store, &user, body),
+        ("POST", ["jobs", "remove-all"]) => remove_all_jobs(store, &user),
+        ("GET", ["jobs", name]) => show_job(store, &user, name, headers),
+        ("DELETE", ["jobs", name]) => remove_job(store, &user, name),
+        ("POST", ["jobs", name, "restore"]) => restore_job(store, settings, &user, name),
+        ("POST", ["jobs", name, "exit"]) => finish_job(store, &user, name, body),
+        ("GET", ["jobs", name, "progress"]) => match find_job(store, &user, name) {
+            Ok(job) => json(StatusCode::OK, &job.progress),
+            Err(res) => res,
+        },
+        ("GET", ["jobs", name, "spec"]) => match find_job(store, &user, name) {
+            Ok(job) => json(StatusCode::OK, &job.spec),
+            Err(res) => res,
+        },
+        ("POST", ["jobs", name, "progress"]) => set_progress(store, &user, name, body),
+        ("GET", ["jobs", name, "resources"]) => match find_job(store, &user, name) {
+            Ok(job) => json(StatusCode::OK, &job.resources),
+            Err(res) => res,
+        },
+        ("POST", ["jobs", name, "resources"]) => set_resources(store, &user, name, body),
+        ("GET", ["jobs", name, "alerts"]) => match find_job(store, &user, name) {
+            Ok(job) => json(StatusCode::OK, &job.alerts),
+            Err(res) => res,
+        },
+        ("POST", ["jobs", name, "alerts"]) => add_alert(store, &user, name, body),
+        ("GET", ["jobs", name, "metrics"]) => list_metrics(store, &user, name),
+        ("POST", ["jobs", name, "metrics"]) => add_metrics(store, &user, name, body),
+        ("GET", ["jobs", name, "artifacts"]) => list_artifacts(store, &user, name),
+        ("GET", ["jobs", name, "artifacts", artifact]) => download_artifact(store, &user, name, artifact),
+        ("POST", ["jobs", name, "artifacts", artifact]) => upload_artifact(store, &user, name, artifact, body),
+        ("POST", ["logs", "new"]) => new_log(store, &user, body),
+        ("GET", ["logs", "stream", name]) => match find_job(store, &user, name) {
+            Ok(job) => stream_ack(store, &job),
+            Err(res) => res,
+        },
+        _ => error(StatusCode::NOT_FOUND, None, "Not found"),
+    }
+}
+
+fn authenticate(store: &Arc<Mutex<Store>>, headers: &HeaderMap) -> Option<User> {
+    let token = headers.get("x-access-token")?.to_str().ok()?;
+    store.lock().unwrap().user_for_token(token)
+}
+
+fn login(store: &Arc<Mutex<Store>>, body: &[u8]) -> Response<Body> {
+    let request: LoginRequest = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(e) => return error(StatusCode::BAD_REQUEST, Some(false), &e.to_string()),
+    };
+
+    match store.lock().unwrap().login(&request.email, &request.password) {
+        Ok(Some(token)) => json(StatusCode::OK, &TokenResponse { auth: true, token: token }),
+        Ok(None) => error(StatusCode::UNAUTHORIZED, Some(false), "Wrong email or password"),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, None, &e.to_string()),
+    }
+}
+
+fn list_jobs(store: &Arc<Mutex<Store>>, user: &User) -> Response<Body> {
+    let jobs: Vec<protocol::Job> = store.lock().unwrap()
+        .jobs_for(&user.id)
+        .iter()
+        .map(wire_job)
+        .collect();
+
+    json(StatusCode::OK, &jobs)
+}
+
+fn new_job(store: &Arc<Mutex<Store>>, user: &User, body: &[u8]) -> Response<Body> {
+    let request: NewJobRequest = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(e) => return error(StatusCode::BAD_REQUEST, None, &e.to_string()),
+    };
+
+    if let Err(e) = protocol::check_job_name(&request.name) {
+        return error(StatusCode::BAD_REQUEST, None, &e);
+    }
+
+    let name = request.name.clone();
+
+    match store.lock().unwrap().create_job(&user.id, request) {
+        Ok(Some(job)) => json(StatusCode::OK, &wire_job(&job)),
+        Ok(None) => error(StatusCode::CONFLICT, None, &format!("A job named {} already exists", name)),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, None, &e.to_string()),
+    }
+}
+
+fn show_job(store: &Arc<Mutex<Store>>, user: &User, name: &str, headers: &HeaderMap) -> Response<Body> {
+    let store = store.lock().unwrap();
+
+    let job = match store.find_job(&user.id, name) {
+        Some(job) => job,
+        None => return error(StatusCode::NOT_FOUND, None, &format!("No job named {}", name)),
+    };
+
+    let header = |key: &str| headers.get(key).and_then(|v| v.to_str().ok());
+    let time = |key: &str| {
+        header(key)
+            .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+            .map(|t| t.with_timezone(&Utc))
+    };
+
+    let limit = header("x-line-limit").and_then(|l| l.parse().ok()).unwrap_or(10);
+
+    match store.logs(&job.id, limit, time("x-since"), time("x-until")) {
+        Ok(logs) => {
+            let logs: Vec<protocol::Log> = logs.into_iter()
+                .map(|log| protocol::Log {
+                    job_id: job.name.clone(),
+                    line: log.line,
+                    seq: log.seq,
+                    timestamp: log.timestamp,
+                    stream: log.stream,
+                })
+                .collect();
+
+            json(StatusCode::OK, &logs)
+        },
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, None, &e.to_string()),
+    }
+}
+
+fn remove_job(store: &Arc<Mutex<Store>>, user: &User, name: &str) -> Response<Body> {
+    match store.lock().unwrap().remove_job(&user.id, name) {
+        Ok(true) => json(StatusCode::OK, &RemovedResponse { removed: 1 }),
+        Ok(false) => error(StatusCode::NOT_FOUND, None, &format!("No job named {}", name)),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, None, &e.to_string()),
+    }
+}
+
+fn remove_all_jobs(store: &Arc<Mutex<Store>>, user: &User) -> Response<Body> {
+    match store.lock().unwrap().remove_all_jobs(&user.id) {
+        Ok(removed) => json(StatusCode::OK, &RemovedResponse { removed: removed }),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, None, &e.to_string()),
+    }
+}
+
+fn restore_job(store: &Arc<Mutex<Store>>, settings: &Settings, user: &User, name: &str) -> Response<Body> {
+    match store.lock().unwrap().restore_job(&user.id, name, settings.undo_window_secs) {
+        Ok(Ok(job)) => json(StatusCode::OK, &wire_job(&job)),
+        Ok(Err(RestoreError::NotFound)) => error(StatusCode::NOT_FOUND, None, &format!("No removed job named {}", name)),
+        Ok(Err(RestoreError::Expired)) => error(StatusCode::GONE, None, "The undo window has expired"),
+        Ok(Err(RestoreError::NameTaken)) => error(StatusCode::CONFLICT, None, &format!("A live job already uses the name {}", name)),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, None, &e.to_string()),
+    }
+}
+
+fn finish_job(store: &Arc<Mutex<Store>>, user: &User, name: &str, body: &[u8]) -> Response<Body> {
+    let exit: JobExit = match serde_json::from_slice(body) {
+        Ok(exit) => exit,
+        Err(e) => return error(StatusCode::BAD_REQUEST, None, &e.to_string()),
+    };
+
+    let job = match find_job(store, user, name) {
+        Ok(job) => job,
+        Err(res) => return res,
+    };
+
+    match store.lock().unwrap().finish_job(&job.id, &exit.status, exit.exit_code) {
+        Ok(()) => json(StatusCode::OK, &json!({ "ok": true })),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, None, &e.to_string()),
+    }
+}
+
+fn set_progress(store: &Arc<Mutex<Store>>, user: &User, name: &str, body: &[u8]) -> Response<Body> {
+    let progress: JobProgress = match serde_json::from_slice(body) {
+        Ok(progress) => progress,
+        Err(e) => return error(StatusCode::BAD_REQUEST, None, &e.to_string()),
+    };
+
+    let job = match find_job(store, user, name) {
+        Ok(job) => job,
+        Err(res) => return res,
+    };
+
+    match store.lock().unwrap().set_progress(&job.id, progress) {
+        Ok(()) => json(StatusCode::OK, &json!({ "ok": true })),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, None, &e.to_string()),
+    }
+}
+
+fn set_resources(store: &Arc<Mutex<Store>>, user: &User, name: &str, body: &[u8]) -> Response<Body> {
+    let resources: ResourceSummary = match serde_json::from_slice(body) {
+        Ok(resources) => resources,
+        Err(e) => return error(StatusCode::BAD_REQUEST, None, &e.to_string()),
+    };
+
+    let job = match find_job(store, user, name) {
+        Ok(job) => job,
+        Err(res) => return res,
+    };
+
+    match store.lock().unwrap().set_resources(&job.id, resources) {
+        Ok(()) => json(StatusCode::OK, &json!({ "ok": true })),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, None, &e.to_string()),
+    }
+}
+
+fn add_alert(store: &Arc<Mutex<Store>>, user: &User, name: &str, body: &[u8]) -> Response<Body> {
+    let alert: Alert = match serde_json::from_slice(body) {
+        Ok(alert) => alert,
+        Err(e) => return error(StatusCode::BAD_REQUEST, None, &e.to_string()),
+    };
+
+    let job = match find_job(store, user, name) {
+        Ok(job) => job,
+        Err(res) => return res,
+    };
+
+    match store.lock().unwrap().add_alert(&job.id, alert) {
+        Ok(()) => json(StatusCode::OK, &json!({ "ok": true })),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, None, &e.to_string()),
+    }
+}
+
+fn list_metrics(store: &Arc<Mutex<Store>>, user: &User, name: &str) -> Response<Body> {
+    let job = match find_job(store, user, name) {
+        Ok(job) => job,
+        Err(res) => return res,
+    };
+
+    match store.lock().unwrap().metrics(&job.id) {
+        Ok(metrics) => json(StatusCode::OK, &metrics),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, None, &e.to_string()),
+    }
+}
+
+fn add_metrics(store: &Arc<Mutex<Store>>, user: &User, name: &str, body: &[u8]) -> Response<Body> {
+    let metrics: Vec<Metric> = match serde_json::from_slice(body) {
+        Ok(metrics) => metrics,
+        Err(e) => return error(StatusCode::BAD_REQUEST, None, &e.to_string()),
+    };
+
+    let job = match find_job(store, user, name) {
+        Ok(job) => job,
+        Err(res) => return res,
+    };
+
+    match store.lock().unwrap().append_metrics(&job.id, &metrics) {
+        Ok(()) => json(StatusCode::OK, &json!({ "ok": true })),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, None, &e.to_string()),
+    }
+}
+
+fn list_artifacts(store: &Arc<Mutex<Store>>, user: &User, name: &str) -> Response<Body> {
+    let job = match find_job(store, user, name) {
+        Ok(job) => job,
+        Err(res) => return res,
+    };
+
+    match store.lock().unwrap().artifacts(&job.id) {
+        Ok(artifacts) => json(StatusCode::OK, &artifacts),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, None, &e.to_string()),
+    }
+}
+
+fn download_artifact(store: &Arc<Mutex<Store>>, user: &User, name: &str, artifact: &str) -> Response<Body> {
+    let job = match find_job(store, user, name) {
+        Ok(job) => job,
+        Err(res) => return res,
+    };
+
+    let contents = store.lock().unwrap()
+        .artifact_path(&job.id, artifact)
+        .and_then(|path| fs::read(path).ok());
+
+    match contents {
+        Some(contents) => {
+            let mut res = Response::new(Body::from(contents));
+            res.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/octet-stream"));
+            res
+        },
+        None => error(StatusCode::NOT_FOUND, None, &format!("{} has no artifact named {}", name, artifact)),
+    }
+}
+
+fn upload_artifact(store: &Arc<Mutex<Store>>, user: &User, name: &str, artifact: &str, body: &[u8]) -> Response<Body> {
+    let job = match find_job(store, user, name) {
+        Ok(job) => job,
+        Err(res) => return res,
+    };
+
+    match store.lock().unwrap().save_artifact(&job.id, artifact, body) {
+        Ok(Some(artifact)) => json(StatusCode::OK, &artifact),
+        Ok(None) => error(StatusCode::BAD_REQUEST, None, &format!("Invalid artifact name {}", artifact)),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, None, &e.to_string()),
+    }
+}
+
+fn new_log(store: &Arc<Mutex<Store>>, user: &User, body: &[u8]) -> Response<Body> {
+    let request: NewLogRequest = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(e) => return error(StatusCode::BAD_REQUEST, None, &e.to_string()),
+    };
+
+    let job = match find_job(store, user, &request.job_name) {
+        Ok(job) => job,
+        Err(res) => return res,
+    };
+
+    match append_log(&mut store.lock().unwrap(), &job, request) {
+        Ok(()) => json(StatusCode::OK, &json!({ "ok": true })),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, None, &e.to_string()),
+    }
+}
+
+fn find_job(store: &Arc<Mutex<Store>>, user: &User, name: &str) -> Result<Job, Response<Body>> {
+    store.lock().unwrap()
+        .find_job(&user.id, name)
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, None, &format!("No job named {}", name)))
+}
+
+// Stores one line, marking a running job done when it's the runner's last line. Runners that
+// know the exit code report it separately, which may already have finished the job.
+fn append_log(store: &mut Store, job: &Job, request: NewLogRequest) -> io::Result<()> {
+    let finished = request.line == "exit" && request.stream.as_ref().map(|s| s.as_str()) == Some("kraken");
+
+    let log = Log {
+        job_id: job.id.clone(),
+        line: request.line,
+        seq: request.seq,
+        timestamp: request.timestamp,
+        stream: request.stream,
+    };
+
+    store.append_log(&log)?;
+
+    let running = store.job_by_id(&job.id).map_or(false, |j| j.status.as_ref().map(|s| s.as_str()) == Some("running"));
+    if finished && running {
+        store.set_status(&job.id, "done")?;
+    }
+
+    Ok(())
+}
+
+// Everything a streaming request needs checked before its body is read
+fn open_stream(store: &Arc<Mutex<Store>>, path: &str, headers: &HeaderMap) -> Result<Job, Response<Body>> {
+    check_version(headers)?;
+
+    let user = match authenticate(store, headers) {
+        Some(user) => user,
+        None => return Err(error(StatusCode::UNAUTHORIZED, Some(false), "Missing or invalid x-access-token")),
+    };
+
+    let name = protocol::decode_path_segment(path.trim_matches('/').split('/').nth(2).unwrap_or(""));
+    find_job(store, &user, &name)
+}
+
+fn store_streamed_line(store: &Arc<Mutex<Store>>, job: &Job, line: &[u8]) {
+    let line = String::from_utf8_lossy(line);
+    if line.trim().is_empty() {
+        return;
+    }
+
+    let request: NewLogRequest = match serde_json::from_str(&line) {
+        Ok(request) => request,
+        Err(e) => {
+            eprintln!("Skipping malformed streamed line for {}: {}", job.name, e);
+            return;
+        }
+    };
+
+    if let Err(e) = append_log(&mut store.lock().unwrap(), job, request) {
+        eprintln!("Couldn't store streamed line for {}: {}", job.name, e);
+    }
+}
+
+fn stream_ack(store: &Arc<Mutex<Store>>, job: &Job) -> Response<Body> {
+    match store.lock().unwrap().last_seq(&job.id) {
+        Ok(acked) => json(StatusCode::OK, &StreamAck { acked: acked }),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, None, &e.to_string()),
+    }
+}
+
+fn wire_job(job: &Job) -> protocol::Job {
+    protocol::Job {
+        id: job.id.clone(),
+        machine: job.machine.clone(),
+        name: job.name.clone(),
+        description: job.description.clone(),
+        status: job.status.clone(),
+        progress: job.progress.clone(),
+        warning: job.alerts.last().map(|a| format!("matched {}: {}", a.pattern, a.line)),
+        group: job.group.clone(),
+        exit_code: job.exit_code,
+        labels: job.labels.clone(),
+    }
+}
+
+fn json<T: Serialize>(status: StatusCode, value: &T) -> Response<Body> {
+    let mut res = Response::new(Body::from(serde_json::to_string(value).unwrap()));
+    *res.status_mut() = status;
+    res.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    res
+}
+
+fn error(status: StatusCode, auth: Option<bool>, message: &str) -> Response<Body> {
+    json(status, &ErrorResponse { auth: auth, message: message.to_string() })
+}
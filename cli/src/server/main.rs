@@ -0,0 +1,152 @@
+// kraken-server: a self-hostable implementation of the Kraken server API
+// Point the CLI at it with `KRAKEN_SERVER_URL=http://<host>:<port>`.
+
+extern crate clap;
+extern crate chrono;
+extern crate hyper;
+extern crate futures;
+extern crate serde;
+extern crate bcrypt;
+
+#[macro_use] extern crate serde_derive;
+#[macro_use] extern crate serde_json;
+
+use std::sync::{Arc, Mutex};
+
+use clap::{Arg, App, ArgMatches, SubCommand};
+use futures::{Future, Stream};
+use hyper::service::service_fn;
+use hyper::{Body, Request, Server};
+
+pub mod api;
+pub mod store;
+#[path = "../protocol.rs"]
+pub mod protocol;
+
+use store::Store;
+
+fn open_store(matches: &ArgMatches, undo_window_secs: Option<i64>) -> Store {
+    let data_dir = matches.value_of("DATA_DIR").unwrap();
+
+    match Store::open(data_dir, undo_window_secs) {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Couldn't open data directory {}: {}", data_dir, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Run
+fn run(matches: &ArgMatches) {
+
+    let undo_window_secs = match matches.value_of("UNDO_WINDOW").unwrap().parse::<i64>() {
+        Ok(secs) => secs,
+        Err(_) => {
+            eprintln!("Invalid --undo-window: {}", matches.value_of("UNDO_WINDOW").unwrap());
+            return;
+        }
+    };
+
+    let store = Arc::new(Mutex::new(open_store(matches, Some(undo_window_secs))));
+    let settings = Arc::new(api::Settings { undo_window_secs: undo_window_secs });
+
+    let addr = match format!("{}:{}", matches.value_of("BIND").unwrap(), matches.value_of("PORT").unwrap()).parse() {
+        Ok(addr) => addr,
+        Err(e) => {
+            eprintln!("Invalid address: {}", e);
+            return;
+        }
+    };
+
+    let new_service = move || {
+        let store = store.clone();
+        let settings = settings.clone();
+
+        service_fn(move |req: Request<Body>| {
+            let store = store.clone();
+            let settings = settings.clone();
+            let (parts, body) = req.into_parts();
+
+            if api::is_stream(&parts.method, parts.uri.path()) {
+                return api::handle_stream(&store, parts.uri.path(), &parts.headers, body);
+            }
+
+            Box::new(body.concat2().map(move |body| {
+                api::handle(&store, &settings, &parts.method, parts.uri.path(), &parts.headers, &body)
+            })) as api::ResponseFuture
+        })
+    };
+
+    let server = Server::bind(&addr)
+        .serve(new_service)
+        .map_err(|e| eprintln!("Server error: {}", e));
+
+    println!("kraken-server listening on http://{}", addr);
+
+    hyper::rt::run(server);
+}
+
+// Add user
+fn add_user(matches: &ArgMatches) {
+
+    // Removed jobs are left for the server to purge, which knows the undo window
+    let mut store = open_store(matches, None);
+    let email = matches.value_of("email").unwrap();
+    let password = matches.value_of("password").unwrap();
+
+    match store.add_user(email, password) {
+        Ok(_) => println!("Added {}. Run `kraken login {} <password>` to use it.", email, email),
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+fn main() {
+
+    let data_dir_arg = Arg::with_name("DATA_DIR")
+        .long("data-dir")
+        .takes_value(true)
+        .default_value("kraken-data")
+        .help("Where users, jobs and logs are stored");
+
+    let app = App::new("Kraken Server")
+        .version("0.1.0")
+        .author("Grant Gumina")
+        .about("Self-hosted server for the kraken CLI")
+
+        // Run Command
+        .subcommand(
+            SubCommand::with_name("run")
+                .arg(data_dir_arg.clone())
+                .arg(Arg::with_name("PORT")
+                    .short("p")
+                    .long("port")
+                    .takes_value(true)
+                    .default_value("5000"))
+                .arg(Arg::with_name("BIND")
+                    .long("bind")
+                    .takes_value(true)
+                    .default_value("127.0.0.1"))
+                .arg(Arg::with_name("UNDO_WINDOW")
+                    .long("undo-window")
+                    .takes_value(true)
+                    .default_value("86400")
+                    .help("Seconds a removed job can still be restored. It's deleted with its logs after that."))
+        )
+
+        // User Commands
+        .subcommand(
+            SubCommand::with_name("add-user")
+                .arg(data_dir_arg)
+                .arg(Arg::with_name("email").required(true))
+                .arg(Arg::with_name("password").required(true))
+        );
+
+    let matches = app.get_matches();
+
+    match matches.subcommand() {
+        ("run", Some(m)) => run(m),
+        ("add-user", Some(m)) => add_user(m),
+        _ => println!("Use `kraken-server -h` for help"),
+    }
+}
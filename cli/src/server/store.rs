@@ -0,0 +1,660 @@
+// Embedded storage for kraken-server
+// Users and jobs are kept in memory. Each change appends the user or job it touched to
+// `journal.jsonl`, and once the journal is long enough it's folded into a `state.json` snapshot,
+// so a change costs one record rather than the whole history. Log lines are appended to
+// `logs/<job id>.jsonl` so shipping a line never rewrites anything. Removed jobs are purged with
+// their logs, metrics and artifacts at the first compaction after their undo window runs out.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::prelude::*;
+
+use protocol::{Alert, Artifact, JobProgress, JobSpec, Metric, NewJobRequest, ResourceSummary};
+
+// Journal entries written before the journal is folded into the snapshot
+const COMPACT_AFTER: usize = 1000;
+
+// How long a login lasts, and how many a user can have at once before the oldest are dropped
+const TOKEN_LIFETIME_SECS: i64 = 30 * 24 * 60 * 60;
+const MAX_TOKENS: usize = 20;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct User {
+    pub id: String,
+    pub email: String,
+    pub password_hash: String,
+    pub tokens: Vec<Token>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Token {
+    pub value: String,
+    // Unix timestamp in seconds
+    pub issued_at: i64,
+}
+
+impl Token {
+    fn expired(&self, now: i64) -> bool {
+        now - self.issued_at > TOKEN_LIFETIME_SECS
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Job {
+    pub id: String,
+    pub owner: String,
+    pub machine: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub status: Option<String>,
+    pub created_at: i64,
+    pub deleted_at: Option<i64>,
+    // Latest report from the job, for jobs that report progress
+    #[serde(default)]
+    pub progress: Option<JobProgress>,
+    // Sent by the job daemon once the job exits
+    #[serde(default)]
+    pub resources: Option<ResourceSummary>,
+    // Output lines that matched the job's `--alert-on` or `--fail-on` patterns
+    #[serde(default)]
+    pub alerts: Vec<Alert>,
+    #[serde(default)]
+    pub group: Option<String>,
+    #[serde(default)]
+    pub exit_code: Option<i64>,
+    // What the job ran, for clients that sent it
+    #[serde(default)]
+    pub spec: Option<JobSpec>,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Log {
+    pub job_id: String,
+    pub line: String,
+    pub seq: Option<u64>,
+    pub timestamp: Option<String>,
+    pub stream: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct State {
+    users: Vec<User>,
+    jobs: Vec<Job>,
+    next_id: u64,
+}
+
+impl State {
+    // Replaces the user or job with the entry's id, or adds it
+    fn apply(&mut self, entry: Entry) {
+        let id = match entry {
+            Entry::User(user) => {
+                let id = user.id.clone();
+                match self.users.iter().position(|u| u.id == user.id) {
+                    Some(index) => self.users[index] = user,
+                    None => self.users.push(user),
+                }
+                id
+            },
+            Entry::Job(job) => {
+                let id = job.id.clone();
+                match self.jobs.iter().position(|j| j.id == job.id) {
+                    Some(index) => self.jobs[index] = job,
+                    None => self.jobs.push(job),
+                }
+                id
+            },
+        };
+
+        // Ids are handed out in order, so the newest one says where to continue
+        if let Ok(n) = u64::from_str_radix(&id, 16) {
+            self.next_id = self.next_id.max(n);
+        }
+    }
+}
+
+// One change in the journal: the whole user or job as it is after the change
+#[derive(Serialize, Deserialize, Debug)]
+enum Entry {
+    User(User),
+    Job(Job),
+}
+
+pub enum RestoreError {
+    NotFound,
+    Expired,
+    // A live job already has the removed job's name
+    NameTaken,
+}
+
+pub struct Store {
+    dir: PathBuf,
+    state: State,
+    journal: File,
+    journal_entries: usize,
+    // How long removed jobs are kept. None keeps them.
+    undo_window_secs: Option<i64>,
+    // Highest seq stored per job, filled in on first use
+    last_seqs: HashMap<String, u64>,
+}
+
+impl Store {
+    pub fn open(dir: &str, undo_window_secs: Option<i64>) -> io::Result<Store> {
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(dir.join("logs"))?;
+        fs::create_dir_all(dir.join("metrics"))?;
+        fs::create_dir_all(dir.join("artifacts"))?;
+
+        let mut state: State = match fs::read_to_string(dir.join("state.json")) {
+            Ok(contents) => serde_json::from_str(&contents)?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => State::default(),
+            Err(e) => return Err(e),
+        };
+
+        let journal_path = dir.join("journal.jsonl");
+        let mut journal_entries = 0;
+
+        if let Ok(file) = File::open(&journal_path) {
+            for line in BufReader::new(file).lines() {
+                // Only the last line can be torn, by a crash while it was written
+                match serde_json::from_str(&line?) {
+                    Ok(entry) => state.apply(entry),
+                    Err(_) => break,
+                }
+                journal_entries += 1;
+            }
+        }
+
+        let journal = OpenOptions::new().create(true).append(true).open(&journal_path)?;
+
+        let mut store = Store {
+            dir: dir,
+            state: state,
+            journal: journal,
+            journal_entries: journal_entries,
+            undo_window_secs: undo_window_secs,
+            last_seqs: HashMap::new(),
+        };
+
+        // Also drops a torn line, which would otherwise hide everything appended after it
+        store.compact()?;
+        Ok(store)
+    }
+
+    // Appends a change to the journal, folding the journal into the snapshot once it's long
+    fn record(&mut self, entry: Entry) -> io::Result<()> {
+        writeln!(self.journal, "{}", serde_json::to_string(&entry)?)?;
+        self.journal_entries += 1;
+
+        if self.journal_entries >= COMPACT_AFTER {
+            self.compact()?;
+        }
+
+        Ok(())
+    }
+
+    // Writes the snapshot, then empties the journal. Replaying a journal the snapshot already
+    // includes changes nothing, so a crash in between loses nothing. Jobs past their undo window
+    // are left out of the snapshot, and their files are deleted once it's written.
+    fn compact(&mut self) -> io::Result<()> {
+        let expired = self.take_expired();
+
+        let tmp_path = self.dir.join("state.json.tmp");
+        serde_json::to_writer_pretty(File::create(&tmp_path)?, &self.state)?;
+        fs::rename(tmp_path, self.dir.join("state.json"))?;
+
+        self.journal = File::create(self.dir.join("journal.jsonl"))?;
+        self.journal_entries = 0;
+
+        for job in expired {
+            self.last_seqs.remove(&job.id);
+            remove_if_exists(fs::remove_file(self.log_path(&job.id)))?;
+            remove_if_exists(fs::remove_file(self.metrics_path(&job.id)))?;
+            remove_if_exists(fs::remove_dir_all(self.dir.join("artifacts").join(&job.id)))?;
+        }
+
+        Ok(())
+    }
+
+    // Takes removed jobs that can no longer be restored out of the state
+    fn take_expired(&mut self) -> Vec<Job> {
+        let cutoff = match self.undo_window_secs {
+            Some(secs) => Utc::now().timestamp() - secs,
+            None => return Vec::new(),
+        };
+
+        let (expired, kept): (Vec<Job>, Vec<Job>) = self.state.jobs.drain(..).partition(|j| j.deleted_at.map_or(false, |at| at < cutoff));
+        self.state.jobs = kept;
+        expired
+    }
+
+    fn save_user(&mut self, index: usize) -> io::Result<()> {
+        let user = self.state.users[index].clone();
+        self.record(Entry::User(user))
+    }
+
+    // Applies `change` to a job and records it, if the job exists
+    fn update_job<F: FnOnce(&mut Job)>(&mut self, job_id: &str, change: F) -> io::Result<()> {
+        let job = match self.state.jobs.iter_mut().find(|j| j.id == job_id) {
+            Some(job) => {
+                change(job);
+                job.clone()
+            },
+            None => return Ok(()),
+        };
+
+        self.record(Entry::Job(job))
+    }
+
+    fn next_id(&mut self) -> String {
+        self.state.next_id += 1;
+        format!("{:08x}", self.state.next_id)
+    }
+
+    // Users and tokens
+
+    pub fn add_user(&mut self, email: &str, password: &str) -> Result<User, String> {
+        if self.state.users.iter().any(|u| u.email == email) {
+            return Err(format!("A user with email {} already exists", email));
+        }
+
+        let password_hash = bcrypt::hash(password, bcrypt::DEFAULT_COST).map_err(|e| e.to_string())?;
+        let user = User {
+            id: self.next_id(),
+            email: email.to_string(),
+            password_hash: password_hash,
+            tokens: Vec::new(),
+        };
+
+        self.state.users.push(user.clone());
+        self.record(Entry::User(user.clone())).map_err(|e| e.to_string())?;
+
+        Ok(user)
+    }
+
+    /// Checks a password and issues a new token for the user, dropping their expired tokens and
+    /// the oldest ones past `MAX_TOKENS`
+    pub fn login(&mut self, email: &str, password: &str) -> io::Result<Option<String>> {
+        let index = match self.state.users.iter().position(|u| u.email == email) {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+
+        if !bcrypt::verify(password, &self.state.users[index].password_hash).unwrap_or(false) {
+            return Ok(None);
+        }
+
+        let now = Utc::now().timestamp();
+        let token = new_token()?;
+
+        {
+            let tokens = &mut self.state.users[index].tokens;
+            tokens.retain(|t| !t.expired(now));
+            tokens.push(Token { value: token.clone(), issued_at: now });
+
+            let excess = tokens.len().saturating_sub(MAX_TOKENS);
+            tokens.drain(..excess);
+        }
+
+        self.save_user(index)?;
+        Ok(Some(token))
+    }
+
+    pub fn user_for_token(&self, token: &str) -> Option<User> {
+        let now = Utc::now().timestamp();
+
+        self.state.users.iter()
+            .find(|u| u.tokens.iter().any(|t| t.value == token && !t.expired(now)))
+            .cloned()
+    }
+
+    // Jobs
+
+    pub fn jobs_for(&self, owner: &str) -> Vec<Job> {
+        self.state.jobs.iter()
+            .filter(|j| j.owner == owner && j.deleted_at.is_none())
+            .cloned()
+            .collect()
+    }
+
+    /// Finds a live job by name. Ids look like names, e.g. `00000002`, so they're never matched here.
+    pub fn find_job(&self, owner: &str, name: &str) -> Option<Job> {
+        self.state.jobs.iter()
+            .find(|j| j.owner == owner && j.deleted_at.is_none() && j.name == name)
+            .cloned()
+    }
+
+    /// Finds a live job by id, for callers that already have the job
+    pub fn job_by_id(&self, job_id: &str) -> Option<Job> {
+        self.state.jobs.iter()
+            .find(|j| j.id == job_id && j.deleted_at.is_none())
+            .cloned()
+    }
+
+    pub fn create_job(&mut self, owner: &str, request: NewJobRequest) -> io::Result<Option<Job>> {
+        if self.find_job(owner, &request.name).is_some() {
+            return Ok(None);
+        }
+
+        let job = Job {
+            id: self.next_id(),
+            owner: owner.to_string(),
+            machine: request.machine,
+            name: request.name,
+            description: request.description,
+            status: Some("running".to_string()),
+            created_at: Utc::now().timestamp(),
+            deleted_at: None,
+            progress: None,
+            resources: None,
+            alerts: Vec::new(),
+            group: request.group,
+            exit_code: None,
+            spec: request.spec,
+            labels: request.labels,
+        };
+
+        self.state.jobs.push(job.clone());
+        self.record(Entry::Job(job.clone()))?;
+
+        Ok(Some(job))
+    }
+
+    pub fn set_status(&mut self, job_id: &str, status: &str) -> io::Result<()> {
+        self.update_job(job_id, |job| job.status = Some(status.to_string()))
+    }
+
+    pub fn finish_job(&mut self, job_id: &str, status: &str, exit_code: Option<i64>) -> io::Result<()> {
+        self.update_job(job_id, |job| {
+            job.status = Some(status.to_string());
+            job.exit_code = exit_code;
+        })
+    }
+
+    pub fn set_progress(&mut self, job_id: &str, progress: JobProgress) -> io::Result<()> {
+        self.update_job(job_id, |job| job.progress = Some(progress))
+    }
+
+    pub fn set_resources(&mut self, job_id: &str, resources: ResourceSummary) -> io::Result<()> {
+        self.update_job(job_id, |job| job.resources = Some(resources))
+    }
+
+    /// Records an alert, failing the job if it came from `--fail-on`
+    pub fn add_alert(&mut self, job_id: &str, alert: Alert) -> io::Result<()> {
+        self.update_job(job_id, |job| {
+            if alert.action == "fail" {
+                job.status = Some("failed".to_string());
+            }
+            job.alerts.push(alert);
+        })
+    }
+
+    /// Soft-deletes a job. It can be restored until the undo window runs out.
+    pub fn remove_job(&mut self, owner: &str, name: &str) -> io::Result<bool> {
+        let removed = self.remove_where(|job| job.owner == owner && job.name == name)?;
+        Ok(removed > 0)
+    }
+
+    pub fn remove_all_jobs(&mut self, owner: &str) -> io::Result<usize> {
+        self.remove_where(|job| job.owner == owner)
+    }
+
+    // Soft-deletes every live job `matches` picks, returning how many there were
+    fn remove_where<F: Fn(&Job) -> bool>(&mut self, matches: F) -> io::Result<usize> {
+        let now = Utc::now().timestamp();
+        let mut removed = Vec::new();
+
+        for job in self.state.jobs.iter_mut() {
+            if job.deleted_at.is_none() && matches(job) {
+                job.deleted_at = Some(now);
+                removed.push(job.clone());
+            }
+        }
+
+        let count = removed.len();
+        for job in removed {
+            self.record(Entry::Job(job))?;
+        }
+
+        Ok(count)
+    }
+
+    pub fn restore_job(&mut self, owner: &str, name: &str, undo_window_secs: i64) -> io::Result<Result<Job, RestoreError>> {
+        if self.find_job(owner, name).is_some() {
+            return Ok(Err(RestoreError::NameTaken));
+        }
+
+        // The most recently removed job with that name
+        let now = Utc::now().timestamp();
+        let index = self.state.jobs.iter()
+            .enumerate()
+            .filter(|&(_, j)| j.owner == owner && j.name == name && j.deleted_at.is_some())
+            .max_by_key(|&(_, j)| j.deleted_at)
+            .map(|(i, _)| i);
+
+        let index = match index {
+            Some(index) => index,
+            None => return Ok(Err(RestoreError::NotFound)),
+        };
+
+        if now - self.state.jobs[index].deleted_at.unwrap() > undo_window_secs {
+            return Ok(Err(RestoreError::Expired));
+        }
+
+        self.state.jobs[index].deleted_at = None;
+        let job = self.state.jobs[index].clone();
+        self.record(Entry::Job(job))?;
+
+        Ok(Ok(self.state.jobs[index].clone()))
+    }
+
+    // Logs
+
+    fn log_path(&self, job_id: &str) -> PathBuf {
+        self.dir.join("logs").join(format!("{}.jsonl", job_id))
+    }
+
+    /// Appends a line unless a line with the same or a later seq is already stored, which
+    /// happens when a streaming client resends after a reconnect. Returns whether it was stored.
+    pub fn append_log(&mut self, log: &Log) -> io::Result<bool> {
+        let last_seq = self.last_seq(&log.job_id)?;
+
+        if let Some(seq) = log.seq {
+            if seq <= last_seq {
+                return Ok(false);
+            }
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(self.log_path(&log.job_id))?;
+        writeln!(file, "{}", serde_json::to_string(log)?)?;
+
+        if let Some(seq) = log.seq {
+            self.last_seqs.insert(log.job_id.clone(), seq);
+        }
+
+        Ok(true)
+    }
+
+    /// Highest seq stored for a job, or 0 if it has no sequenced lines
+    pub fn last_seq(&mut self, job_id: &str) -> io::Result<u64> {
+        if let Some(seq) = self.last_seqs.get(job_id) {
+            return Ok(*seq);
+        }
+
+        let seq = read_last_seq(&self.log_path(job_id))?;
+
+        self.last_seqs.insert(job_id.to_string(), seq);
+        Ok(seq)
+    }
+
+    /// The last `limit` lines of a job, optionally only those captured between `since` and `until`
+    pub fn logs(&self, job_id: &str, limit: usize, since: Option<DateTime<Utc>>, until: Option<DateTime<Utc>>) -> io::Result<Vec<Log>> {
+        let file = match File::open(self.log_path(job_id)) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut logs = Vec::new();
+
+        for line in BufReader::new(file).lines() {
+            let log: Log = serde_json::from_str(&line?)?;
+
+            let timestamp = log.timestamp.as_ref()
+                .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+                .map(|t| t.with_timezone(&Utc));
+
+            let in_range = match timestamp {
+                Some(t) => since.map_or(true, |s| t >= s) && until.map_or(true, |u| t <= u),
+                None => since.is_none() && until.is_none(),
+            };
+
+            if in_range {
+                logs.push(log);
+            }
+        }
+
+        let start = logs.len().saturating_sub(limit);
+        Ok(logs.split_off(start))
+    }
+
+    // Metrics and artifacts
+
+    fn metrics_path(&self, job_id: &str) -> PathBuf {
+        self.dir.join("metrics").join(format!("{}.jsonl", job_id))
+    }
+
+    pub fn append_metrics(&mut self, job_id: &str, metrics: &[Metric]) -> io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(self.metrics_path(job_id))?;
+
+        for metric in metrics {
+            writeln!(file, "{}", serde_json::to_string(metric)?)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn metrics(&self, job_id: &str) -> io::Result<Vec<Metric>> {
+        let file = match File::open(self.metrics_path(job_id)) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut metrics = Vec::new();
+        for line in BufReader::new(file).lines() {
+            metrics.push(serde_json::from_str(&line?)?);
+        }
+
+        Ok(metrics)
+    }
+
+    /// Where an uploaded artifact is kept. None for names that could escape the job's directory.
+    pub fn artifact_path(&self, job_id: &str, name: &str) -> Option<PathBuf> {
+        if name.is_empty() || name.starts_with('.') || name.contains('/') || name.contains('\\') {
+            return None;
+        }
+
+        Some(self.dir.join("artifacts").join(job_id).join(name))
+    }
+
+    /// Stores an uploaded artifact, replacing any earlier upload with the same name
+    pub fn save_artifact(&mut self, job_id: &str, name: &str, contents: &[u8]) -> io::Result<Option<Artifact>> {
+        let path = match self.artifact_path(job_id, name) {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        fs::create_dir_all(self.dir.join("artifacts").join(job_id))?;
+        fs::write(&path, contents)?;
+
+        Ok(Some(Artifact { name: name.to_string(), size: contents.len() as u64, path: None }))
+    }
+
+    pub fn artifacts(&self, job_id: &str) -> io::Result<Vec<Artifact>> {
+        let entries = match fs::read_dir(self.dir.join("artifacts").join(job_id)) {
+            Ok(entries) => entries,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut artifacts = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            artifacts.push(Artifact {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                size: entry.metadata()?.len(),
+                path: None,
+            });
+        }
+
+        artifacts.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(artifacts)
+    }
+}
+
+// Lines are only stored in seq order, so the last seq is on the last line that has one. The log
+// is read back from its end a chunk at a time until that line turns up.
+fn read_last_seq(path: &Path) -> io::Result<u64> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    let mut end = file.metadata()?.len();
+    // The start of a line cut off by the previous chunk
+    let mut carry = Vec::new();
+
+    while end > 0 {
+        let start = end.saturating_sub(64 * 1024);
+        let mut chunk = vec![0; (end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut chunk)?;
+        chunk.extend_from_slice(&carry);
+
+        // Unless the chunk starts the file, its first line may be cut off too
+        let first_line_end = if start > 0 {
+            match chunk.iter().position(|&b| b == b'\n') {
+                Some(i) => i,
+                None => {
+                    carry = chunk;
+                    end = start;
+                    continue;
+                },
+            }
+        } else {
+            0
+        };
+
+        let whole_lines = if start > 0 { &chunk[first_line_end + 1..] } else { &chunk[..] };
+        for line in whole_lines.split(|&b| b == b'\n').rev() {
+            if let Ok(Log { seq: Some(seq), .. }) = serde_json::from_slice(line) {
+                return Ok(seq);
+            }
+        }
+
+        carry = chunk[..first_line_end].to_vec();
+        end = start;
+    }
+
+    Ok(0)
+}
+
+fn remove_if_exists(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        other => other,
+    }
+}
+
+fn new_token() -> io::Result<String> {
+    let mut bytes = [0u8; 24];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
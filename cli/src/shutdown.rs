@@ -0,0 +1,61 @@
+// Graceful shutdown
+// When the machine shuts down, or the daemon running a job gets SIGTERM, SIGINT or SIGHUP, the
+// job is sent SIGTERM and given a grace period to save a checkpoint and exit before it's killed.
+// It's then marked "interrupted", locally and on the server, rather than dying without a record,
+// and `kraken resume` starts it again from its spec, with its `--resume-cmd` if it has one.
+// A job killed from `kraken top` gets the same SIGTERM, but top leaves a marker first and the job
+// is marked "failed" instead, so it isn't resumed.
+
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use triggers::JobProcess;
+
+// How long an interrupted job's output keeps being shipped, since the machine may be going down
+pub const SHIP_WITHIN_SECS: u64 = 5;
+
+// The signals a daemon is stopped with
+const SIGNALS: [libc::c_int; 3] = [libc::SIGTERM, libc::SIGINT, libc::SIGHUP];
+
+/// Blocks the stop signals in this thread and every thread it starts afterwards, so they're left
+/// for `forward` to wait for instead of killing the daemon. Jobs start with them unblocked.
+pub fn block_signals() {
+    unsafe {
+        let set = signal_set();
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut());
+    }
+}
+
+/// Waits for a stop signal and passes it on to the job, which is killed once `grace` is over.
+/// The flag returned is set once that's happened.
+pub fn forward(job: JobProcess, grace: Duration) -> Arc<AtomicBool> {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let flag = interrupted.clone();
+
+    // Not joined, since it waits for a signal that usually never comes
+    thread::spawn(move || {
+        let set = unsafe { signal_set() };
+        let mut signal = 0;
+
+        // Only the first signal counts, the job is already on its way out after that
+        if unsafe { libc::sigwait(&set, &mut signal) } == 0 {
+            flag.store(true, Ordering::SeqCst);
+            job.terminate_within(grace);
+        }
+    });
+
+    interrupted
+}
+
+unsafe fn signal_set() -> libc::sigset_t {
+    let mut set: libc::sigset_t = mem::zeroed();
+    libc::sigemptyset(&mut set);
+    for &signal in &SIGNALS {
+        libc::sigaddset(&mut set, signal);
+    }
+    set
+}
@@ -0,0 +1,592 @@
+// Full-screen job monitor for `kraken top`
+// Jobs come from the server merged with the local registry, refreshed on a background thread
+// so a slow server never freezes the keyboard. Keys:
+//
+//   up/down, j/k   select a job
+//   enter          open or close the log pane
+//   f              follow the selected job's output in the log pane
+//   x              kill the selected job (only jobs started on this machine)
+//   d              remove the selected job
+//   m              cycle the machine filter
+//   r              refresh now
+//   q, esc         quit
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::screen::AlternateScreen;
+use termion::{clear, color, cursor, style};
+
+use job_output;
+use job_registry;
+use kraken_utils;
+use krephis;
+use log_line::LogLine;
+
+pub struct TopOptions {
+    pub output_dir: String,
+    // Skip the server and only show jobs started on this machine
+    pub local_only: bool,
+    pub machine: Option<String>,
+    pub refresh: Duration,
+}
+
+#[derive(Debug, Clone)]
+struct Row {
+    name: String,
+    machine: String,
+    status: String,
+    started_at: Option<i64>,
+    finished_at: Option<i64>,
+    last_line: String,
+    // Started on this machine, so its files and pid are here
+    local: bool,
+}
+
+struct Snapshot {
+    rows: Vec<Row>,
+    error: Option<String>,
+    logs: Option<(String, Vec<String>)>,
+    taken_at: DateTime<Local>,
+}
+
+enum Event {
+    Key(Key),
+    Snapshot(Snapshot),
+}
+
+// What the refresh thread should fetch logs for
+#[derive(Default)]
+struct Watch {
+    job: Option<String>,
+    follow: bool,
+    log_lines: usize,
+}
+
+enum Action {
+    Kill(Row),
+    Remove(Row),
+}
+
+struct Ui {
+    rows: Vec<Row>,
+    selected: Option<String>,
+    machine: Option<String>,
+    log_pane: bool,
+    follow: bool,
+    logs: Vec<String>,
+    confirm: Option<Action>,
+    message: String,
+    error: Option<String>,
+    taken_at: Option<DateTime<Local>>,
+}
+
+pub fn run(options: TopOptions) {
+
+    let stdout = match io::stdout().into_raw_mode() {
+        Ok(stdout) => stdout,
+        Err(_) => {
+            eprintln!("kraken top needs a terminal. Use `kraken show jobs` instead.");
+            return;
+        }
+    };
+    let mut screen = AlternateScreen::from(stdout);
+
+    let (events, receiver) = mpsc::channel();
+    let (poke, poked) = mpsc::channel();
+    let watch = Arc::new(Mutex::new(Watch::default()));
+
+    let key_events = events.clone();
+    thread::spawn(move || {
+        for key in io::stdin().keys() {
+            match key {
+                Ok(key) => {
+                    if key_events.send(Event::Key(key)).is_err() {
+                        break;
+                    }
+                },
+                Err(_) => break,
+            }
+        }
+    });
+
+    let refresh_watch = watch.clone();
+    let refresh_options = TopOptions {
+        output_dir: options.output_dir.clone(),
+        local_only: options.local_only,
+        machine: None,
+        refresh: options.refresh,
+    };
+    thread::spawn(move || refresh_loop(&refresh_options, &refresh_watch, &events, &poked));
+
+    let mut ui = Ui {
+        rows: Vec::new(),
+        selected: None,
+        machine: options.machine.clone(),
+        log_pane: false,
+        follow: false,
+        logs: Vec::new(),
+        confirm: None,
+        message: String::new(),
+        error: None,
+        taken_at: None,
+    };
+
+    let _ = write!(screen, "{}", cursor::Hide);
+    draw(&mut screen, &ui);
+
+    for event in receiver {
+        match event {
+            Event::Snapshot(snapshot) => {
+                ui.rows = snapshot.rows;
+                ui.error = snapshot.error;
+                ui.taken_at = Some(snapshot.taken_at);
+
+                if let Some((job, lines)) = snapshot.logs {
+                    if ui.log_pane && ui.selected.as_ref() == Some(&job) {
+                        ui.logs = lines;
+                    }
+                }
+            },
+            Event::Key(key) => {
+                if !handle_key(key, &mut ui, &options) {
+                    break;
+                }
+
+                let mut w = watch.lock().unwrap();
+                w.job = if ui.log_pane { ui.selected.clone() } else { None };
+                w.follow = ui.follow;
+                w.log_lines = terminal_height();
+                let _ = poke.send(());
+            },
+        }
+
+        draw(&mut screen, &ui);
+    }
+
+    let _ = write!(screen, "{}", cursor::Show);
+    let _ = screen.flush();
+}
+
+// Returns false when it's time to quit
+fn handle_key(key: Key, ui: &mut Ui, options: &TopOptions) -> bool {
+
+    if let Some(action) = ui.confirm.take() {
+        ui.message = match key {
+            Key::Char('y') | Key::Char('Y') => perform(action, options),
+            _ => "Cancelled".to_string(),
+        };
+        return true;
+    }
+
+    ui.message.clear();
+    let visible = visible_rows(ui);
+    let position = ui.selected.as_ref().and_then(|name| visible.iter().position(|r| &r.name == name));
+
+    match key {
+        Key::Char('q') | Key::Esc | Key::Ctrl('c') => return false,
+        Key::Up | Key::Char('k') => {
+            let index = position.map_or(0, |i| i.saturating_sub(1));
+            ui.selected = visible.get(index).map(|r| r.name.clone());
+            ui.logs.clear();
+        },
+        Key::Down | Key::Char('j') => {
+            let index = position.map_or(0, |i| (i + 1).min(visible.len().saturating_sub(1)));
+            ui.selected = visible.get(index).map(|r| r.name.clone());
+            ui.logs.clear();
+        },
+        Key::Char('\n') => {
+            ui.log_pane = !ui.log_pane;
+            if !ui.log_pane {
+                ui.follow = false;
+            }
+        },
+        Key::Char('f') => {
+            ui.follow = !ui.follow;
+            if ui.follow {
+                ui.log_pane = true;
+            }
+        },
+        Key::Char('m') => {
+            ui.machine = next_machine(&ui.rows, &ui.machine);
+            ui.selected = None;
+            ui.logs.clear();
+        },
+        Key::Char('x') => {
+            match selected_row(ui) {
+                Some(ref row) if !row.local => ui.message = format!("{} runs on {}, kill it from there", row.name, row.machine),
+                Some(row) => {
+                    ui.message = format!("Kill {}? (y/n)", row.name);
+                    ui.confirm = Some(Action::Kill(row));
+                },
+                None => {},
+            }
+        },
+        Key::Char('d') => {
+            match selected_row(ui) {
+                Some(ref row) if row.status == "running" => ui.message = format!("{} is still running, kill it first", row.name),
+                Some(row) => {
+                    ui.message = format!("Remove {}? (y/n)", row.name);
+                    ui.confirm = Some(Action::Remove(row));
+                },
+                None => {},
+            }
+        },
+        _ => {},
+    }
+
+    true
+}
+
+fn perform(action: Action, options: &TopOptions) -> String {
+    match action {
+        Action::Kill(row) => {
+            match job_registry::read_pid(&options.output_dir, &row.name) {
+                Some(pid) if job_registry::pid_alive(pid) => {
+                    // Without it the daemon takes the SIGTERM for a shutdown and the job gets resumed
+                    if let Err(e) = job_registry::mark_killed(&options.output_dir, &row.name) {
+                        return format!("Couldn't kill {}: {}", row.name, e);
+                    }
+
+                    // The daemon leads its own process group, so this reaches the command too
+                    let killed = unsafe { libc::kill(-pid, libc::SIGTERM) == 0 || libc::kill(pid, libc::SIGTERM) == 0 };
+                    if killed {
+                        format!("Sent SIGTERM to {}", row.name)
+                    } else {
+                        let error = io::Error::last_os_error();
+                        job_registry::take_killed(&options.output_dir, &row.name);
+                        format!("Couldn't kill {}: {}", row.name, error)
+                    }
+                },
+                _ => format!("{} isn't running", row.name),
+            }
+        },
+        Action::Remove(row) => {
+            if options.local_only {
+                remove_local_files(&options.output_dir, &row.name)
+            } else {
+                match krephis::delete_job(&row.name) {
+                    Ok(()) => format!("Removed {}. Use `kraken restore job {}` to undo.", row.name, row.name),
+                    Err(e) => e.to_string(),
+                }
+            }
+        },
+    }
+}
+
+fn remove_local_files(dir: &str, job_name: &str) -> String {
+    let paths = match job_output::artifacts(dir, job_name) {
+        Ok(paths) => paths,
+        Err(e) => return format!("Couldn't remove {}: {}", job_name, e),
+    };
+
+    for path in paths {
+        if let Err(e) = fs::remove_file(&path) {
+            return format!("Couldn't remove {}: {}", path.display(), e);
+        }
+    }
+
+    format!("Removed {}", job_name)
+}
+
+fn refresh_loop(options: &TopOptions, watch: &Arc<Mutex<Watch>>, events: &Sender<Event>, poked: &Receiver<()>) {
+    // Last lines of remote jobs that have finished never change, so they're only fetched once
+    let mut finished_lines: HashMap<String, String> = HashMap::new();
+
+    loop {
+        let (rows, error) = load_rows(options, &mut finished_lines);
+
+        let logs = {
+            let mut w = watch.lock().unwrap();
+            let logs = w.job.clone().map(|job| {
+                let lines = load_logs(options, &rows, &job, w.log_lines);
+                (job, lines)
+            });
+
+            if !w.follow {
+                w.job = None;
+            }
+            logs
+        };
+
+        let snapshot = Snapshot { rows: rows, error: error, logs: logs, taken_at: Local::now() };
+        if events.send(Event::Snapshot(snapshot)).is_err() {
+            return;
+        }
+
+        match poked.recv_timeout(options.refresh) {
+            Ok(()) | Err(RecvTimeoutError::Timeout) => {},
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+fn load_rows(options: &TopOptions, finished_lines: &mut HashMap<String, String>) -> (Vec<Row>, Option<String>) {
+    let dir = &options.output_dir;
+    let mut rows = Vec::new();
+    let mut error = None;
+
+    for record in job_registry::list(dir).unwrap_or_default() {
+        let mut status = record.status.clone();
+        if status == "running" && !job_registry::is_running(dir, &record.name) {
+            status = "killed".to_string();
+        }
+
+        let last_line = job_output::tail_lines(dir, &record.name, 1)
+            .ok()
+            .and_then(|lines| lines.into_iter().last())
+            .map(|record| LogLine::parse_record(&record).map(|line| line.text).unwrap_or(record))
+            .unwrap_or_default();
+
+        rows.push(Row {
+            name: record.name,
+            machine: record.machine,
+            status: status,
+            started_at: Some(record.started_at),
+            finished_at: record.finished_at,
+            last_line: last_line,
+            local: true,
+        });
+    }
+
+    if options.local_only {
+        return (rows, error);
+    }
+
+    match krephis::jobs() {
+        Ok(jobs) => {
+            for job in jobs {
+                if rows.iter().any(|r| r.local && r.name == job.name) {
+                    continue;
+                }
+
+                let status = job.status.unwrap_or_default();
+
+                let last_line = match finished_lines.get(&job.name) {
+                    Some(line) => line.clone(),
+                    None => {
+                        let line = krephis::logs(&job.name, 1)
+                            .ok()
+                            .and_then(|logs| logs.into_iter().last())
+                            .map(|log| log.line)
+                            .unwrap_or_default();
+
+                        if status != "running" {
+                            finished_lines.insert(job.name.clone(), line.clone());
+                        }
+                        line
+                    },
+                };
+
+                rows.push(Row {
+                    name: job.name,
+                    machine: job.machine,
+                    status: status,
+                    started_at: None,
+                    finished_at: None,
+                    last_line: last_line,
+                    local: false,
+                });
+            }
+        },
+        Err(e) => error = Some(e.to_string()),
+    }
+
+    (rows, error)
+}
+
+fn load_logs(options: &TopOptions, rows: &[Row], job_name: &str, count: usize) -> Vec<String> {
+    let local = rows.iter().any(|r| r.local && r.name == job_name);
+
+    if local || options.local_only {
+        match job_output::tail_lines(&options.output_dir, job_name, count) {
+            Ok(records) => records.iter()
+                .map(|record| LogLine::parse_record(record).map(|line| line.text).unwrap_or_else(|| record.clone()))
+                .collect(),
+            Err(e) => vec![format!("Couldn't read output: {}", e)],
+        }
+    } else {
+        match krephis::logs(job_name, count) {
+            Ok(logs) => logs.into_iter().map(|log| log.line).collect(),
+            Err(e) => vec![e.to_string()],
+        }
+    }
+}
+
+fn visible_rows(ui: &Ui) -> Vec<Row> {
+    ui.rows.iter()
+        .filter(|r| ui.machine.as_ref().map_or(true, |m| &r.machine == m))
+        .cloned()
+        .collect()
+}
+
+fn selected_row(ui: &Ui) -> Option<Row> {
+    let name = ui.selected.as_ref()?;
+    visible_rows(ui).into_iter().find(|r| &r.name == name)
+}
+
+// All machines, then each machine in turn
+fn next_machine(rows: &[Row], current: &Option<String>) -> Option<String> {
+    let mut machines: Vec<String> = rows.iter().map(|r| r.machine.clone()).collect();
+    machines.sort();
+    machines.dedup();
+
+    match *current {
+        None => machines.into_iter().next(),
+        Some(ref machine) => {
+            let index = machines.iter().position(|m| m == machine);
+            index.and_then(|i| machines.get(i + 1).cloned())
+        },
+    }
+}
+
+fn terminal_height() -> usize {
+    terminal_size().1
+}
+
+// Some terminals report 0x0 until they're resized
+fn terminal_size() -> (usize, usize) {
+    match termion::terminal_size() {
+        Ok((width, height)) if width > 0 && height > 4 => (width as usize, height as usize),
+        _ => (80, 24),
+    }
+}
+
+fn draw<W: Write>(out: &mut W, ui: &Ui) {
+    let (width, height) = terminal_size();
+
+    let rows = visible_rows(ui);
+    let position = ui.selected.as_ref().and_then(|name| rows.iter().position(|r| &r.name == name));
+
+    let mut lines: Vec<String> = Vec::new();
+
+    let updated = ui.taken_at.map(|t| t.format("%H:%M:%S").to_string()).unwrap_or_else(|| "loading".to_string());
+    let header = format!(
+        " kraken top   {} jobs   machine: {}   updated {}",
+        rows.len(),
+        ui.machine.as_ref().map(|m| m.as_str()).unwrap_or("all"),
+        updated
+    );
+    lines.push(format!("{}{}{}", style::Invert, fit(&header, width), style::Reset));
+
+    let name_width = 24;
+    let machine_width = 16;
+    let status_width = 9;
+    let runtime_width = 9;
+    let last_width = width.saturating_sub(name_width + machine_width + status_width + runtime_width + 4);
+
+    lines.push(format!(
+        "{}{} {} {} {} {}{}",
+        style::Bold,
+        fit("NAME", name_width),
+        fit("MACHINE", machine_width),
+        fit("STATUS", status_width),
+        fit("RUNTIME", runtime_width),
+        fit("LAST OUTPUT", last_width),
+        style::Reset
+    ));
+
+    // One line each for the header, column names and footer
+    let body_height = height.saturating_sub(3);
+    let table_height = if ui.log_pane { body_height / 2 } else { body_height };
+    let offset = position.map_or(0, |p| (p + 1).saturating_sub(table_height));
+
+    for (i, row) in rows.iter().enumerate().skip(offset).take(table_height) {
+        let line = format!(
+            "{} {} {}{}{} {} {}",
+            fit(&row.name, name_width),
+            fit(&row.machine, machine_width),
+            status_color(&row.status),
+            fit(&row.status, status_width),
+            color::Fg(color::Reset),
+            fit(&runtime(row), runtime_width),
+            fit(&row.last_line, last_width)
+        );
+
+        if Some(i) == position {
+            lines.push(format!("{}{}{}", style::Invert, line, style::Reset));
+        } else {
+            lines.push(line);
+        }
+    }
+
+    if rows.is_empty() {
+        lines.push(" No jobs".to_string());
+    }
+
+    while lines.len() < table_height + 2 {
+        lines.push(String::new());
+    }
+
+    if ui.log_pane {
+        let title = match ui.selected {
+            Some(ref name) => format!(" logs: {}{} ", name, if ui.follow { " (following)" } else { "" }),
+            None => " logs: select a job ".to_string(),
+        };
+        lines.push(format!("{}{}{}", style::Invert, fit(&title, width), style::Reset));
+
+        let log_height = body_height.saturating_sub(table_height + 1);
+        let first = ui.logs.len().saturating_sub(log_height);
+        for line in &ui.logs[first..] {
+            lines.push(fit(line, width));
+        }
+
+        while lines.len() < height - 1 {
+            lines.push(String::new());
+        }
+    }
+
+    let footer = if !ui.message.is_empty() {
+        ui.message.clone()
+    } else if let Some(ref e) = ui.error {
+        e.clone()
+    } else {
+        "enter logs  f follow  x kill  d remove  m machine  r refresh  q quit".to_string()
+    };
+    lines.truncate(height - 1);
+    lines.resize(height - 1, String::new());
+    lines.push(fit(&footer, width));
+
+    let _ = write!(out, "{}{}{}", clear::All, cursor::Goto(1, 1), lines.join("\r\n"));
+    let _ = out.flush();
+}
+
+fn status_color(status: &str) -> String {
+    match status {
+        "running" => color::Fg(color::Green).to_string(),
+        "failed" | "killed" => color::Fg(color::Red).to_string(),
+        "interrupted" => color::Fg(color::Yellow).to_string(),
+        _ => color::Fg(color::Reset).to_string(),
+    }
+}
+
+fn runtime(row: &Row) -> String {
+    match row.started_at {
+        Some(started_at) => {
+            let finished_at = row.finished_at.unwrap_or_else(|| Utc::now().timestamp());
+            kraken_utils::format_duration((finished_at - started_at).max(0))
+        },
+        None => "-".to_string(),
+    }
+}
+
+// Pads or cuts `text` to exactly `width` columns
+fn fit(text: &str, width: usize) -> String {
+    let mut fitted: String = text.chars().filter(|c| !c.is_control()).take(width).collect();
+    let length = fitted.chars().count();
+
+    for _ in length..width {
+        fitted.push(' ');
+    }
+
+    fitted
+}
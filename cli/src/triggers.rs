@@ -0,0 +1,160 @@
+// Output pattern triggers
+// `new job --alert-on <regex>` and `--fail-on <regex>` are checked against every line the job
+// prints. An alert pattern flags the job with a warning, locally and on the server, and sends
+// the job's `--notify` targets an alert. A fail pattern does the same, then terminates the job
+// and marks it failed. Each pattern fires once, on the first line it matches, so a job stuck
+// printing the same error doesn't flood anyone.
+
+use std::io;
+use std::mem;
+use std::process::{Child, ExitStatus};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use regex::Regex;
+
+use log_line;
+use protocol::Alert;
+use resources;
+
+// How long a `--fail-on` job gets to exit after SIGTERM before it's killed
+const KILL_AFTER_SECS: u64 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Alert,
+    Fail,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Action::Alert => "alert",
+            Action::Fail => "fail",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Trigger {
+    pattern: Regex,
+    action: Action,
+    fired: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Triggers {
+    triggers: Vec<Trigger>,
+}
+
+impl Triggers {
+    pub fn new<'a, A, F>(alert_on: A, fail_on: F) -> Result<Triggers, String>
+        where A: IntoIterator<Item = &'a str>,
+              F: IntoIterator<Item = &'a str>
+    {
+        let mut triggers = Vec::new();
+
+        let patterns = alert_on.into_iter().map(|p| (p, Action::Alert))
+            .chain(fail_on.into_iter().map(|p| (p, Action::Fail)));
+
+        for (pattern, action) in patterns {
+            let regex = Regex::new(pattern).map_err(|e| {
+                let flag = if action == Action::Alert { "--alert-on" } else { "--fail-on" };
+                format!("Invalid {} pattern {}: {}", flag, pattern, e)
+            })?;
+
+            triggers.push(Trigger { pattern: regex, action: action, fired: false });
+        }
+
+        Ok(Triggers { triggers: triggers })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.triggers.is_empty()
+    }
+
+    /// Alerts for the patterns `line` is the first to match
+    pub fn check(&mut self, line: &str) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+
+        for trigger in self.triggers.iter_mut().filter(|t| !t.fired) {
+            if trigger.pattern.is_match(line) {
+                trigger.fired = true;
+                alerts.push(Alert {
+                    pattern: trigger.pattern.as_str().to_string(),
+                    line: line.to_string(),
+                    action: trigger.action.as_str().to_string(),
+                    timestamp: log_line::format_timestamp(&Utc::now()),
+                });
+            }
+        }
+
+        alerts
+    }
+}
+
+/// A running job's process, which is only signalled until it's been waited for, since its pid
+/// can belong to another process after that
+#[derive(Debug, Clone)]
+pub struct JobProcess {
+    pid: u32,
+    reaped: Arc<Mutex<bool>>,
+}
+
+impl JobProcess {
+    pub fn new(pid: u32) -> JobProcess {
+        JobProcess { pid: pid, reaped: Arc::new(Mutex::new(false)) }
+    }
+
+    /// SIGTERMs the job's whole process tree, then SIGKILLs whatever is left once the grace
+    /// period is over, unless the job has exited by then. Doesn't wait for it.
+    pub fn terminate(&self) {
+        self.terminate_within(Duration::from_secs(KILL_AFTER_SECS));
+    }
+
+    /// Like `terminate`, with the job given `grace` to exit
+    pub fn terminate_within(&self, grace: Duration) {
+        self.signal_tree(libc::SIGTERM);
+
+        let job = self.clone();
+        thread::spawn(move || {
+            thread::sleep(grace);
+            job.signal_tree(libc::SIGKILL);
+        });
+    }
+
+    /// Waits for `child`, the job's process, to exit. It's left a zombie until no more signals
+    /// can be sent to it, so they never reach a process that's been given its pid.
+    pub fn wait(&self, child: &mut Child) -> io::Result<ExitStatus> {
+        loop {
+            let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+            if unsafe { libc::waitid(libc::P_PID, self.pid, &mut info, libc::WEXITED | libc::WNOWAIT) } == 0 {
+                break;
+            }
+
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+
+        *self.reaped.lock().unwrap() = true;
+        child.wait()
+    }
+
+    fn signal_tree(&self, signal: libc::c_int) {
+        // Held while signalling, so the job can't be reaped in between
+        let reaped = self.reaped.lock().unwrap();
+        if *reaped {
+            return;
+        }
+
+        for pid in resources::process_tree(self.pid) {
+            unsafe {
+                libc::kill(pid as libc::pid_t, signal);
+            }
+        }
+    }
+}
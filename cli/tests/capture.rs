@@ -0,0 +1,133 @@
+// Regression tests for pathological job output
+// `capture` only depends on std, so it's pulled in directly rather than through the binary.
+
+#[path = "../src/capture.rs"]
+#[allow(dead_code)]
+mod capture;
+
+use capture::{CaptureOptions, Decoding, LineSplitter};
+use std::io::{self, Read};
+
+fn lines_of(input: &[u8], max_line_bytes: usize, decoding: Decoding) -> Vec<String> {
+    let options = CaptureOptions { max_line_bytes: max_line_bytes, decoding: decoding };
+    LineSplitter::new(input, options).map(|line| line.unwrap()).collect()
+}
+
+// Hands out one byte per read, so every `\r\n` and UTF-8 sequence straddles a chunk boundary
+struct Trickle<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Read for Trickle<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.data.is_empty() || buf.is_empty() {
+            return Ok(0);
+        }
+
+        buf[0] = self.data[0];
+        self.data = &self.data[1..];
+        Ok(1)
+    }
+}
+
+struct Broken;
+
+impl Read for Broken {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::Other, "pipe exploded"))
+    }
+}
+
+#[test]
+fn plain_lines() {
+    assert_eq!(lines_of(b"one\ntwo\n", 0, Decoding::Lossy), vec!["one", "two"]);
+}
+
+#[test]
+fn empty_input_has_no_lines() {
+    assert!(lines_of(b"", 0, Decoding::Lossy).is_empty());
+}
+
+#[test]
+fn blank_lines_are_kept() {
+    assert_eq!(lines_of(b"\n\nx\n", 0, Decoding::Lossy), vec!["", "", "x"]);
+}
+
+#[test]
+fn last_line_without_newline() {
+    assert_eq!(lines_of(b"one\ntwo", 0, Decoding::Lossy), vec!["one", "two"]);
+}
+
+#[test]
+fn crlf_line_endings() {
+    assert_eq!(lines_of(b"one\r\ntwo\r\n", 0, Decoding::Lossy), vec!["one", "two"]);
+}
+
+#[test]
+fn invalid_utf8_lossy() {
+    assert_eq!(lines_of(b"ok \xff\xfe done\n", 0, Decoding::Lossy), vec!["ok \u{fffd}\u{fffd} done"]);
+}
+
+#[test]
+fn invalid_utf8_escaped() {
+    assert_eq!(lines_of(b"ok \xff\xfe done\n", 0, Decoding::Escaped), vec!["ok \\xff\\xfe done"]);
+}
+
+#[test]
+fn truncated_utf8_at_eof_escaped() {
+    assert_eq!(lines_of(b"caf\xc3", 0, Decoding::Escaped), vec!["caf\\xc3"]);
+}
+
+#[test]
+fn progress_bar_collapses_to_last_redraw() {
+    assert_eq!(lines_of(b"0%\r50%\r100%\ndone\n", 0, Decoding::Lossy), vec!["100%", "done"]);
+}
+
+#[test]
+fn progress_bar_without_final_newline() {
+    assert_eq!(lines_of(b"10%\r20%\r", 0, Decoding::Lossy), vec!["20%"]);
+}
+
+#[test]
+fn long_lines_are_split() {
+    assert_eq!(lines_of(b"abcdefgh\n", 3, Decoding::Lossy), vec!["abc", "def", "gh"]);
+}
+
+#[test]
+fn line_of_exactly_max_length_is_not_split() {
+    assert_eq!(lines_of(b"abc\nde\n", 3, Decoding::Lossy), vec!["abc", "de"]);
+}
+
+#[test]
+fn splitting_never_cuts_a_character_in_half() {
+    // Each é is two bytes
+    let lines = lines_of("éééé\n".as_bytes(), 3, Decoding::Lossy);
+    assert_eq!(lines, vec!["é", "é", "é", "é"]);
+}
+
+#[test]
+fn huge_line_is_split_into_bounded_pieces() {
+    let input = vec![b'x'; 1024 * 1024];
+    let lines = lines_of(&input, 4096, Decoding::Lossy);
+
+    assert_eq!(lines.len(), 256);
+    assert!(lines.iter().all(|line| line.len() == 4096));
+}
+
+#[test]
+fn one_byte_reads() {
+    let input = b"a\r\nb\xffc\r1\r2\n\xc3\xbc\n";
+    let reader = Trickle { data: input };
+    let options = CaptureOptions { max_line_bytes: 0, decoding: Decoding::Lossy };
+    let lines: Vec<String> = LineSplitter::new(reader, options).map(|line| line.unwrap()).collect();
+
+    assert_eq!(lines, vec!["a", "2", "ü"]);
+}
+
+#[test]
+fn read_errors_are_returned_not_panicked() {
+    let options = CaptureOptions { max_line_bytes: 0, decoding: Decoding::Lossy };
+    let mut lines = LineSplitter::new(Broken, options);
+
+    assert!(lines.next().unwrap().is_err());
+}
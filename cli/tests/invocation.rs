@@ -0,0 +1,130 @@
+// How a job's command runs: with a shell, directly after `--`, or from a `--script` file
+
+mod mock_server;
+
+use mock_server::{stderr, MockServer};
+use std::fs;
+use std::time::Duration;
+
+#[test]
+fn commands_after_a_double_dash_run_without_a_shell() {
+    let server = MockServer::start("invocation-direct");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "direct"}"#);
+    server.expect_job("direct");
+
+    server.kraken(&["new", "job", "-n", "direct", "--", "printf", "%s|", "two words", "$HOME"]);
+    assert!(server.printed("direct", "two words|$HOME|"), "the job's output never arrived");
+    assert!(server.printed("direct", "$> printf '%s|' 'two words' '$HOME'"), "the header doesn't show the invocation");
+
+    let created = &server.requests_to("POST", "/jobs/new")[0];
+    assert!(created.body.contains(r#""argv":["printf","%s|","two words","$HOME"]"#), "{}", created.body);
+    assert!(created.body.contains(r#""invocation":["printf","%s|","two words","$HOME"]"#), "{}", created.body);
+}
+
+#[test]
+fn commands_run_with_the_chosen_shell() {
+    let server = MockServer::start("invocation-shell");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "shelled"}"#);
+    server.expect_job("shelled");
+
+    server.kraken(&["new", "job", "-n", "shelled", "--shell", "bash", "echo ${BASH_VERSION:+in bash}"]);
+    assert!(server.printed("shelled", "in bash"), "the job's output never arrived");
+    assert!(server.printed("shelled", "$> bash -c 'echo ${BASH_VERSION:+in bash}'"), "the header doesn't show the invocation");
+
+    let record = fs::read_to_string(server.home.join("jobs").join("kraken-job-shelled.json")).unwrap();
+    assert!(record.contains(r#""invocation""#) && record.contains(r#""echo ${BASH_VERSION:+in bash}""#), "{}", record);
+}
+
+#[test]
+fn scripts_run_from_their_copy() {
+    let server = MockServer::start("invocation-script");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "scripted"}"#);
+    server.expect_job("scripted");
+    server.expect_job("scripted-again");
+
+    let script = server.home.join("train.sh");
+    fs::write(&script, "#!/bin/sh\necho \"first version got $1\"\n").unwrap();
+
+    server.kraken(&["new", "job", "-n", "scripted", "--script", script.to_str().unwrap(), "--", "an argument"]);
+    assert!(server.printed("scripted", "first version got an argument"), "the script never printed");
+
+    let copy = server.home.join("jobs").join("kraken-job-scripted.script");
+    assert!(server.printed("scripted", &format!("$> {} 'an argument'", copy.display())), "the header doesn't show the invocation");
+
+    let created = &server.requests_to("POST", "/jobs/new")[0];
+    assert!(created.body.contains(r#""command":"train.sh""#), "{}", created.body);
+    assert!(created.body.contains(r##""script":"#!/bin/sh\necho \"first version got $1\"\n""##), "{}", created.body);
+
+    // Running it again runs the script the job started with
+    fs::write(&script, "#!/bin/sh\necho changed\n").unwrap();
+    server.kraken(&["rerun", "job", "scripted", "-n", "scripted-again"]);
+    assert!(server.printed("scripted-again", "first version got an argument"), "the rerun didn't run the saved script");
+}
+
+#[test]
+fn commands_that_cant_run_are_refused() {
+    let server = MockServer::start("invocation-refused");
+    server.store_token("abc123");
+
+    let output = server.kraken(&["new", "job", "--", "no-such-program", "--flag"]);
+    assert!(stderr(&output).contains("Couldn't find no-such-program to run"), "{}", stderr(&output));
+
+    let output = server.kraken(&["new", "job", "--shell", "bash", "--", "ls"]);
+    assert!(stderr(&output).contains("--shell can't be used with a command given after --"), "{}", stderr(&output));
+
+    let output = server.kraken(&["new", "job", "--script", "/no/such/script.sh"]);
+    assert!(stderr(&output).contains("Couldn't read --script /no/such/script.sh"), "{}", stderr(&output));
+
+    assert!(server.requests_to("POST", "/jobs/new").is_empty());
+}
+
+#[test]
+fn jobs_that_cant_start_are_failed() {
+    let server = MockServer::start("invocation-no-interpreter");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "orphan"}"#);
+    server.respond("POST", "/jobs/orphan/exit", 200, r#"{"ok": true}"#);
+
+    let script = server.home.join("orphan.sh");
+    fs::write(&script, "#!/no/such/interpreter\necho never\n").unwrap();
+    server.kraken(&["new", "job", "-n", "orphan", "--script", script.to_str().unwrap()]);
+    assert!(server.wait_for(Duration::from_secs(20), |requests| requests.iter().any(|r| r.path == "/jobs/orphan/exit")));
+
+    let jobs = server.home.join("jobs");
+    let errors = fs::read_to_string(jobs.join("kraken-job-orphan.err")).unwrap();
+    assert!(errors.contains("Couldn't find") && errors.contains("to run"), "{}", errors);
+
+    let record = fs::read_to_string(jobs.join("kraken-job-orphan.json")).unwrap();
+    assert!(record.contains(r#""status": "failed""#), "{}", record);
+
+    let exit = &server.requests_to("POST", "/jobs/orphan/exit")[0];
+    assert_eq!(exit.body, r#"{"status":"failed","exitCode":null}"#);
+}
+
+#[test]
+fn relative_output_dirs_hold_all_of_a_jobs_files() {
+    let server = MockServer::start("invocation-relative-output");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "tidy"}"#);
+    server.expect_job("tidy");
+
+    let workdir = server.home.join("work");
+    fs::create_dir_all(&workdir).unwrap();
+    let script = server.home.join("tidy.sh");
+    fs::write(&script, "#!/bin/sh\necho tidy from $(pwd)\n").unwrap();
+
+    // The daemon runs in the workdir, while the output dir is relative to where kraken started
+    server.command(&["new", "job", "-n", "tidy", "--output-dir", "logs", "--workdir", workdir.to_str().unwrap(), "--script", script.to_str().unwrap()])
+        .current_dir(&server.home)
+        .output()
+        .unwrap();
+    assert!(server.printed("tidy", &format!("tidy from {}", fs::canonicalize(&workdir).unwrap().display())), "the job never ran");
+
+    for extension in &["json", "script", "out", "err"] {
+        assert!(server.home.join("logs").join(format!("kraken-job-tidy.{}", extension)).exists(), "no {} file", extension);
+    }
+    assert!(!workdir.join("logs").exists());
+}
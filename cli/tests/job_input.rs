@@ -0,0 +1,115 @@
+// Feeding jobs input from a file, from what's piped into kraken and with `send job`
+
+mod mock_server;
+
+use mock_server::{stderr, MockServer};
+use std::fs;
+use std::io::Write;
+use std::process::Stdio;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn jobs_read_a_stdin_file() {
+    let server = MockServer::start("input-file");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "counted"}"#);
+    server.expect_job("counted");
+
+    let input = server.home.join("input.txt");
+    fs::write(&input, "one\ntwo\nthree\n").unwrap();
+
+    server.kraken(&["new", "job", "-n", "counted", "--stdin-file", input.to_str().unwrap(), "--", "wc", "-l"]);
+    assert!(server.printed("counted", "3"), "the job never counted its input");
+}
+
+#[test]
+fn jobs_read_what_is_piped_into_kraken() {
+    let server = MockServer::start("input-piped");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "sorted"}"#);
+    server.expect_job("sorted");
+
+    let mut kraken = server.command(&["new", "job", "-n", "sorted", "--stdin-file", "-", "sort"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    kraken.stdin.take().unwrap().write_all(b"pear\napple\n").unwrap();
+    kraken.wait().unwrap();
+
+    assert!(server.printed("sorted", "apple"), "the job never read its input");
+    assert_eq!(fs::read_to_string(server.home.join("jobs").join("kraken-job-sorted.stdin")).unwrap(), "pear\napple\n");
+}
+
+#[test]
+fn kraken_leaves_its_own_input_alone_unless_asked() {
+    let server = MockServer::start("input-idle");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "unfed"}"#);
+    server.expect_job("unfed");
+
+    // Like a script running kraken with its stdin a pipe nothing is written to yet
+    let mut kraken = server.command(&["new", "job", "-n", "unfed", "cat; echo no input"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    let _stdin = kraken.stdin.take().unwrap();
+
+    let started = Instant::now();
+    while kraken.try_wait().unwrap().is_none() {
+        if started.elapsed() > Duration::from_secs(20) {
+            let _ = kraken.kill();
+            panic!("kraken waited for input it wasn't asked to read");
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    assert!(server.printed("unfed", "no input"), "the job never ran");
+    assert!(!server.home.join("jobs").join("kraken-job-unfed.stdin").exists());
+}
+
+#[test]
+fn send_job_writes_to_a_running_job() {
+    let server = MockServer::start("input-send");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "listening"}"#);
+    server.expect_job("listening");
+
+    let mut kraken = server.command(&["new", "job", "-n", "listening", "--stdin-pipe", "while read line; do echo \"got $line\"; done; echo closed"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let pipe = server.home.join("jobs").join("kraken-job-listening.stdin-pipe");
+    let started = Instant::now();
+    while !pipe.exists() && started.elapsed() < Duration::from_secs(20) {
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    let output = server.kraken(&["send", "job", "listening", "hello there"]);
+    assert!(stderr(&output).is_empty(), "{}", stderr(&output));
+
+    let output = server.kraken(&["send", "job", "listening", "bye", "--eof"]);
+    assert!(stderr(&output).is_empty(), "{}", stderr(&output));
+
+    // The job only ends once its stdin is closed
+    let started = Instant::now();
+    while kraken.try_wait().unwrap().is_none() {
+        if started.elapsed() > Duration::from_secs(20) {
+            let _ = kraken.kill();
+            panic!("the job's stdin was never closed");
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    for line in &["got hello there", "got bye", "closed"] {
+        assert!(server.printed("listening", line), "the job never printed {}", line);
+    }
+    assert!(!pipe.exists());
+
+    let output = server.kraken(&["send", "job", "listening", "too late"]);
+    assert!(stderr(&output).contains("listening isn't reading input from kraken"), "{}", stderr(&output));
+}
@@ -0,0 +1,89 @@
+// Job names with characters that need encoding in URLs, and names already taken by another job
+
+mod mock_server;
+
+use mock_server::{stderr, stdout, MockServer};
+use std::fs;
+use std::time::Duration;
+
+fn streamed(server: &MockServer, path_name: &str) -> bool {
+    let path = format!("/logs/stream/{}", path_name);
+    server.wait_for(Duration::from_secs(20), |requests| requests.iter().any(|r| r.method == "POST" && r.path == path))
+}
+
+#[test]
+fn names_with_spaces_are_encoded() {
+    let server = MockServer::start("names-spaces");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "nightly run"}"#);
+    server.respond("GET", "/jobs/nightly%20run", 200, r#"[{"jobId": "nightly run", "line": "done"}]"#);
+    server.expect_job("nightly%20run");
+
+    server.kraken(&["new", "job", "-n", "nightly run", "echo hi"]);
+    assert!(streamed(&server, "nightly%20run"), "the job's output never arrived");
+    assert!(server.requests_to("POST", "/jobs/new")[0].body.contains(r#""name":"nightly run""#));
+
+    let output = server.kraken(&["show", "job", "nightly run"]);
+    assert!(stdout(&output).contains("done"), "{}", stderr(&output));
+}
+
+#[test]
+fn names_with_slashes_are_refused() {
+    let server = MockServer::start("names-slashes");
+    server.store_token("abc123");
+
+    let output = server.kraken(&["new", "job", "-n", "team/nightly", "true"]);
+
+    assert!(stderr(&output).contains("Invalid job name `team/nightly`: it contains /"), "{}", stderr(&output));
+    assert!(server.requests_to("POST", "/jobs/new").is_empty());
+}
+
+#[test]
+fn names_taken_on_the_server() {
+    let server = MockServer::start("names-server");
+    server.store_token("abc123");
+    server.respond("GET", "/jobs", 200, r#"[
+        {"_id": "1", "machine": "box", "name": "train", "status": "finished"},
+        {"_id": "2", "machine": "box", "name": "train-2", "status": "finished"},
+        {"_id": "3", "machine": "box", "name": "serve", "status": "running"}
+    ]"#);
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "4", "name": "train"}"#);
+    server.respond("DELETE", "/jobs/train", 200, r#"{"removed": 1}"#);
+    server.expect_job("train");
+    server.expect_job("train-3");
+
+    let output = server.kraken(&["new", "job", "-n", "train", "true"]);
+    assert!(stderr(&output).contains("A job named train already exists on the server. Use --replace"), "{}", stderr(&output));
+    assert!(server.requests_to("POST", "/jobs/new").is_empty());
+
+    let output = server.kraken(&["new", "job", "-n", "serve", "--replace", "true"]);
+    assert!(stderr(&output).contains("serve is still running, so it can't be replaced"), "{}", stderr(&output));
+    assert!(server.requests_to("DELETE", "/jobs/serve").is_empty());
+
+    server.kraken(&["new", "job", "-n", "train", "--auto-suffix", "true"]);
+    assert!(server.requests_to("POST", "/jobs/new")[0].body.contains(r#""name":"train-3""#));
+
+    server.kraken(&["new", "job", "-n", "train", "--replace", "true"]);
+    assert_eq!(server.requests_to("DELETE", "/jobs/train").len(), 1);
+    assert!(server.requests_to("POST", "/jobs/new")[1].body.contains(r#""name":"train""#));
+}
+
+#[test]
+fn names_taken_on_this_machine() {
+    let server = MockServer::start("names-local");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "backup"}"#);
+    server.expect_job("backup");
+
+    let jobs = server.home.join("jobs");
+    fs::create_dir_all(&jobs).unwrap();
+    fs::write(jobs.join("kraken-job-backup.out"), "from the last run\n").unwrap();
+
+    let output = server.kraken(&["new", "job", "-n", "backup", "true"]);
+    assert!(stderr(&output).contains("A job named backup already exists on this machine"), "{}", stderr(&output));
+    assert_eq!(fs::read_to_string(jobs.join("kraken-job-backup.out")).unwrap(), "from the last run\n");
+
+    server.kraken(&["new", "job", "-n", "backup", "--replace", "echo fresh"]);
+    assert!(streamed(&server, "backup"), "the job's output never arrived");
+    assert!(!fs::read_to_string(jobs.join("kraken-job-backup.out")).unwrap_or_default().contains("from the last run"));
+}
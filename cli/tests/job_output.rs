@@ -0,0 +1,132 @@
+// Rotating and compressing local job output, and reading it back across segments
+// `job_output` only depends on std and the compression crates, so it's pulled in directly
+// rather than through the binary.
+
+extern crate flate2;
+extern crate zstd;
+
+#[path = "../src/job_output.rs"]
+#[allow(dead_code)]
+mod job_output;
+
+use job_output::{Compression, JobOutput, RotationPolicy};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+// A fresh output dir for one test
+fn output_dir(name: &str) -> String {
+    let dir: PathBuf = env::temp_dir().join(format!("kraken-job-output-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir.to_string_lossy().into_owned()
+}
+
+fn policy(max_bytes: u64, compression: Compression) -> RotationPolicy {
+    RotationPolicy { max_bytes: max_bytes, max_age: None, compression: compression }
+}
+
+fn write(dir: &str, job_name: &str, policy: RotationPolicy, lines: &[&str]) {
+    let mut output = JobOutput::create(dir, job_name, policy).unwrap();
+    for line in lines {
+        output.write_line(line).unwrap();
+    }
+    output.flush().unwrap();
+}
+
+fn segment_names(dir: &str, job_name: &str) -> Vec<String> {
+    job_output::segments(dir, job_name).unwrap().into_iter()
+        .map(|(_, path)| path.file_name().unwrap().to_string_lossy().into_owned())
+        .collect()
+}
+
+#[test]
+fn output_is_rotated_once_a_segment_is_full() {
+    let dir = output_dir("rotate");
+
+    // Each line is 6 bytes with its newline, so every second line fills a segment
+    write(&dir, "chatty", policy(12, Compression::None), &["line1", "line2", "line3", "line4", "line5"]);
+
+    assert_eq!(segment_names(&dir, "chatty"), vec!["kraken-job-chatty.out.1", "kraken-job-chatty.out.2"]);
+    assert_eq!(fs::read_to_string(job_output::job_file_path(&dir, "chatty", "out.1")).unwrap(), "line1\nline2\n");
+    assert_eq!(fs::read_to_string(job_output::job_file_path(&dir, "chatty", "out")).unwrap(), "line5\n");
+}
+
+#[test]
+fn compressed_segments_read_back_in_order() {
+    for &(compression, extension) in &[(Compression::Gzip, "gz"), (Compression::Zstd, "zst")] {
+        let dir = output_dir(extension);
+        write(&dir, "packed", policy(12, compression), &["line1", "line2", "line3", "line4", "line5"]);
+
+        let names = segment_names(&dir, "packed");
+        assert_eq!(names, vec![format!("kraken-job-packed.out.1.{}", extension), format!("kraken-job-packed.out.2.{}", extension)]);
+
+        let lines = job_output::read_lines(&dir, "packed").unwrap();
+        assert_eq!(lines, vec!["line1", "line2", "line3", "line4", "line5"]);
+    }
+}
+
+#[test]
+fn a_new_job_with_the_same_name_starts_without_old_segments() {
+    let dir = output_dir("reused");
+    write(&dir, "again", policy(12, Compression::Gzip), &["old1", "old2", "old3", "old4", "old5"]);
+    write(&dir, "again", policy(12, Compression::Gzip), &["new1"]);
+
+    assert!(segment_names(&dir, "again").is_empty());
+    assert_eq!(job_output::read_lines(&dir, "again").unwrap(), vec!["new1"]);
+}
+
+#[test]
+fn jobs_with_similar_names_keep_their_own_segments() {
+    let dir = output_dir("similar");
+    write(&dir, "job", policy(12, Compression::None), &["a1", "a2", "a3", "a4", "a5"]);
+    write(&dir, "job-2", policy(12, Compression::None), &["b1"]);
+
+    assert_eq!(job_output::read_lines(&dir, "job").unwrap(), vec!["a1", "a2", "a3", "a4", "a5"]);
+    assert_eq!(job_output::read_lines(&dir, "job-2").unwrap(), vec!["b1"]);
+}
+
+#[test]
+fn jobs_named_like_segments_keep_their_own_files() {
+    let dir = output_dir("dotted");
+    write(&dir, "foo.out.1", policy(12, Compression::Gzip), &["b1", "b2", "b3", "b4", "b5"]);
+    write(&dir, "foo", policy(12, Compression::Gzip), &["a1", "a2", "a3", "a4", "a5"]);
+
+    // Starting `foo` leaves the live file and segments of `foo.out.1` alone
+    assert_eq!(segment_names(&dir, "foo"), vec!["kraken-job-foo.out.1.gz"]);
+    assert_eq!(segment_names(&dir, "foo.out.1"), vec!["kraken-job-foo.out.1.out.1.gz"]);
+    assert_eq!(job_output::read_lines(&dir, "foo").unwrap(), vec!["a1", "a2", "a3", "a4", "a5"]);
+    assert_eq!(job_output::read_lines(&dir, "foo.out.1").unwrap(), vec!["b1", "b2", "b3", "b4", "b5"]);
+}
+
+#[test]
+fn quiet_output_is_rotated_once_it_is_old() {
+    let dir = output_dir("quiet");
+    let policy = RotationPolicy { max_bytes: 0, max_age: Some(Duration::from_millis(100)), compression: Compression::Gzip };
+
+    let mut output = JobOutput::create(&dir, "quiet", policy).unwrap();
+    output.write_line("line1").unwrap();
+    output.tick().unwrap();
+    assert!(segment_names(&dir, "quiet").is_empty());
+
+    // No more lines arrive, but the segment still gets too old
+    thread::sleep(Duration::from_millis(200));
+    output.tick().unwrap();
+
+    assert_eq!(segment_names(&dir, "quiet"), vec!["kraken-job-quiet.out.1.gz"]);
+    assert_eq!(job_output::read_lines(&dir, "quiet").unwrap(), vec!["line1"]);
+}
+
+#[test]
+fn recent_lines_stop_at_the_covering_segment() {
+    let dir = output_dir("recent");
+    write(&dir, "long", policy(12, Compression::Gzip), &["line1", "line2", "line3", "line4", "line5"]);
+
+    // The first segment would fail to decompress if it were read
+    fs::write(job_output::job_file_path(&dir, "long", "out.1.gz"), "not gzip").unwrap();
+
+    let lines = job_output::read_recent_lines(&dir, "long", |first| first == "line3").unwrap();
+    assert_eq!(lines, vec!["line3", "line4", "line5"]);
+}
@@ -0,0 +1,146 @@
+// Running jobs on a terminal with `--tty` and attaching to them with `attach job`
+
+mod mock_server;
+
+use mock_server::{stderr, MockServer};
+use std::io::{Read, Write};
+use std::process::{Child, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Starts a `--tty` job and waits for its terminal to be shared
+fn start(server: &MockServer, job_name: &str, command: &str) -> Child {
+    let kraken = server.command(&["new", "job", "-n", job_name, "--tty", command])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let socket = server.home.join("jobs").join(format!("kraken-job-{}.tty", job_name));
+    let started = Instant::now();
+    while !socket.exists() && started.elapsed() < Duration::from_secs(20) {
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    kraken
+}
+
+// Attaches, types `keys` once `prompt` is shown and returns what `attach job` showed once it ends
+fn attach(server: &MockServer, job_name: &str, prompt: &str, keys: &[u8]) -> String {
+    let mut attached = server.command(&["attach", "job", job_name])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let shown = Arc::new(Mutex::new(String::new()));
+    let mut stdout = attached.stdout.take().unwrap();
+    let reader = {
+        let shown = shown.clone();
+        thread::spawn(move || {
+            let mut buf = [0; 1024];
+            while let Ok(read) = stdout.read(&mut buf) {
+                if read == 0 {
+                    break;
+                }
+                shown.lock().unwrap().push_str(&String::from_utf8_lossy(&buf[..read]));
+            }
+        })
+    };
+
+    let started = Instant::now();
+    while !shown.lock().unwrap().contains(prompt) {
+        if started.elapsed() > Duration::from_secs(20) {
+            let _ = attached.kill();
+            panic!("attach job never showed {:?}: {}", prompt, shown.lock().unwrap());
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    let mut stdin = attached.stdin.take().unwrap();
+    stdin.write_all(keys).unwrap();
+
+    while attached.try_wait().unwrap().is_none() {
+        if started.elapsed() > Duration::from_secs(20) {
+            let _ = attached.kill();
+            panic!("attach job never ended");
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    reader.join().unwrap();
+    let shown = shown.lock().unwrap().clone();
+    shown
+}
+
+fn wait_for_exit(kraken: &mut Child) {
+    let started = Instant::now();
+    while kraken.try_wait().unwrap().is_none() {
+        if started.elapsed() > Duration::from_secs(20) {
+            let _ = kraken.kill();
+            panic!("the job never exited");
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[test]
+fn jobs_on_a_terminal_can_be_attached_to() {
+    let server = MockServer::start("terminal-attach");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "talk"}"#);
+    server.expect_job("talk");
+
+    let mut kraken = start(&server, "talk", "tty; read line; echo \"got $line\"");
+
+    let shown = attach(&server, "talk", "/dev/pts/", b"hello\n");
+    assert!(shown.contains("/dev/pts/"), "{}", shown);
+    assert!(shown.contains("got hello"), "{}", shown);
+    assert!(shown.contains("talk exited"), "{}", shown);
+
+    wait_for_exit(&mut kraken);
+
+    // What's typed is echoed by the terminal, so it's in the output too
+    for line in &["hello", "got hello"] {
+        assert!(server.printed("talk", line), "the job never printed {}", line);
+    }
+    assert!(!server.home.join("jobs").join("kraken-job-talk.tty").exists());
+
+    let created = &server.requests_to("POST", "/jobs/new")[0];
+    assert!(created.body.contains("--tty"), "{}", created.body);
+}
+
+#[test]
+fn detaching_leaves_the_job_running() {
+    let server = MockServer::start("terminal-detach");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "waiting"}"#);
+    server.expect_job("waiting");
+
+    let mut kraken = start(&server, "waiting", "echo ready; read line; echo \"got $line\"");
+
+    let shown = attach(&server, "waiting", "ready", b"\x10\x11");
+    assert!(shown.contains("ready"), "{}", shown);
+    assert!(shown.contains("Detached from waiting"), "{}", shown);
+    assert!(kraken.try_wait().unwrap().is_none(), "the job exited when detached from");
+
+    // Running out of input detaches too
+    let output = server.kraken(&["attach", "job", "waiting"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Detached from waiting"), "{}", stderr(&output));
+    assert!(kraken.try_wait().unwrap().is_none(), "the job exited when its input ran out");
+
+    let shown = attach(&server, "waiting", "ready", b"done\n");
+    assert!(shown.contains("got done"), "{}", shown);
+
+    wait_for_exit(&mut kraken);
+    assert!(server.printed("waiting", "got done"), "the job never printed its input");
+}
+
+#[test]
+fn jobs_without_a_terminal_cant_be_attached_to() {
+    let server = MockServer::start("terminal-none");
+
+    let output = server.kraken(&["attach", "job", "plain"]);
+    assert!(stderr(&output).contains("plain isn't running with a terminal"), "{}", stderr(&output));
+}
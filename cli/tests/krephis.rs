@@ -0,0 +1,599 @@
+// Every `krephis` endpoint, exercised through the CLI against the mock server
+
+mod mock_server;
+
+use mock_server::{stderr, stdout, MockServer};
+use std::fs;
+use std::time::Duration;
+
+// Login
+
+#[test]
+fn login_stores_token() {
+    let server = MockServer::start("login");
+    server.respond("POST", "/auth/login", 200, r#"{"auth": true, "token": "abc123"}"#);
+
+    let output = server.kraken(&["login", "me@example.com", "hunter2"]);
+    assert!(output.status.success());
+
+    let token = fs::read_to_string(server.home.join(".krakenrc")).unwrap();
+    assert_eq!(token, "abc123\n");
+
+    let requests = server.requests_to("POST", "/auth/login");
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].header("content-type"), Some("application/json"));
+    assert!(requests[0].body.contains("\"email\":\"me@example.com\""));
+    assert!(requests[0].body.contains("\"password\":\"hunter2\""));
+}
+
+#[test]
+fn login_server_error() {
+    let server = MockServer::start("login-500");
+    server.respond("POST", "/auth/login", 500, r#"{"auth": false, "message": "database is down"}"#);
+
+    let output = server.kraken(&["login", "me@example.com", "hunter2"]);
+
+    assert!(stderr(&output).contains("Server error: database is down"));
+    assert!(!server.home.join(".krakenrc").exists());
+}
+
+#[test]
+fn login_malformed_json() {
+    let server = MockServer::start("login-malformed");
+    server.respond("POST", "/auth/login", 200, "<html>definitely not json</html>");
+
+    let output = server.kraken(&["login", "me@example.com", "hunter2"]);
+
+    assert!(stderr(&output).contains("json parsing error"));
+    assert!(!server.home.join(".krakenrc").exists());
+}
+
+// Show
+
+#[test]
+fn show_jobs_prints_table() {
+    let server = MockServer::start("show-jobs");
+    server.store_token("abc123");
+    server.respond("GET", "/jobs", 200, r#"[
+        {"_id": "1", "machine": "box", "name": "train-model", "description": "resnet", "status": "running"},
+        {"_id": "2", "machine": "box", "name": "backup", "description": null, "status": null}
+    ]"#);
+
+    let output = server.kraken(&["show", "jobs"]);
+    let out = stdout(&output);
+
+    assert!(out.contains("train-model"));
+    assert!(out.contains("resnet"));
+    assert!(out.contains("backup"));
+    assert_eq!(server.requests_to("GET", "/jobs")[0].header("x-access-token"), Some("abc123"));
+}
+
+#[test]
+fn show_jobs_flags_warnings() {
+    let server = MockServer::start("show-jobs-warning");
+    server.store_token("abc123");
+    server.respond("GET", "/jobs", 200, r#"[
+        {"_id": "1", "machine": "box", "name": "train-model", "status": "failed", "warning": "matched Traceback: Traceback (most recent call last):"}
+    ]"#);
+
+    let output = server.kraken(&["show", "jobs"]);
+
+    assert!(stdout(&output).contains("failed (matched Traceback: Traceback"), "{}", stdout(&output));
+}
+
+#[test]
+fn show_jobs_filters_by_tag() {
+    let server = MockServer::start("show-jobs-tag");
+    server.store_token("abc123");
+    server.respond("GET", "/jobs", 200, r#"[
+        {"_id": "1", "machine": "box", "name": "train-model", "status": "running", "labels": {"owner": "me", "project": "resnet"}},
+        {"_id": "2", "machine": "box", "name": "train-other", "status": "running", "labels": {"project": "bert"}},
+        {"_id": "3", "machine": "box", "name": "backup", "status": "done"}
+    ]"#);
+
+    let output = server.kraken(&["show", "jobs", "--tag", "project=resnet"]);
+    let out = stdout(&output);
+
+    assert!(out.contains("owner=me, project=resnet"), "{}", out);
+    assert!(!out.contains("train-other") && !out.contains("backup"), "{}", out);
+
+    // A name alone matches any value, and every tag has to match
+    let out = stdout(&server.kraken(&["show", "jobs", "--tag", "project"]));
+    assert!(out.contains("train-model") && out.contains("train-other") && !out.contains("backup"), "{}", out);
+
+    let output = server.kraken(&["show", "jobs", "--tag", "project", "--tag", "owner=you"]);
+    assert!(stderr(&output).contains("No jobs tagged project, owner=you"), "{}", stderr(&output));
+}
+
+#[test]
+fn show_group_aggregates_its_jobs() {
+    let server = MockServer::start("show-group");
+    server.store_token("abc123");
+    server.respond("GET", "/jobs", 200, r#"[
+        {"_id": "1", "machine": "alpha", "name": "alpha-fleet", "status": "done", "group": "fleet", "exitCode": 0},
+        {"_id": "2", "machine": "beta", "name": "beta-fleet", "status": "failed", "group": "fleet", "exitCode": 3},
+        {"_id": "3", "machine": "gamma", "name": "gamma-fleet", "status": "running", "group": "fleet"},
+        {"_id": "4", "machine": "alpha", "name": "other", "status": "running", "group": "other"}
+    ]"#);
+
+    let output = server.kraken(&["show", "group", "fleet"]);
+    let out = stdout(&output);
+
+    let row = out.lines().find(|l| l.contains("beta-fleet")).unwrap();
+    let cells: Vec<&str> = row.split('|').map(|c| c.trim()).filter(|c| !c.is_empty()).collect();
+    assert_eq!(cells, vec!["beta-fleet", "beta", "failed", "3"]);
+    assert!(!out.contains("other"), "{}", out);
+    assert!(out.contains("3 jobs: 1 done, 1 failed, 1 running"), "{}", out);
+
+    let output = server.kraken(&["show", "group", "missing"]);
+    assert!(stderr(&output).contains("No jobs in group missing"));
+}
+
+#[test]
+fn calling_show_jobs_with_invalid_token() {
+    let server = MockServer::start("show-jobs-401");
+    server.store_token("expired");
+    server.respond("GET", "/jobs", 401, r#"{"auth": false, "message": "Failed to authenticate token."}"#);
+
+    let output = server.kraken(&["show", "jobs"]);
+
+    assert!(stderr(&output).contains("Failed to authenticate token."));
+}
+
+#[test]
+fn show_jobs_without_token() {
+    let server = MockServer::start("show-jobs-no-token");
+
+    let output = server.kraken(&["show", "jobs"]);
+
+    assert!(stderr(&output).contains("Run `kraken login`"));
+    assert!(server.requests().is_empty());
+}
+
+#[test]
+fn show_jobs_malformed_json() {
+    let server = MockServer::start("show-jobs-malformed");
+    server.store_token("abc123");
+    server.respond("GET", "/jobs", 200, r#"[{"name": "missing the other fields"}]"#);
+
+    let output = server.kraken(&["show", "jobs"]);
+
+    assert!(stderr(&output).contains("json parsing error"));
+}
+
+#[test]
+fn show_job_prints_lines() {
+    let server = MockServer::start("show-job");
+    server.store_token("abc123");
+    server.respond("GET", "/jobs/train-model", 200, r#"[
+        {"jobId": "train-model", "line": "epoch 1", "seq": 1, "timestamp": "2020-01-01T00:00:00.000Z", "stream": "stdout"},
+        {"jobId": "train-model", "line": "epoch 2"}
+    ]"#);
+
+    let output = server.kraken(&["show", "job", "train-model", "25"]);
+    let out = stdout(&output);
+
+    assert!(out.contains("epoch 1"));
+    assert!(out.contains("epoch 2"));
+
+    let request = &server.requests_to("GET", "/jobs/train-model")[0];
+    assert_eq!(request.header("x-line-limit"), Some("25"));
+    assert_eq!(request.header("x-access-token"), Some("abc123"));
+}
+
+#[test]
+fn show_job_with_timestamps() {
+    let server = MockServer::start("show-job-timestamps");
+    server.store_token("abc123");
+    server.respond("GET", "/jobs/train-model", 200, r#"[
+        {"jobId": "train-model", "line": "epoch 1", "seq": 1, "timestamp": "2020-01-01T00:00:00.000Z", "stream": "stderr"}
+    ]"#);
+
+    let output = server.kraken(&["show", "job", "train-model", "--timestamps"]);
+
+    assert!(stdout(&output).contains("2020-01-01T00:00:00.000Z [stderr] epoch 1"));
+}
+
+#[test]
+fn show_job_not_found() {
+    let server = MockServer::start("show-job-404");
+    server.store_token("abc123");
+    server.respond("GET", "/jobs/nope", 404, r#"{"message": "No job named nope"}"#);
+
+    let output = server.kraken(&["show", "job", "nope"]);
+
+    assert!(stderr(&output).contains("Server error: No job named nope"));
+}
+
+// New job
+
+// The mock has no streaming endpoint, so this also covers falling back to HTTP
+#[test]
+fn new_job_ships_logs() {
+    let server = MockServer::start("new-job");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "mock-job"}"#);
+    server.respond("POST", "/logs/new", 200, r#"{"ok": true}"#);
+
+    server.kraken(&["new", "job", "-n", "mock-job", "-d", "from the tests", "--tag", "project=resnet", "--tag", "owner=me", "echo hello; echo oops 1>&2"]);
+
+    let finished = server.wait_for(Duration::from_secs(20), |requests| {
+        requests.iter().any(|r| r.path == "/logs/new" && r.body.contains("\"line\":\"exit\""))
+    });
+    assert!(finished, "the job never sent its exit line");
+
+    let job = &server.requests_to("POST", "/jobs/new")[0];
+    assert!(job.body.contains("\"name\":\"mock-job\""));
+    assert!(job.body.contains("\"description\":\"from the tests\""));
+    assert!(job.body.contains(r#""labels":{"owner":"me","project":"resnet"}"#), "{}", job.body);
+
+    let record = fs::read_to_string(server.home.join("jobs").join("kraken-job-mock-job.json")).unwrap();
+    assert!(record.contains(r#""project": "resnet""#), "{}", record);
+
+    let logs = server.requests_to("POST", "/logs/new");
+    assert!(logs.iter().all(|r| r.header("x-access-token") == Some("abc123")));
+    assert!(logs.iter().all(|r| r.body.contains("\"jobName\":\"mock-job\"")));
+    assert!(logs.iter().any(|r| r.body.contains("\"line\":\"hello\"") && r.body.contains("\"stream\":\"stdout\"")));
+    assert!(logs.iter().any(|r| r.body.contains("\"line\":\"oops\"") && r.body.contains("\"stream\":\"stderr\"")));
+}
+
+#[test]
+fn new_job_streams_logs() {
+    let server = MockServer::start("new-job-stream");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "stream-job"}"#);
+    server.respond("GET", "/logs/stream/stream-job", 200, r#"{"acked": 0}"#);
+    server.respond("POST", "/logs/stream/stream-job", 200, r#"{"acked": 1000}"#);
+
+    server.kraken(&["new", "job", "-n", "stream-job", "echo hello"]);
+
+    let finished = server.wait_for(Duration::from_secs(20), |requests| {
+        requests.iter().any(|r| r.path == "/logs/stream/stream-job" && r.body.contains("\"line\":\"exit\""))
+    });
+    assert!(finished, "the job never streamed its exit line");
+
+    let streams = server.requests_to("POST", "/logs/stream/stream-job");
+    assert_eq!(streams[0].header("transfer-encoding"), Some("chunked"));
+    assert!(streams.iter().any(|r| r.body.contains("\"line\":\"hello\"")));
+    assert!(server.requests_to("POST", "/logs/new").is_empty());
+}
+
+// A JSON 404 comes from a server that can stream, so it's retried rather than a reason to fall back
+#[test]
+fn new_job_retries_stream_errors_from_the_server() {
+    let server = MockServer::start("new-job-stream-retry");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "retry-job"}"#);
+    server.respond("GET", "/logs/stream/retry-job", 404, r#"{"message": "No job named retry-job"}"#);
+    server.respond("POST", "/logs/new", 200, r#"{"ok": true}"#);
+
+    server.kraken(&["new", "job", "-n", "retry-job", "echo hello"]);
+
+    let retried = server.wait_for(Duration::from_secs(20), |requests| {
+        requests.iter().filter(|r| r.method == "GET" && r.path == "/logs/stream/retry-job").count() > 3
+    });
+    assert!(retried, "the shipper stopped asking for the stream ack");
+
+    server.expect_job("retry-job");
+
+    let finished = server.wait_for(Duration::from_secs(20), |requests| {
+        requests.iter().any(|r| r.path == "/logs/stream/retry-job" && r.body.contains("\"line\":\"exit\""))
+    });
+    assert!(finished, "the job never streamed its exit line");
+    assert!(server.requests_to("POST", "/logs/new").is_empty());
+}
+
+#[test]
+fn new_job_reports_progress() {
+    let server = MockServer::start("new-job-progress");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "progress-job"}"#);
+    server.respond("GET", "/logs/stream/progress-job", 200, r#"{"acked": 0}"#);
+    server.respond("POST", "/logs/stream/progress-job", 200, r#"{"acked": 1000}"#);
+    server.respond("POST", "/jobs/progress-job/progress", 200, r#"{"ok": true}"#);
+
+    server.kraken(&["new", "job", "-n", "progress-job", r#"echo '##kraken progress 3/4 "almost"'; echo done"#]);
+
+    let finished = server.wait_for(Duration::from_secs(20), |requests| {
+        requests.iter().any(|r| r.path == "/logs/stream/progress-job" && r.body.contains("\"line\":\"exit\""))
+    });
+    assert!(finished, "the job never streamed its exit line");
+
+    let reports = server.requests_to("POST", "/jobs/progress-job/progress");
+    assert!(reports.iter().any(|r| r.body.contains("\"current\":3") && r.body.contains("\"message\":\"almost\"")));
+
+    // Reports aren't part of the job's output
+    let streams = server.requests_to("POST", "/logs/stream/progress-job");
+    assert!(streams.iter().any(|r| r.body.contains("\"line\":\"done\"")));
+    assert!(!streams.iter().any(|r| r.body.contains("\"line\":\"##kraken")));
+}
+
+#[test]
+fn show_job_prints_progress() {
+    let server = MockServer::start("show-job-progress");
+    server.store_token("abc123");
+    server.respond("GET", "/jobs/train", 200, r#"[{"jobId": "train", "line": "epoch 3 done"}]"#);
+    server.respond("GET", "/jobs/train/progress", 200, r#"{"current": 3, "total": 4, "message": "epoch 3", "updatedAt": "2019-01-01T00:00:00.000Z"}"#);
+
+    let output = server.kraken(&["show", "job", "train"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(stdout.contains("epoch 3 done"));
+    assert!(stdout.contains("Progress: 3/4 (75%) epoch 3"), "{}", stdout);
+}
+
+#[test]
+fn new_job_reports_metrics_and_uploads_artifacts() {
+    let server = MockServer::start("new-job-metrics");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "metrics-job"}"#);
+    server.respond("GET", "/logs/stream/metrics-job", 200, r#"{"acked": 0}"#);
+    server.respond("POST", "/logs/stream/metrics-job", 200, r#"{"acked": 1000}"#);
+    server.respond("POST", "/jobs/metrics-job/metrics", 200, r#"{"ok": true}"#);
+    server.respond("POST", "/jobs/metrics-job/artifacts/model.txt", 200, r#"{"name": "model.txt", "size": 8}"#);
+
+    let model = server.home.join("model.txt");
+    let command = format!("echo weights > {0}; echo '##kraken metric step=1 loss=0.5 acc=0.75'; echo '##kraken artifact {0}'", model.display());
+    server.kraken(&["new", "job", "-n", "metrics-job", "--upload-artifacts", &command]);
+
+    let uploaded = server.wait_for(Duration::from_secs(20), |requests| {
+        requests.iter().any(|r| r.path == "/jobs/metrics-job/artifacts/model.txt")
+    });
+    assert!(uploaded, "the artifact was never uploaded");
+
+    let upload = &server.requests_to("POST", "/jobs/metrics-job/artifacts/model.txt")[0];
+    assert_eq!(upload.body, "weights\n");
+
+    let batches = server.requests_to("POST", "/jobs/metrics-job/metrics");
+    assert!(batches.iter().any(|r| r.body.contains("\"name\":\"loss\",\"value\":0.5,\"step\":1")));
+
+    // Metrics are kept locally too
+    let output = server.kraken(&["show", "job", "metrics-job", "--metrics", "--csv", "--local"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("timestamp,step,name,value\n"), "{}", stdout);
+    assert!(stdout.contains(",1,acc,0.75"), "{}", stdout);
+}
+
+#[test]
+fn artifacts_with_the_same_file_name_are_kept_apart() {
+    let server = MockServer::start("new-job-artifact-names");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "twins"}"#);
+    server.respond("GET", "/logs/stream/twins", 200, r#"{"acked": 0}"#);
+    server.respond("POST", "/logs/stream/twins", 200, r#"{"acked": 1000}"#);
+    server.respond("POST", "/jobs/twins/artifacts/model.pt", 200, r#"{"name": "model.pt", "size": 2}"#);
+    server.respond("POST", "/jobs/twins/artifacts/model-2.pt", 200, r#"{"name": "model-2.pt", "size": 2}"#);
+
+    let a = server.home.join("a");
+    let b = server.home.join("b");
+    let command = format!(
+        "mkdir -p {0} {1}; echo a > {0}/model.pt; echo b > {1}/model.pt; echo '##kraken artifact {0}/model.pt'; echo '##kraken artifact {1}/model.pt'",
+        a.display(),
+        b.display()
+    );
+    server.kraken(&["new", "job", "-n", "twins", "--upload-artifacts", &command]);
+
+    let uploaded = server.wait_for(Duration::from_secs(20), |requests| {
+        requests.iter().any(|r| r.path == "/jobs/twins/artifacts/model-2.pt")
+    });
+    assert!(uploaded, "the second artifact was never uploaded under its own name");
+
+    assert_eq!(server.requests_to("POST", "/jobs/twins/artifacts/model.pt")[0].body, "a\n");
+    assert_eq!(server.requests_to("POST", "/jobs/twins/artifacts/model-2.pt")[0].body, "b\n");
+}
+
+#[test]
+fn metrics_that_arent_numbers_stay_in_the_output() {
+    let server = MockServer::start("new-job-nan-metrics");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "nan-job"}"#);
+    server.respond("GET", "/logs/stream/nan-job", 200, r#"{"acked": 0}"#);
+    server.respond("POST", "/logs/stream/nan-job", 200, r#"{"acked": 1000}"#);
+    server.respond("POST", "/jobs/nan-job/metrics", 200, r#"{"ok": true}"#);
+
+    let command = "echo '##kraken metric loss=NaN'; echo '##kraken metric loss=-inf'; echo '##kraken metric loss=0.5'";
+    server.kraken(&["new", "job", "-n", "nan-job", command]);
+
+    for line in &["##kraken metric loss=NaN", "##kraken metric loss=-inf"] {
+        let shown = server.wait_for(Duration::from_secs(20), |requests| {
+            requests.iter().any(|r| r.path == "/logs/stream/nan-job" && r.body.contains(line))
+        });
+        assert!(shown, "{} was taken out of the output", line);
+    }
+
+    let output = server.kraken(&["show", "job", "nan-job", "--metrics", "--csv", "--local"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().count(), 2, "{}", stdout);
+    assert!(stdout.contains(",loss,0.5"), "{}", stdout);
+}
+
+#[test]
+fn show_job_metrics_summarizes() {
+    let server = MockServer::start("show-job-metrics");
+    server.store_token("abc123");
+    server.respond("GET", "/jobs/train/metrics", 200, r#"[
+        {"name": "loss", "value": 0.9, "step": 1, "timestamp": "2019-01-01T00:00:00.000Z"},
+        {"name": "loss", "value": 0.2, "step": 2, "timestamp": "2019-01-01T00:01:00.000Z"},
+        {"name": "loss", "value": 0.4, "step": 3, "timestamp": "2019-01-01T00:02:00.000Z"}
+    ]"#);
+    server.respond("GET", "/jobs/train/artifacts", 200, r#"[{"name": "model.pt", "size": 2048}]"#);
+
+    let output = server.kraken(&["show", "job", "train", "--metrics"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    let row = stdout.lines().find(|l| l.contains("loss")).unwrap();
+    let cells: Vec<&str> = row.split('|').map(|c| c.trim()).filter(|c| !c.is_empty()).collect();
+    assert_eq!(cells, vec!["loss", "0.4", "0.2", "0.9", "3"]);
+    assert!(stdout.contains("model.pt"));
+}
+
+#[test]
+fn new_job_reports_resources() {
+    let server = MockServer::start("new-job-resources");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "resources-job"}"#);
+    server.respond("GET", "/logs/stream/resources-job", 200, r#"{"acked": 0}"#);
+    server.respond("POST", "/logs/stream/resources-job", 200, r#"{"acked": 1000}"#);
+    server.respond("POST", "/jobs/resources-job/resources", 200, r#"{"ok": true}"#);
+
+    server.kraken(&["new", "job", "-n", "resources-job", "--sample-interval", "1", "sleep 3 & sleep 3; wait"]);
+
+    let reported = server.wait_for(Duration::from_secs(20), |requests| {
+        requests.iter().any(|r| r.path == "/jobs/resources-job/resources")
+    });
+    assert!(reported, "resource usage was never sent");
+
+    let summary = &server.requests_to("POST", "/jobs/resources-job/resources")[0];
+    assert!(summary.body.contains("\"peakProcesses\":3"), "{}", summary.body);
+
+    // The summary and the exit are saved together, so neither is lost
+    let record = fs::read_to_string(server.home.join("jobs").join("kraken-job-resources-job.json")).unwrap();
+    assert!(record.contains(r#""status": "done""#), "{}", record);
+    assert!(record.contains(r#""peakProcesses": 3"#), "{}", record);
+
+    // Samples are kept locally too
+    let output = server.kraken(&["show", "job", "resources-job", "--resources", "--local"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Memory"), "{}", stdout);
+    assert!(stdout.contains("samples)"), "{}", stdout);
+}
+
+#[test]
+fn show_job_resources_prints_summary() {
+    let server = MockServer::start("show-job-resources");
+    server.store_token("abc123");
+    server.respond("GET", "/jobs/train/resources", 200, r#"{"samples": 12, "durationSecs": 55, "peakCpuPercent": 180.5,
+        "avgCpuPercent": 97.2, "peakRssBytes": 3145728, "avgRssBytes": 1048576, "readBytes": 0, "writeBytes": 2048, "peakProcesses": 3}"#);
+
+    let output = server.kraken(&["show", "job", "train", "--resources"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    let row = stdout.lines().find(|l| l.contains("CPU")).unwrap();
+    let cells: Vec<&str> = row.split('|').map(|c| c.trim()).filter(|c| !c.is_empty()).collect();
+    assert_eq!(cells, vec!["CPU", "180.5%", "97.2%"]);
+    assert!(stdout.contains("3.0 MiB"), "{}", stdout);
+    assert!(stdout.contains("wrote 2.0 KiB over 55s (12 samples)"), "{}", stdout);
+}
+
+// Remove
+
+#[test]
+fn remove_job_sends_delete() {
+    let server = MockServer::start("remove-job");
+    server.store_token("abc123");
+    server.respond("GET", "/jobs", 200, r#"[{"_id": "1", "machine": "elsewhere", "name": "old-job", "status": "done"}]"#);
+    server.respond("DELETE", "/jobs/old-job", 200, r#"{"removed": 1}"#);
+
+    let output = server.kraken(&["remove", "job", "old-job"]);
+
+    assert!(output.status.success());
+    assert_eq!(server.requests_to("DELETE", "/jobs/old-job").len(), 1);
+}
+
+#[test]
+fn remove_job_refuses_running_job() {
+    let server = MockServer::start("remove-running-job");
+    server.store_token("abc123");
+    server.respond("GET", "/jobs", 200, r#"[{"_id": "1", "machine": "elsewhere", "name": "busy", "status": "running"}]"#);
+
+    let output = server.kraken(&["remove", "job", "busy"]);
+
+    assert!(stderr(&output).contains("still running"));
+    assert!(server.requests_to("DELETE", "/jobs/busy").is_empty());
+}
+
+#[test]
+fn remove_all_with_yes() {
+    let server = MockServer::start("remove-all");
+    server.store_token("abc123");
+    server.respond("GET", "/jobs", 200, r#"[
+        {"_id": "1", "machine": "a", "name": "one", "status": "done"},
+        {"_id": "2", "machine": "b", "name": "two", "status": "failed"}
+    ]"#);
+    server.respond("DELETE", "/jobs/one", 200, r#"{"removed": 1}"#);
+    server.respond("DELETE", "/jobs/two", 200, r#"{"removed": 1}"#);
+
+    let output = server.kraken(&["remove", "job", "--all", "--yes"]);
+
+    // Jobs are removed by name, so one started after the list was shown is left alone
+    assert_eq!(server.requests_to("DELETE", "/jobs/one").len(), 1);
+    assert_eq!(server.requests_to("DELETE", "/jobs/two").len(), 1);
+    assert!(server.requests_to("POST", "/jobs/remove-all").is_empty());
+    assert!(stdout(&output).contains("Removed 2 of 2 jobs"), "{}", stdout(&output));
+}
+
+#[test]
+fn remove_all_counts_only_removed_jobs() {
+    let server = MockServer::start("remove-all-partial");
+    server.store_token("abc123");
+    server.respond("GET", "/jobs", 200, r#"[
+        {"_id": "1", "machine": "a", "name": "one", "status": "done"},
+        {"_id": "2", "machine": "b", "name": "two", "status": "done"}
+    ]"#);
+    server.respond("DELETE", "/jobs/one", 200, r#"{"removed": 1}"#);
+    server.respond("DELETE", "/jobs/two", 500, r#"{"message": "boom"}"#);
+
+    let output = server.kraken(&["remove", "job", "--all", "--yes"]);
+
+    assert!(stdout(&output).contains("Removed 1 of 2 jobs"), "{}", stdout(&output));
+    assert!(stderr(&output).contains("two"), "{}", stderr(&output));
+}
+
+#[test]
+fn remove_all_scoped_to_machine() {
+    let server = MockServer::start("remove-all-machine");
+    server.store_token("abc123");
+    server.respond("GET", "/jobs", 200, r#"[
+        {"_id": "1", "machine": "a", "name": "one", "status": "done"},
+        {"_id": "2", "machine": "b", "name": "two", "status": "done"}
+    ]"#);
+    server.respond("DELETE", "/jobs/one", 200, r#"{"removed": 1}"#);
+
+    server.kraken(&["remove", "job", "--all", "--yes", "--machine", "a"]);
+
+    assert_eq!(server.requests_to("DELETE", "/jobs/one").len(), 1);
+    assert!(server.requests_to("DELETE", "/jobs/two").is_empty());
+    assert!(server.requests_to("POST", "/jobs/remove-all").is_empty());
+}
+
+#[test]
+fn remove_jobs_by_tag() {
+    let server = MockServer::start("remove-tag");
+    server.store_token("abc123");
+    server.respond("GET", "/jobs", 200, r#"[
+        {"_id": "1", "machine": "a", "name": "one", "status": "done", "labels": {"experiment": "lr-sweep"}},
+        {"_id": "2", "machine": "a", "name": "two", "status": "done", "labels": {"experiment": "baseline"}},
+        {"_id": "3", "machine": "b", "name": "three", "status": "done"}
+    ]"#);
+    server.respond("DELETE", "/jobs/one", 200, r#"{"removed": 1}"#);
+
+    server.kraken(&["remove", "job", "--tag", "experiment=lr-sweep", "--yes"]);
+
+    assert_eq!(server.requests_to("DELETE", "/jobs/one").len(), 1);
+    assert!(server.requests_to("DELETE", "/jobs/two").is_empty());
+    assert!(server.requests_to("DELETE", "/jobs/three").is_empty());
+    assert!(server.requests_to("POST", "/jobs/remove-all").is_empty());
+}
+
+#[test]
+fn remove_all_needs_confirmation() {
+    let server = MockServer::start("remove-all-unconfirmed");
+    server.store_token("abc123");
+    server.respond("GET", "/jobs", 200, r#"[{"_id": "1", "machine": "a", "name": "one", "status": "done"}]"#);
+
+    let output = server.kraken(&["remove", "job", "--all"]);
+
+    assert!(stdout(&output).contains("Nothing removed"));
+    assert!(server.requests_to("POST", "/jobs/remove-all").is_empty());
+}
+
+#[test]
+fn restore_job_after_undo_window() {
+    let server = MockServer::start("restore-expired");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/old-job/restore", 410, r#"{"message": "The undo window has expired"}"#);
+
+    let output = server.kraken(&["restore", "job", "old-job"]);
+
+    assert!(stderr(&output).contains("undo window"));
+}
@@ -3,6 +3,11 @@ extern crate assert_cli;
 #[cfg(test)]
 mod integration {
     use assert_cli;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpStream;
+    use std::process::{Command, Stdio};
+    use std::thread;
+    use std::time::Duration;
 
     #[test]
     fn calling_logout() {
@@ -12,10 +17,129 @@ mod integration {
     }
 
     #[test]
-    fn calling_show_jobs_with_invalid_token() {
+    fn calling_remove_job_without_name_fails() {
         assert_cli::Assert::main_binary()
-            .with_args(&["show", "jobs"])
+            .with_args(&["remove", "job"])
+            .fails()
+            .stderr().contains("JOB_NAME")
             .unwrap();
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn calling_prune_dry_run_keeps_files() {
+        let dir = std::env::temp_dir().join("kraken-test-prune");
+        let _ = std::fs::create_dir_all(&dir);
+
+        let output_file = dir.join("kraken-job-old-job.out");
+        std::fs::write(&output_file, "exit\n").unwrap();
+
+        assert_cli::Assert::main_binary()
+            .with_args(&["prune", "--dry-run", "--output-dir", dir.to_str().unwrap()])
+            .succeeds()
+            .stdout().contains("Would remove")
+            .unwrap();
+
+        assert!(output_file.exists());
+    }
+
+    #[test]
+    fn prune_keeps_the_newest_of_the_filtered_jobs() {
+        let dir = std::env::temp_dir().join("kraken-test-prune-keep-last");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // Oldest first: two failed jobs, then a newer done one
+        for &(name, status, age) in &[("failed-old", "failed", "300"), ("failed-new", "failed", "200"), ("done-newest", "done", "100")] {
+            let record = dir.join(format!("kraken-job-{}.json", name));
+            std::fs::write(&record, format!(r#"{{
+                "name": "{}", "machine": "test", "description": "", "command": "true",
+                "status": "{}", "started_at": 0, "finished_at": 1, "exit_code": 1
+            }}"#, name, status)).unwrap();
+
+            let stamp = format!("@{}", unix_now() - age.parse::<u64>().unwrap());
+            assert!(Command::new("touch").arg("-d").arg(stamp).arg(&record).status().unwrap().success());
+        }
+
+        let output = Command::new(env!("CARGO_BIN_EXE_kraken"))
+            .args(&["prune", "--dry-run", "--status", "failed", "--keep-last", "1", "--output-dir", dir.to_str().unwrap()])
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+
+        assert!(stdout.contains("kraken-job-failed-old.json"), "{}", stdout);
+        assert!(!stdout.contains("kraken-job-failed-new.json"), "{}", stdout);
+        assert!(!stdout.contains("kraken-job-done-newest.json"), "{}", stdout);
+    }
+
+    fn unix_now() -> u64 {
+        std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs()
+    }
+
+    #[test]
+    fn calling_prune_on_running_jobs_fails() {
+        assert_cli::Assert::main_binary()
+            .with_args(&["prune", "--status", "running"])
+            .fails()
+            .stderr().contains("isn't a valid value")
+            .unwrap();
+    }
+
+    #[test]
+    fn top_without_a_terminal_fails() {
+        let output = Command::new(env!("CARGO_BIN_EXE_kraken"))
+            .args(&["top", "--local"])
+            .output()
+            .unwrap();
+
+        assert!(String::from_utf8_lossy(&output.stderr).contains("needs a terminal"));
+    }
+
+    #[test]
+    fn serve_lists_local_jobs() {
+        let dir = std::env::temp_dir().join("kraken-test-serve");
+        let _ = std::fs::create_dir_all(&dir);
+
+        std::fs::write(dir.join("kraken-job-served-job.json"), r#"{
+            "name": "served-job", "machine": "test", "description": "", "command": "true",
+            "status": "done", "started_at": 0, "finished_at": 1, "exit_code": 0
+        }"#).unwrap();
+        std::fs::write(dir.join("kraken-job-served-job.out"), "1\t2020-01-01T00:00:00.000Z\tstdout\thello\n").unwrap();
+
+        // Port 0 lets the OS pick a free port, which the dashboard prints once it's bound
+        let mut server = Command::new(env!("CARGO_BIN_EXE_kraken"))
+            .args(&["serve", "--port", "0", "--output-dir", dir.to_str().unwrap()])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let mut banner = String::new();
+        BufReader::new(server.stdout.take().unwrap()).read_line(&mut banner).unwrap();
+        let addr = banner.trim().rsplit("http://").next().unwrap().to_string();
+
+        let get = |path: &str| -> Option<String> {
+            let mut stream = TcpStream::connect(addr.as_str()).ok()?;
+            write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path).ok()?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response).ok()?;
+            Some(response)
+        };
+
+        let mut jobs = None;
+        for _ in 0..50 {
+            jobs = get("/api/jobs");
+            if jobs.is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        let logs = get("/api/jobs/served-job/logs?after=0");
+        let _ = server.kill();
+
+        let jobs = jobs.unwrap();
+        assert!(jobs.contains("\"served-job\""));
+        assert!(!jobs.contains("\"command\""), "{}", jobs);
+        assert!(logs.unwrap().contains("\"hello\""));
+    }
+
+}
@@ -0,0 +1,246 @@
+// In-process mock of the Kraken server
+// Records every request it receives and answers with scripted responses, so the CLI can be
+// tested against every `krephis` endpoint without the hosted server.
+
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{Command, Output};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl RecordedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|&&(ref k, _)| k.eq_ignore_ascii_case(name))
+            .map(|&(_, ref v)| v.as_str())
+    }
+}
+
+struct Route {
+    method: String,
+    path: String,
+    status: u16,
+    body: String,
+}
+
+#[derive(Default)]
+struct State {
+    routes: Vec<Route>,
+    requests: Vec<RecordedRequest>,
+}
+
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    // Stands in for `~` so the CLI reads and writes its own `.krakenrc`
+    pub home: PathBuf,
+}
+
+impl MockServer {
+    pub fn start(name: &str) -> MockServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let accept_state = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if let Ok(stream) = stream {
+                    let state = accept_state.clone();
+                    thread::spawn(move || handle(stream, &state));
+                }
+            }
+        });
+
+        let home = env::temp_dir().join(format!("kraken-mock-{}", name));
+        let _ = fs::remove_dir_all(&home);
+        fs::create_dir_all(&home).unwrap();
+
+        MockServer { addr: addr, state: state, home: home }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Answers `method path` with `status` and `body`. Later scripts win over earlier ones.
+    pub fn respond(&self, method: &str, path: &str, status: u16, body: &str) -> &MockServer {
+        self.state.lock().unwrap().routes.push(Route {
+            method: method.to_string(),
+            path: path.to_string(),
+            status: status,
+            body: body.to_string(),
+        });
+        self
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    pub fn requests_to(&self, method: &str, path: &str) -> Vec<RecordedRequest> {
+        self.requests().into_iter().filter(|r| r.method == method && r.path == path).collect()
+    }
+
+    /// Polls until `done` returns true for the recorded requests, or gives up after `timeout`
+    pub fn wait_for<F: Fn(&[RecordedRequest]) -> bool>(&self, timeout: Duration, done: F) -> bool {
+        let start = Instant::now();
+
+        while start.elapsed() < timeout {
+            if done(&self.requests()) {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        false
+    }
+
+    pub fn store_token(&self, token: &str) {
+        fs::write(self.home.join(".krakenrc"), format!("{}\n", token)).unwrap();
+    }
+
+    /// Runs the kraken binary against this server, with stdin closed
+    pub fn kraken(&self, args: &[&str]) -> Output {
+        self.command(args).output().unwrap()
+    }
+
+    /// The kraken command `kraken` runs, for tests that give it input or leave it running
+    pub fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_kraken"));
+        command.args(args)
+            .env("HOME", &self.home)
+            .env("KRAKEN_SERVER_URL", self.url())
+            .env("KRAKEN_OUTPUT_DIR", self.home.join("jobs"));
+        command
+    }
+
+    /// Accepts the output a job started against this server ships, under its name in paths
+    pub fn expect_job(&self, path_name: &str) {
+        self.respond("GET", &format!("/logs/stream/{}", path_name), 200, r#"{"acked": 0}"#);
+        self.respond("POST", &format!("/logs/stream/{}", path_name), 200, r#"{"acked": 1000}"#);
+    }
+
+    /// Waits for the job to ship `line` as one of its output lines
+    pub fn printed(&self, job_name: &str, line: &str) -> bool {
+        let path = format!("/logs/stream/{}", job_name);
+        let line = format!(r#""line":"{}""#, line);
+        self.wait_for(Duration::from_secs(20), |requests| requests.iter().any(|r| r.path == path && r.body.contains(&line)))
+    }
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+fn handle(stream: TcpStream, state: &Arc<Mutex<State>>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("").to_string();
+    let path = parts.next().unwrap_or("").to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some(i) = line.find(':') {
+            headers.push((line[..i].trim().to_string(), line[i + 1..].trim().to_string()));
+        }
+    }
+
+    let header = |name: &str| headers.iter()
+        .find(|&&(ref k, _)| k.eq_ignore_ascii_case(name))
+        .map(|&(_, ref v)| v.clone());
+
+    let body = if header("transfer-encoding").map_or(false, |v| v.eq_ignore_ascii_case("chunked")) {
+        read_chunked(&mut reader)
+    } else {
+        let length = header("content-length").and_then(|v| v.parse::<usize>().ok()).unwrap_or(0);
+        let mut body = vec![0; length];
+        let _ = reader.read_exact(&mut body);
+        body
+    };
+
+    let request = RecordedRequest {
+        method: method,
+        path: path,
+        headers: headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    };
+
+    let (status, body) = {
+        let mut state = state.lock().unwrap();
+
+        let response = state.routes.iter()
+            .rev()
+            .find(|r| r.method == request.method && r.path == request.path)
+            .map(|r| (r.status, r.body.clone()))
+            .unwrap_or((404, format!("no mock for {} {}", request.method, request.path)));
+
+        state.requests.push(request);
+        response
+    };
+
+    let mut stream = stream;
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+}
+
+// Reads a chunked body to the end, the way streaming log uploads arrive
+fn read_chunked<R: BufRead>(reader: &mut R) -> Vec<u8> {
+    let mut body = Vec::new();
+
+    loop {
+        let mut size = String::new();
+        if reader.read_line(&mut size).unwrap_or(0) == 0 {
+            break;
+        }
+
+        let size = usize::from_str_radix(size.trim(), 16).unwrap_or(0);
+        let mut chunk = vec![0; size + 2];
+        if size == 0 || reader.read_exact(&mut chunk).is_err() {
+            break;
+        }
+
+        body.extend_from_slice(&chunk[..size]);
+    }
+
+    body
+}
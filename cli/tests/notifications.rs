@@ -0,0 +1,215 @@
+// `new job --notify`, `--alert-on` and `--fail-on`, with the mock server standing in for a
+// webhook receiver and a scripted SMTP server for email
+
+mod mock_server;
+
+use mock_server::MockServer;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn start_job_server(name: &str, job_name: &str) -> MockServer {
+    let server = MockServer::start(name);
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, &format!(r#"{{"_id": "1", "name": "{}"}}"#, job_name));
+    server.expect_job(job_name);
+    server
+}
+
+fn wait_for_file(path: &std::path::Path, timeout: Duration) -> Option<String> {
+    let start = Instant::now();
+
+    while start.elapsed() < timeout {
+        if let Ok(contents) = fs::read_to_string(path) {
+            if contents.ends_with('\n') {
+                return Some(contents);
+            }
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    None
+}
+
+#[test]
+fn webhook_gets_the_result() {
+    let server = start_job_server("notify-webhook", "hook-job");
+    server.respond("POST", "/hook", 200, "{}");
+
+    let target = format!("webhook:{}/hook", server.url());
+    server.kraken(&["new", "job", "-n", "hook-job", "--notify", &target, "exit 3"]);
+
+    let notified = server.wait_for(Duration::from_secs(20), |requests| requests.iter().any(|r| r.path == "/hook"));
+    assert!(notified, "the webhook was never called");
+
+    let hook = &server.requests_to("POST", "/hook")[0];
+    assert_eq!(hook.header("content-type"), Some("application/json"));
+    assert!(hook.body.contains(r#""jobName":"hook-job""#), "{}", hook.body);
+    assert!(hook.body.contains(r#""status":"failed","exitCode":3"#), "{}", hook.body);
+}
+
+#[test]
+fn webhook_is_retried() {
+    let server = start_job_server("notify-webhook-retry", "retry-job");
+    server.respond("POST", "/hook", 503, "{}");
+
+    let target = format!("webhook:{}/hook", server.url());
+    server.kraken(&["new", "job", "-n", "retry-job", "--notify", &target, "true"]);
+
+    let first = server.wait_for(Duration::from_secs(20), |requests| requests.iter().any(|r| r.path == "/hook"));
+    assert!(first, "the webhook was never called");
+
+    server.respond("POST", "/hook", 200, "{}");
+
+    let retried = server.wait_for(Duration::from_secs(20), |requests| requests.iter().filter(|r| r.path == "/hook").count() >= 2);
+    assert!(retried, "the webhook wasn't retried");
+}
+
+#[test]
+fn command_hook_gets_the_job_in_its_environment() {
+    let server = start_job_server("notify-command", "cmd-job");
+
+    let result = server.home.join("hook-result");
+    let target = format!(r#"cmd:echo "$KRAKEN_JOB_NAME $KRAKEN_JOB_STATUS $KRAKEN_EXIT_CODE" > {}"#, result.display());
+    server.kraken(&["new", "job", "-n", "cmd-job", "--notify", &target, "echo hi"]);
+
+    let contents = wait_for_file(&result, Duration::from_secs(20)).expect("the command hook never ran");
+    assert_eq!(contents, "cmd-job done 0\n");
+}
+
+#[test]
+fn email_goes_through_smtp() {
+    let server = start_job_server("notify-email", "mail-job");
+
+    // Answers every command with success and keeps what the client sent
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let transcript = Arc::new(Mutex::new(Vec::new()));
+
+    let received = transcript.clone();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut stream = stream;
+        let mut in_data = false;
+
+        write!(stream, "220 mock ESMTP\r\n").unwrap();
+
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                break;
+            }
+
+            let line = line.trim_end().to_string();
+            received.lock().unwrap().push(line.clone());
+
+            let reply = if in_data {
+                if line != "." {
+                    continue;
+                }
+                in_data = false;
+                "250 queued"
+            } else if line == "DATA" {
+                in_data = true;
+                "354 go ahead"
+            } else if line == "QUIT" {
+                "221 bye"
+            } else {
+                "250 ok"
+            };
+
+            write!(stream, "{}\r\n", reply).unwrap();
+        }
+    });
+
+    fs::write(
+        server.home.join(".kraken.json"),
+        format!(r#"{{"smtp_server": "{}", "smtp_from": "kraken@example.com"}}"#, addr),
+    ).unwrap();
+
+    server.kraken(&["new", "job", "-n", "mail-job", "--notify", "email:me@example.com", "exit 1"]);
+
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(20) && !transcript.lock().unwrap().iter().any(|l| l == "QUIT") {
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    let transcript = transcript.lock().unwrap();
+    assert!(transcript.iter().any(|l| l == "QUIT"), "{:?}", *transcript);
+    assert!(transcript.iter().any(|l| l == "MAIL FROM:<kraken@example.com>"), "{:?}", *transcript);
+    assert!(transcript.iter().any(|l| l == "RCPT TO:<me@example.com>"), "{:?}", *transcript);
+    assert!(transcript.iter().any(|l| l.starts_with("Subject: Job mail-job on ") && l.ends_with(" failed (exit code 1)")), "{:?}", *transcript);
+}
+
+#[test]
+fn invalid_target_is_refused() {
+    let server = MockServer::start("notify-invalid");
+    server.store_token("abc123");
+
+    let output = server.kraken(&["new", "job", "-n", "bad-notify", "--notify", "pager:oncall", "true"]);
+
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid --notify pager:oncall"));
+    assert!(server.requests_to("POST", "/jobs/new").is_empty());
+}
+
+#[test]
+fn alert_on_flags_the_job_and_notifies_once() {
+    let server = start_job_server("alert-on", "alert-job");
+    server.respond("POST", "/jobs/alert-job/alerts", 200, r#"{"ok": true}"#);
+    server.respond("POST", "/hook", 200, "{}");
+
+    let target = format!("webhook:{}/hook", server.url());
+    let command = "echo 'CUDA out of memory'; echo 'CUDA out of memory' >&2; echo done";
+    server.kraken(&["new", "job", "-n", "alert-job", "--alert-on", "out of memory", "--notify", &target, command]);
+
+    // One hook for the alert, one once the job is done
+    let notified = server.wait_for(Duration::from_secs(20), |requests| requests.iter().filter(|r| r.path == "/hook").count() >= 2);
+    assert!(notified, "the webhook wasn't called twice");
+
+    let alerts = server.requests_to("POST", "/jobs/alert-job/alerts");
+    assert_eq!(alerts.len(), 1);
+    assert!(alerts[0].body.contains(r#""pattern":"out of memory","line":"CUDA out of memory","action":"alert""#), "{}", alerts[0].body);
+
+    let hooks = server.requests_to("POST", "/hook");
+    assert!(hooks.iter().any(|r| r.body.contains(r#""status":"alert""#) && r.body.contains(r#""line":"CUDA out of memory""#)));
+    assert!(hooks.iter().any(|r| r.body.contains(r#""status":"done","exitCode":0"#)));
+}
+
+#[test]
+fn fail_on_stops_the_job() {
+    let server = start_job_server("fail-on", "fail-job");
+    server.respond("POST", "/jobs/fail-job/alerts", 200, r#"{"ok": true}"#);
+
+    let started = Instant::now();
+    server.kraken(&["new", "job", "-n", "fail-job", "--fail-on", "^Traceback", "echo 'Traceback (most recent call last):'; sleep 30; echo never"]);
+
+    let finished = server.wait_for(Duration::from_secs(20), |requests| {
+        requests.iter().any(|r| r.path == "/logs/stream/fail-job" && r.body.contains("\"line\":\"exit\""))
+    });
+    assert!(finished, "the job was never stopped");
+    assert!(started.elapsed() < Duration::from_secs(20));
+
+    let alerts = server.requests_to("POST", "/jobs/fail-job/alerts");
+    assert!(alerts[0].body.contains(r#""action":"fail""#), "{}", alerts[0].body);
+
+    let streams = server.requests_to("POST", "/logs/stream/fail-job");
+    assert!(!streams.iter().any(|r| r.body.contains("\"line\":\"never\"")));
+
+    let record = fs::read_to_string(server.home.join("jobs").join("kraken-job-fail-job.json")).unwrap();
+    assert!(record.contains(r#""status": "failed""#), "{}", record);
+}
+
+#[test]
+fn invalid_pattern_is_refused() {
+    let server = MockServer::start("alert-invalid");
+    server.store_token("abc123");
+
+    let output = server.kraken(&["new", "job", "-n", "bad-pattern", "--fail-on", "(unclosed", "true"]);
+
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid --fail-on pattern (unclosed"));
+    assert!(server.requests_to("POST", "/jobs/new").is_empty());
+}
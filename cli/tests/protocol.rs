@@ -0,0 +1,277 @@
+// Round-trips every wire type and checks it against the wire format in `api/openapi.yaml`
+
+#[macro_use] extern crate serde_derive;
+extern crate serde;
+extern crate serde_json;
+
+#[path = "../src/protocol.rs"]
+#[allow(dead_code)]
+mod protocol;
+
+use protocol::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+fn round_trip<T: Serialize + DeserializeOwned + PartialEq + Debug>(value: T, wire: &str) {
+    let json = serde_json::to_value(&value).unwrap();
+    let expected: serde_json::Value = serde_json::from_str(wire).unwrap();
+    assert_eq!(json, expected);
+
+    let back: T = serde_json::from_str(wire).unwrap();
+    assert_eq!(back, value);
+}
+
+#[test]
+fn login_request() {
+    round_trip(
+        LoginRequest { email: "me@example.com".to_string(), password: "hunter2".to_string() },
+        r#"{"email": "me@example.com", "password": "hunter2"}"#,
+    );
+}
+
+#[test]
+fn token_response() {
+    round_trip(
+        TokenResponse { auth: true, token: "abc123".to_string() },
+        r#"{"auth": true, "token": "abc123"}"#,
+    );
+}
+
+#[test]
+fn new_job_request() {
+    round_trip(
+        NewJobRequest { machine: "box".to_string(), name: "train".to_string(), description: Some("resnet".to_string()), group: None, spec: None, labels: BTreeMap::new() },
+        r#"{"machine": "box", "name": "train", "description": "resnet"}"#,
+    );
+}
+
+#[test]
+fn new_job_request_with_its_spec() {
+    let spec = JobSpec {
+        command: "python train.py".to_string(),
+        env: vec!["EPOCHS=10".to_string()],
+        workdir: "/home/me/resnet".to_string(),
+        description: "resnet".to_string(),
+        options: vec!["--notify".to_string(), "email:me@example.com".to_string()],
+        argv: Vec::new(),
+        script: None,
+        invocation: vec!["/bin/sh".to_string(), "-c".to_string(), "python train.py".to_string()],
+    };
+
+    round_trip(
+        NewJobRequest { machine: "box".to_string(), name: "train".to_string(), description: Some("resnet".to_string()), group: None, spec: Some(spec.clone()), labels: BTreeMap::new() },
+        r#"{"machine": "box", "name": "train", "description": "resnet", "spec": {"command": "python train.py", "env": ["EPOCHS=10"], "workdir": "/home/me/resnet", "description": "resnet", "options": ["--notify", "email:me@example.com"], "invocation": ["/bin/sh", "-c", "python train.py"]}}"#,
+    );
+
+    let minimal: JobSpec = serde_json::from_str(r#"{"command": "true", "workdir": "/"}"#).unwrap();
+    assert!(minimal.env.is_empty() && minimal.options.is_empty() && minimal.invocation.is_empty());
+}
+
+#[test]
+fn job_uses_underscore_id() {
+    round_trip(
+        Job {
+            id: "5c1f".to_string(),
+            machine: "box".to_string(),
+            name: "train".to_string(),
+            description: None,
+            status: Some("running".to_string()),
+            progress: None,
+            warning: None,
+            group: None,
+            exit_code: None,
+            labels: BTreeMap::new(),
+        },
+        r#"{"_id": "5c1f", "machine": "box", "name": "train", "description": null, "status": "running"}"#,
+    );
+}
+
+#[test]
+fn job_from_old_server_without_optional_fields() {
+    let job: Job = serde_json::from_str(r#"{"_id": "1", "machine": "box", "name": "train"}"#).unwrap();
+    assert_eq!(job.description, None);
+    assert_eq!(job.status, None);
+}
+
+#[test]
+fn job_in_a_group_with_an_exit_code() {
+    let job: Job = serde_json::from_str(r#"{"_id": "1", "machine": "box", "name": "box-fleet", "group": "fleet", "exitCode": 3}"#).unwrap();
+    assert_eq!(job.group, Some("fleet".to_string()));
+    assert_eq!(job.exit_code, Some(3));
+
+    round_trip(
+        JobExit { status: "failed".to_string(), exit_code: Some(3) },
+        r#"{"status": "failed", "exitCode": 3}"#,
+    );
+}
+
+#[test]
+fn job_labels() {
+    let mut labels = BTreeMap::new();
+    labels.insert("project".to_string(), "resnet".to_string());
+
+    let job: Job = serde_json::from_str(r#"{"_id": "1", "machine": "box", "name": "train", "labels": {"project": "resnet"}}"#).unwrap();
+    assert_eq!(job.labels, labels);
+
+    round_trip(
+        NewJobRequest { machine: "box".to_string(), name: "train".to_string(), description: None, group: None, spec: None, labels: labels },
+        r#"{"machine": "box", "name": "train", "description": null, "labels": {"project": "resnet"}}"#,
+    );
+}
+
+#[test]
+fn job_progress_is_camel_case() {
+    round_trip(
+        JobProgress {
+            current: 42,
+            total: Some(100),
+            message: Some("epoch 3".to_string()),
+            updated_at: "2019-01-01T00:00:00.000Z".to_string(),
+            eta_secs: Some(90),
+        },
+        r#"{"current": 42, "total": 100, "message": "epoch 3", "updatedAt": "2019-01-01T00:00:00.000Z", "etaSecs": 90}"#,
+    );
+
+    let progress: JobProgress = serde_json::from_str(r#"{"current": 7, "updatedAt": "2019-01-01T00:00:00.000Z"}"#).unwrap();
+    assert_eq!(progress.total, None);
+    assert_eq!(progress.eta_secs, None);
+}
+
+#[test]
+fn metric() {
+    round_trip(
+        Metric { name: "loss".to_string(), value: 0.12, step: Some(3), timestamp: "2019-01-01T00:00:00.000Z".to_string() },
+        r#"{"name": "loss", "value": 0.12, "step": 3, "timestamp": "2019-01-01T00:00:00.000Z"}"#,
+    );
+}
+
+#[test]
+fn artifact_path_stays_local() {
+    round_trip(
+        Artifact { name: "model.pt".to_string(), size: 1024, path: None },
+        r#"{"name": "model.pt", "size": 1024}"#,
+    );
+}
+
+#[test]
+fn resource_summary_is_camel_case() {
+    round_trip(
+        ResourceSummary {
+            samples: 12,
+            duration_secs: 55,
+            peak_cpu_percent: 180.5,
+            avg_cpu_percent: 97.2,
+            peak_rss_bytes: 2048,
+            avg_rss_bytes: 1024,
+            read_bytes: 10,
+            write_bytes: 20,
+            peak_processes: 3,
+        },
+        r#"{"samples": 12, "durationSecs": 55, "peakCpuPercent": 180.5, "avgCpuPercent": 97.2, "peakRssBytes": 2048, "avgRssBytes": 1024, "readBytes": 10, "writeBytes": 20, "peakProcesses": 3}"#,
+    );
+}
+
+#[test]
+fn alert() {
+    round_trip(
+        Alert {
+            pattern: "^Traceback".to_string(),
+            line: "Traceback (most recent call last):".to_string(),
+            action: "fail".to_string(),
+            timestamp: "2019-01-01T00:00:00.000Z".to_string(),
+        },
+        r#"{"pattern": "^Traceback", "line": "Traceback (most recent call last):", "action": "fail", "timestamp": "2019-01-01T00:00:00.000Z"}"#,
+    );
+}
+
+#[test]
+fn new_log_request_is_camel_case() {
+    round_trip(
+        NewLogRequest {
+            job_name: "train".to_string(),
+            line: "epoch 1".to_string(),
+            seq: Some(7),
+            timestamp: Some("2020-01-01T00:00:00.000Z".to_string()),
+            stream: Some("stdout".to_string()),
+        },
+        r#"{"jobName": "train", "line": "epoch 1", "seq": 7, "timestamp": "2020-01-01T00:00:00.000Z", "stream": "stdout"}"#,
+    );
+}
+
+#[test]
+fn log_without_capture_details() {
+    round_trip(
+        Log { job_id: "train".to_string(), line: "epoch 1".to_string(), seq: None, timestamp: None, stream: None },
+        r#"{"jobId": "train", "line": "epoch 1"}"#,
+    );
+}
+
+#[test]
+fn stream_ack() {
+    round_trip(StreamAck { acked: 42 }, r#"{"acked": 42}"#);
+}
+
+#[test]
+fn removed_response() {
+    round_trip(RemovedResponse { removed: 3 }, r#"{"removed": 3}"#);
+}
+
+#[test]
+fn error_response() {
+    round_trip(
+        ErrorResponse { auth: Some(false), message: "Failed to authenticate token.".to_string() },
+        r#"{"auth": false, "message": "Failed to authenticate token."}"#,
+    );
+
+    let error: ErrorResponse = serde_json::from_str(r#"{"message": "boom"}"#).unwrap();
+    assert_eq!(error.auth, None);
+}
+
+#[test]
+fn version_compatibility() {
+    assert!(compatible(Some(API_VERSION)));
+    assert!(compatible(Some("1.7")));
+    assert!(compatible(None));
+    assert!(!compatible(Some("2.0")));
+}
+
+#[test]
+fn job_names_in_paths() {
+    assert_eq!(encode_path_segment("train-model_v2.1~"), "train-model_v2.1~");
+    assert_eq!(encode_path_segment("nightly run/eu"), "nightly%20run%2Feu");
+    assert_eq!(encode_path_segment("café"), "caf%C3%A9");
+
+    for name in &["nightly run/eu", "café", "100%", "a?b#c"] {
+        assert_eq!(decode_path_segment(&encode_path_segment(name)), *name);
+    }
+    assert_eq!(decode_path_segment("50%zz"), "50%zz");
+}
+
+#[test]
+fn job_name_checks() {
+    assert!(check_job_name("nightly run (eu)").is_ok());
+    assert_eq!(check_job_name(""), Err("Invalid job name ``: it's empty".to_string()));
+    assert_eq!(check_job_name("a/b"), Err("Invalid job name `a/b`: it contains /".to_string()));
+    assert_eq!(check_job_name("-rf"), Err("Invalid job name `-rf`: it starts with -".to_string()));
+    assert!(check_job_name("two\nlines").is_err());
+    assert!(check_job_name(&"x".repeat(MAX_JOB_NAME_BYTES)).is_ok());
+    assert!(check_job_name(&"x".repeat(MAX_JOB_NAME_BYTES + 1)).is_err());
+}
+
+#[test]
+fn spec_matches_code() {
+    let spec = include_str!("../api/openapi.yaml");
+
+    assert!(spec.contains(&format!("  version: \"{}\"", API_VERSION)));
+    assert!(spec.contains(&format!("name: {}", API_VERSION_HEADER)));
+
+    for path in &["/auth/login:", "/jobs:", "/jobs/new:", "/jobs/{name}:", "/jobs/{name}/restore:", "/jobs/{name}/exit:", "/jobs/{name}/spec:", "/jobs/{name}/progress:", "/jobs/{name}/metrics:", "/jobs/{name}/artifacts:", "/jobs/{name}/artifacts/{artifact}:", "/jobs/{name}/resources:", "/jobs/{name}/alerts:", "/jobs/remove-all:", "/logs/new:", "/logs/stream/{name}:"] {
+        assert!(spec.contains(path), "{} is missing from the spec", path);
+    }
+
+    for schema in &["LoginRequest:", "TokenResponse:", "NewJobRequest:", "JobSpec:", "Job:", "JobExit:", "JobProgress:", "Metric:", "Artifact:", "ResourceSummary:", "Alert:", "NewLogRequest:", "Log:", "StreamAck:", "RemovedResponse:", "ErrorResponse:"] {
+        assert!(spec.contains(schema), "{} is missing from the spec", schema);
+    }
+}
@@ -0,0 +1,165 @@
+// `new job --host`, with a script standing in for ssh that runs the command locally in a
+// separate home directory playing the server
+
+mod mock_server;
+
+use mock_server::{stdout, MockServer};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::time::Duration;
+
+// Records the host, then runs the command with the host's own home and a PATH without kraken.
+// `user@down` can't be reached.
+fn fake_ssh(server: &MockServer) {
+    let script = server.home.join("fake-ssh");
+    fs::write(&script, r#"#!/bin/sh
+[ "$1" = user@down ] && exit 255
+echo "$1" >> "$(dirname "$0")/ssh-hosts"
+mkdir -p "$(dirname "$0")/remote-homes/$1"
+HOME="$(dirname "$0")/remote-homes/$1" PATH=/usr/bin:/bin exec sh -c "$2"
+"#).unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+    fs::write(server.home.join(".kraken.json"), format!(r#"{{"ssh_command": "{}"}}"#, script.display())).unwrap();
+}
+
+#[test]
+fn new_job_on_another_host() {
+    let server = MockServer::start("remote-launch");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "remote-job"}"#);
+    server.respond("GET", "/logs/stream/remote-job", 200, r#"{"acked": 0}"#);
+    server.respond("POST", "/logs/stream/remote-job", 200, r#"{"acked": 1000}"#);
+    fake_ssh(&server);
+
+    let output = server.kraken(&["new", "job", "--host", "user@box", "-n", "remote-job", "-d", "on the box", r#"echo "hello 'quoted' world""#]);
+    let out = stdout(&output);
+
+    assert!(out.contains("Installing kraken on user@box"), "{}", out);
+    assert!(out.contains("Started remote-job on user@box"), "{}", out);
+
+    let remote_home = server.home.join("remote-homes").join("user@box");
+    assert!(remote_home.join(".kraken/bin/kraken").exists());
+    assert_eq!(fs::read_to_string(remote_home.join(".krakenrc")).unwrap(), "abc123\n");
+    assert!(fs::read_to_string(server.home.join("ssh-hosts")).unwrap().lines().all(|h| h == "user@box"));
+
+    // The remote kraken registered the job and ran the command as given
+    let created = &server.requests_to("POST", "/jobs/new")[0];
+    assert_eq!(created.header("x-access-token"), Some("abc123"));
+    assert!(created.body.contains("on the box"), "{}", created.body);
+
+    let streamed = server.wait_for(Duration::from_secs(20), |requests| {
+        requests.iter().any(|r| r.path == "/logs/stream/remote-job" && r.body.contains(r#""line":"hello 'quoted' world""#))
+    });
+    assert!(streamed, "the remote job's output never arrived");
+
+    // Now it's installed, and without a name the job is named after the host
+    let output = server.kraken(&["new", "job", "--host", "user@box", "true"]);
+    let out = stdout(&output);
+
+    assert!(!out.contains("Installing"), "{}", out);
+    assert!(out.contains("Started box-"), "{}", out);
+}
+
+#[test]
+fn new_job_on_unreachable_host() {
+    let server = MockServer::start("remote-unreachable");
+    server.store_token("abc123");
+    fs::write(server.home.join(".kraken.json"), r#"{"ssh_command": "false"}"#).unwrap();
+
+    let output = server.kraken(&["new", "job", "--host", "user@nowhere", "true"]);
+
+    assert!(String::from_utf8_lossy(&output.stderr).contains("on user@nowhere failed"));
+    assert!(server.requests_to("POST", "/jobs/new").is_empty());
+}
+
+#[test]
+fn new_job_on_every_host_in_a_file() {
+    let server = MockServer::start("remote-fan-out");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "fanned"}"#);
+    fake_ssh(&server);
+
+    let hosts_file = server.home.join("hosts.txt");
+    fs::write(&hosts_file, "user@alpha\n# not this one\n\nuser@beta\n").unwrap();
+
+    let output = server.kraken(&["new", "job", "--hosts-file", hosts_file.to_str().unwrap(), "true"]);
+    let out = stdout(&output);
+
+    let group = out.lines()
+        .find(|l| l.starts_with("Started 2 of 2 jobs in group "))
+        .and_then(|l| l["Started 2 of 2 jobs in group ".len()..].split('.').next())
+        .unwrap_or_else(|| panic!("{}", out))
+        .to_string();
+
+    assert!(out.contains(&format!("Started alpha-{} on user@alpha", group)), "{}", out);
+    assert!(out.contains(&format!("Started beta-{} on user@beta", group)), "{}", out);
+
+    let created = server.requests_to("POST", "/jobs/new");
+    assert_eq!(created.len(), 2);
+    for name in &["alpha", "beta"] {
+        let body = format!(r#""name":"{}-{}""#, name, group);
+        assert!(created.iter().any(|r| r.body.contains(&body) && r.body.contains(&format!(r#""group":"{}""#, group))));
+    }
+}
+
+#[test]
+fn new_job_on_hosts_reports_the_ones_that_failed() {
+    let server = MockServer::start("remote-fan-out-down");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "fanned"}"#);
+    fake_ssh(&server);
+
+    let output = server.kraken(&["new", "job", "--hosts", "user@up,user@down", "true"]);
+    let out = stdout(&output);
+
+    assert!(out.contains("Started 1 of 2 jobs in group"), "{}", out);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Couldn't start a job on user@down"));
+    assert_eq!(server.requests_to("POST", "/jobs/new").len(), 1);
+}
+
+#[test]
+fn new_job_on_another_host_with_a_script() {
+    let server = MockServer::start("remote-script");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "remote-script"}"#);
+    server.respond("GET", "/logs/stream/remote-script", 200, r#"{"acked": 0}"#);
+    server.respond("POST", "/logs/stream/remote-script", 200, r#"{"acked": 1000}"#);
+    fake_ssh(&server);
+
+    let script = server.home.join("deploy.sh");
+    fs::write(&script, "echo \"deploying $1\"\n").unwrap();
+
+    let output = server.kraken(&["new", "job", "--host", "user@box", "-n", "remote-script", "--script", script.to_str().unwrap(), "--", "v2"]);
+    assert!(stdout(&output).contains("Started remote-script on user@box"), "{}", stdout(&output));
+
+    // The script was copied over, since the host doesn't have this machine's files
+    let copied = server.home.join("remote-homes").join("user@box").join(".kraken/scripts/remote-script/deploy.sh");
+    assert_eq!(fs::read_to_string(copied).unwrap(), "echo \"deploying $1\"\n");
+
+    let streamed = server.wait_for(Duration::from_secs(20), |requests| {
+        requests.iter().any(|r| r.path == "/logs/stream/remote-script" && r.body.contains(r#""line":"deploying v2""#))
+    });
+    assert!(streamed, "the remote job's output never arrived");
+}
+
+#[test]
+fn new_job_on_another_host_with_input() {
+    let server = MockServer::start("remote-input");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "remote-input"}"#);
+    server.respond("GET", "/logs/stream/remote-input", 200, r#"{"acked": 0}"#);
+    server.respond("POST", "/logs/stream/remote-input", 200, r#"{"acked": 1000}"#);
+    fake_ssh(&server);
+
+    let input = server.home.join("hosts-to-check.txt");
+    fs::write(&input, "alpha\nbeta\n").unwrap();
+
+    server.kraken(&["new", "job", "--host", "user@box", "-n", "remote-input", "--stdin-file", input.to_str().unwrap(), "--", "tail", "-n", "1"]);
+
+    // The input went over ssh, since the file is only on this machine
+    let streamed = server.wait_for(Duration::from_secs(20), |requests| {
+        requests.iter().any(|r| r.path == "/logs/stream/remote-input" && r.body.contains(r#""line":"beta""#))
+    });
+    assert!(streamed, "the remote job never read its input");
+}
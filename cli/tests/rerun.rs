@@ -0,0 +1,119 @@
+// `rerun job` and `new job --from`, starting jobs again from the spec in their local record or
+// on the server
+
+mod mock_server;
+
+use mock_server::{stderr, MockServer};
+use std::fs;
+
+#[test]
+fn rerun_job_starts_it_again_with_overrides() {
+    let server = MockServer::start("rerun-local");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "first"}"#);
+    server.expect_job("first");
+    server.expect_job("second");
+
+    let workdir = server.home.join("work");
+    fs::create_dir_all(&workdir).unwrap();
+    let workdir = fs::canonicalize(workdir).unwrap();
+
+    server.kraken(&[
+        "new", "job", "-n", "first", "-d", "say hi", "--env", "GREETING=hi", "--env", "NAME=kraken",
+        "--workdir", workdir.to_str().unwrap(), "--sample-interval", "0",
+        r#"echo "$GREETING $NAME from $(pwd)""#,
+    ]);
+    assert!(server.printed("first", &format!("hi kraken from {}", workdir.display())), "the first run never printed");
+
+    let created = &server.requests_to("POST", "/jobs/new")[0];
+    // Values can be secrets, so only the names go to the server
+    assert!(created.body.contains(r#""env":["GREETING","NAME"]"#), "{}", created.body);
+    assert!(created.body.contains(r#""options":["--sample-interval","0"]"#), "{}", created.body);
+
+    // Variables given again replace the saved ones
+    server.kraken(&["rerun", "job", "first", "-n", "second", "--env", "GREETING=hello"]);
+    assert!(server.printed("second", &format!("hello kraken from {}", workdir.display())), "the rerun never printed");
+
+    let rerun = &server.requests_to("POST", "/jobs/new")[1];
+    assert!(rerun.body.contains(r#""name":"second","description":"say hi""#), "{}", rerun.body);
+    assert!(rerun.body.contains(r#""options":["--sample-interval","0"]"#), "{}", rerun.body);
+
+    let record = fs::read_to_string(server.home.join("jobs").join("kraken-job-second.json")).unwrap();
+    assert!(record.contains(r#""GREETING=hello""#), "{}", record);
+}
+
+#[test]
+fn new_job_from_a_job_that_ran_elsewhere() {
+    let server = MockServer::start("rerun-from-server");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "copied"}"#);
+    server.expect_job("copied");
+
+    let spec = format!(
+        r#"{{"command": "echo original", "env": ["WHO=server"], "workdir": "{}", "description": "from the server", "options": []}}"#,
+        server.home.display()
+    );
+    server.respond("GET", "/jobs/elsewhere/spec", 200, &spec);
+
+    server.kraken(&["new", "job", "--from", "elsewhere", "-n", "copied", r#"echo "$WHO copy""#]);
+    assert!(server.printed("copied", "server copy"), "the copy never printed");
+
+    let created = &server.requests_to("POST", "/jobs/new")[0];
+    assert!(created.body.contains(r#""description":"from the server""#), "{}", created.body);
+}
+
+#[test]
+fn variables_the_server_only_has_by_name_come_from_here() {
+    let server = MockServer::start("rerun-env-names");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "secret"}"#);
+    server.expect_job("secret");
+
+    let spec = format!(
+        r#"{{"command": "echo \"token $KRAKEN_TEST_TOKEN\"", "env": ["KRAKEN_TEST_TOKEN"], "workdir": "{}", "description": "", "options": []}}"#,
+        server.home.display()
+    );
+    server.respond("GET", "/jobs/elsewhere/spec", 200, &spec);
+
+    let output = server.kraken(&["rerun", "job", "elsewhere", "-n", "secret"]);
+    assert!(stderr(&output).contains("Set KRAKEN_TEST_TOKEN here or give --env KRAKEN_TEST_TOKEN=VALUE"), "{}", stderr(&output));
+    assert!(server.requests_to("POST", "/jobs/new").is_empty());
+
+    server.kraken(&["rerun", "job", "elsewhere", "-n", "secret", "--env", "KRAKEN_TEST_TOKEN=abc"]);
+    assert!(server.printed("secret", "token abc"), "the rerun never printed");
+}
+
+#[test]
+fn jobs_without_a_spec_cant_be_rerun() {
+    let server = MockServer::start("rerun-no-spec");
+    server.store_token("abc123");
+    server.respond("GET", "/jobs/ancient/spec", 200, "null");
+
+    let output = server.kraken(&["rerun", "job", "ancient"]);
+    assert!(stderr(&output).contains("ancient was started by a kraken that didn't keep its spec"), "{}", stderr(&output));
+
+    let output = server.kraken(&["new", "job", "--env", "NOVALUE", "true"]);
+    assert!(stderr(&output).contains("Invalid --env NOVALUE, expected NAME=VALUE"), "{}", stderr(&output));
+    assert!(server.requests_to("POST", "/jobs/new").is_empty());
+}
+
+#[test]
+fn rerun_job_finds_jobs_in_another_output_dir() {
+    let server = MockServer::start("rerun-output-dir");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "kept"}"#);
+    server.expect_job("kept");
+    server.expect_job("kept-again");
+
+    let elsewhere = server.home.join("elsewhere");
+    fs::create_dir_all(&elsewhere).unwrap();
+    let elsewhere = elsewhere.to_str().unwrap();
+
+    server.kraken(&["new", "job", "-n", "kept", "--output-dir", elsewhere, "echo kept elsewhere"]);
+    assert!(server.printed("kept", "kept elsewhere"), "the first run never printed");
+
+    // Its spec is only in the record there, the server doesn't have it
+    server.kraken(&["rerun", "job", "kept", "-n", "kept-again", "--output-dir", elsewhere]);
+    assert!(server.printed("kept-again", "kept elsewhere"), "the rerun never printed");
+    assert!(server.home.join("elsewhere").join("kraken-job-kept-again.json").exists());
+}
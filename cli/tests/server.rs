@@ -0,0 +1,355 @@
+// Drives the CLI against a local kraken-server
+
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::{Child, Command, Output};
+use std::thread;
+use std::time::Duration;
+
+struct TestServer {
+    process: Child,
+    url: String,
+    home: PathBuf,
+    data_dir: PathBuf,
+    port: u16,
+    // Extra arguments to `kraken-server run`
+    args: Vec<String>,
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+    }
+}
+
+fn start_server(name: &str, port: u16) -> TestServer {
+    start_server_with(name, port, &[])
+}
+
+fn start_server_with(name: &str, port: u16, args: &[&str]) -> TestServer {
+    let root = env::temp_dir().join(format!("kraken-test-{}", name));
+    let _ = fs::remove_dir_all(&root);
+    let data_dir = root.join("data");
+    let home = root.join("home");
+    fs::create_dir_all(&home).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_kraken-server"))
+        .args(&["add-user", "--data-dir", data_dir.to_str().unwrap(), "test@example.com", "hunter2"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+
+    TestServer {
+        process: run_server(&data_dir, port, &args),
+        url: format!("http://127.0.0.1:{}", port),
+        home: home,
+        data_dir: data_dir,
+        port: port,
+        args: args,
+    }
+}
+
+fn run_server(data_dir: &PathBuf, port: u16, args: &[String]) -> Child {
+    let process = Command::new(env!("CARGO_BIN_EXE_kraken-server"))
+        .args(&["run", "--data-dir", data_dir.to_str().unwrap(), "--port", &port.to_string()])
+        .args(args)
+        .spawn()
+        .unwrap();
+
+    for _ in 0..50 {
+        if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    process
+}
+
+// Stops the server and starts it again on the same data
+fn restart(server: &mut TestServer) {
+    let _ = server.process.kill();
+    let _ = server.process.wait();
+    server.process = run_server(&server.data_dir, server.port, &server.args);
+}
+
+fn kraken(server: &TestServer, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_kraken"))
+        .args(args)
+        .env("HOME", &server.home)
+        .env("KRAKEN_SERVER_URL", &server.url)
+        .env("KRAKEN_OUTPUT_DIR", server.home.join("jobs"))
+        .output()
+        .unwrap()
+}
+
+fn wait_for_output(server: &TestServer, args: &[&str], expected: &str) -> String {
+    let mut out = String::new();
+
+    for _ in 0..100 {
+        out = String::from_utf8_lossy(&kraken(server, args).stdout).into_owned();
+        if out.contains(expected) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    out
+}
+
+// Sends `lines` as one chunk of a chunked request, the way the CLI streams logs
+fn stream_lines(server: &TestServer, path: &str, lines: &[&str]) -> String {
+    let token = fs::read_to_string(server.home.join(".krakenrc")).unwrap();
+    let body = lines.iter().map(|l| format!("{}\n", l)).collect::<String>();
+
+    let mut stream = TcpStream::connect(server.url.trim_start_matches("http://")).unwrap();
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: localhost\r\nx-access-token: {}\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+        path,
+        token.trim(),
+        body.len(),
+        body
+    ).unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn login_stores_a_token() {
+    let server = start_server("server-login", 18801);
+
+    let output = kraken(&server, &["login", "test@example.com", "hunter2"]);
+    assert!(output.status.success());
+
+    let token = fs::read_to_string(server.home.join(".krakenrc")).unwrap();
+    assert!(token.trim().len() > 0);
+}
+
+#[test]
+fn login_with_wrong_password_fails() {
+    let server = start_server("server-bad-login", 18802);
+
+    let output = kraken(&server, &["login", "test@example.com", "wrong"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Wrong email or password"));
+    assert!(!server.home.join(".krakenrc").exists());
+}
+
+#[test]
+fn show_jobs_after_login() {
+    let server = start_server("server-show-jobs", 18803);
+
+    kraken(&server, &["login", "test@example.com", "hunter2"]);
+    let output = kraken(&server, &["show", "jobs"]);
+
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Job Name"));
+}
+
+#[test]
+fn streamed_logs_resume_without_duplicates() {
+    let server = start_server("server-stream", 18804);
+    kraken(&server, &["login", "test@example.com", "hunter2"]);
+
+    kraken(&server, &["new", "job", "-n", "streamed", "echo one; echo two"]);
+    let out = wait_for_output(&server, &["show", "job", "streamed", "100"], "exit");
+    assert!(out.contains("two"), "{}", out);
+
+    // A reconnecting client resends lines the server already has
+    let response = stream_lines(&server, "/logs/stream/streamed", &[
+        r#"{"jobName": "streamed", "line": "one", "seq": 4, "stream": "stdout"}"#,
+        r#"{"jobName": "streamed", "line": "late", "seq": 1000, "stream": "stdout"}"#,
+    ]);
+    assert!(response.contains(r#"{"acked":1000}"#), "{}", response);
+
+    let out = wait_for_output(&server, &["show", "job", "streamed", "100"], "late");
+    assert_eq!(out.lines().filter(|l| l.trim() == "one").count(), 1, "{}", out);
+}
+
+#[test]
+fn metrics_and_artifacts_reach_the_server() {
+    let server = start_server("server-metrics", 18805);
+    kraken(&server, &["login", "test@example.com", "hunter2"]);
+
+    let model = server.home.join("model.txt");
+    let command = format!("echo weights > {0}; echo '##kraken metric loss=0.5'; echo '##kraken artifact {0}'", model.display());
+    kraken(&server, &["new", "job", "-n", "measured", "--upload-artifacts", &command]);
+
+    let out = wait_for_output(&server, &["show", "job", "measured", "--metrics"], "model.txt");
+    assert!(out.contains("loss"), "{}", out);
+    assert!(out.contains("| 8 "), "{}", out);
+}
+
+#[test]
+fn fail_on_marks_the_job_failed_on_the_server() {
+    let server = start_server("server-alerts", 18806);
+    kraken(&server, &["login", "test@example.com", "hunter2"]);
+
+    kraken(&server, &["new", "job", "-n", "stopped", "--fail-on", "out of memory", "echo 'CUDA out of memory'; sleep 30"]);
+
+    let out = wait_for_output(&server, &["show", "jobs"], "failed (matched out of memory: CUDA out of memory)");
+    assert!(out.contains("stopped"), "{}", out);
+}
+
+#[test]
+fn exit_codes_reach_the_server() {
+    let server = start_server("server-exit", 18807);
+    kraken(&server, &["login", "test@example.com", "hunter2"]);
+
+    kraken(&server, &["new", "job", "-n", "grouped-ok", "--group", "nightly", "true"]);
+    kraken(&server, &["new", "job", "-n", "grouped-bad", "--group", "nightly", "exit 4"]);
+
+    let out = wait_for_output(&server, &["show", "group", "nightly"], "2 jobs: 1 done, 1 failed");
+    let row = out.lines().find(|l| l.contains("grouped-bad")).unwrap();
+    assert!(row.contains("| 4 "), "{}", out);
+}
+
+#[test]
+fn rerun_from_another_machine() {
+    let server = start_server("server-rerun", 18808);
+    kraken(&server, &["login", "test@example.com", "hunter2"]);
+
+    kraken(&server, &["new", "job", "-n", "original", "--env", "WHO=kraken", r#"echo "hello from $WHO""#]);
+    wait_for_output(&server, &["show", "jobs"], "done");
+
+    // Without the local record the spec comes from the server, which only has the variable's name
+    let output = Command::new(env!("CARGO_BIN_EXE_kraken"))
+        .args(&["rerun", "job", "original", "-n", "again"])
+        .env("WHO", "kraken")
+        .env("HOME", &server.home)
+        .env("KRAKEN_SERVER_URL", &server.url)
+        .env("KRAKEN_OUTPUT_DIR", server.home.join("elsewhere"))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let out = wait_for_output(&server, &["show", "job", "again"], "hello from kraken");
+    assert!(out.contains("hello from kraken"), "{}", out);
+}
+
+#[test]
+fn tags_reach_the_server() {
+    let server = start_server("server-tags", 18809);
+    kraken(&server, &["login", "test@example.com", "hunter2"]);
+
+    kraken(&server, &["new", "job", "-n", "tagged", "--tag", "project=resnet", "true"]);
+    kraken(&server, &["new", "job", "-n", "untagged", "true"]);
+
+    let out = wait_for_output(&server, &["show", "jobs", "--tag", "project=resnet"], "tagged");
+    assert!(out.contains("project=resnet"), "{}", out);
+    assert!(!out.contains("untagged"), "{}", out);
+}
+
+#[test]
+fn names_with_spaces_reach_the_server() {
+    let server = start_server("server-names", 18810);
+    kraken(&server, &["login", "test@example.com", "hunter2"]);
+
+    kraken(&server, &["new", "job", "-n", "nightly run", "echo hello from the night"]);
+    let out = wait_for_output(&server, &["show", "job", "nightly run"], "hello from the night");
+    assert!(out.contains("hello from the night"), "{}", out);
+
+    let output = kraken(&server, &["new", "job", "-n", "nightly run", "true"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("A job named nightly run already exists"));
+
+    kraken(&server, &["new", "job", "-n", "nightly run", "--auto-suffix", "true"]);
+    wait_for_output(&server, &["show", "jobs"], "nightly run-2");
+}
+
+#[test]
+fn jobs_and_logs_survive_a_restart() {
+    let mut server = start_server("server-restart", 18811);
+    kraken(&server, &["login", "test@example.com", "hunter2"]);
+
+    kraken(&server, &["new", "job", "-n", "kept", "echo before"]);
+    wait_for_output(&server, &["show", "jobs"], "done");
+
+    let response = stream_lines(&server, "/logs/stream/kept", &[
+        r#"{"jobName": "kept", "line": "late", "seq": 1000, "stream": "stdout"}"#,
+    ]);
+    assert!(response.contains(r#"{"acked":1000}"#), "{}", response);
+
+    restart(&mut server);
+
+    // The login and the job come back from the journal, and the last seq from the log's end
+    let out = wait_for_output(&server, &["show", "jobs"], "kept");
+    assert!(out.contains("done"), "{}", out);
+
+    let response = stream_lines(&server, "/logs/stream/kept", &[
+        r#"{"jobName": "kept", "line": "resent", "seq": 1000, "stream": "stdout"}"#,
+    ]);
+    assert!(response.contains(r#"{"acked":1000}"#), "{}", response);
+
+    let out = wait_for_output(&server, &["show", "job", "kept", "100"], "late");
+    assert!(out.contains("before"), "{}", out);
+    assert!(!out.contains("resent"), "{}", out);
+}
+
+#[test]
+fn restoring_over_a_live_job_names_the_conflict() {
+    let server = start_server("server-restore-taken", 18812);
+    kraken(&server, &["login", "test@example.com", "hunter2"]);
+
+    kraken(&server, &["new", "job", "-n", "reused", "true"]);
+    wait_for_output(&server, &["show", "jobs"], "done");
+    kraken(&server, &["remove", "job", "reused"]);
+
+    // The removed job's local files are still here, so the new one replaces them
+    kraken(&server, &["new", "job", "-n", "reused", "--replace", "true"]);
+    wait_for_output(&server, &["show", "jobs"], "reused");
+
+    let output = kraken(&server, &["restore", "job", "reused"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("A live job already uses the name reused"), "{}", stderr);
+}
+
+#[test]
+fn job_names_that_look_like_ids_are_names() {
+    let server = start_server("server-id-names", 18813);
+    kraken(&server, &["login", "test@example.com", "hunter2"]);
+
+    // The user has id 00000001, so this job gets 00000002
+    kraken(&server, &["new", "job", "-n", "first", "true"]);
+    wait_for_output(&server, &["show", "jobs"], "done");
+
+    let output = kraken(&server, &["new", "job", "-n", "00000002", "true"]);
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("already exists"), "{}", String::from_utf8_lossy(&output.stderr));
+    wait_for_output(&server, &["show", "jobs"], "00000002");
+
+    kraken(&server, &["remove", "job", "00000002", "--force"]);
+    let out = kraken(&server, &["show", "jobs"]);
+    let out = String::from_utf8_lossy(&out.stdout);
+    assert!(out.contains("first"), "{}", out);
+    assert!(!out.contains("00000002"), "{}", out);
+}
+
+#[test]
+fn removed_jobs_are_purged_after_the_undo_window() {
+    let mut server = start_server_with("server-purge", 18814, &["--undo-window", "1"]);
+    kraken(&server, &["login", "test@example.com", "hunter2"]);
+
+    kraken(&server, &["new", "job", "-n", "purged", "echo gone soon"]);
+    wait_for_output(&server, &["show", "job", "purged"], "gone soon");
+
+    let logs = server.data_dir.join("logs");
+    assert_eq!(fs::read_dir(&logs).unwrap().count(), 1);
+
+    kraken(&server, &["remove", "job", "purged", "--force"]);
+    thread::sleep(Duration::from_secs(2));
+
+    // Opening the store compacts it, which purges what can't be restored any more
+    restart(&mut server);
+
+    assert_eq!(fs::read_dir(&logs).unwrap().count(), 0);
+    let state = fs::read_to_string(server.data_dir.join("state.json")).unwrap();
+    assert!(!state.contains("purged"), "{}", state);
+
+    let output = kraken(&server, &["restore", "job", "purged"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No removed job named purged"));
+}
@@ -0,0 +1,137 @@
+// Stopping a job's kraken with SIGTERM, as a shutdown does, and `kraken resume`
+
+mod mock_server;
+
+use mock_server::MockServer;
+use std::fs;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Jobs here report how they exited
+fn expect_job(server: &MockServer, job_name: &str) {
+    server.expect_job(job_name);
+    server.respond("POST", &format!("/jobs/{}/exit", job_name), 200, r#"{"ok": true}"#);
+}
+
+fn record(server: &MockServer, job_name: &str) -> String {
+    fs::read_to_string(server.home.join("jobs").join(format!("kraken-job-{}.json", job_name))).unwrap_or_default()
+}
+
+// The daemon running the job, which is what a shutdown signals
+fn daemon_pid(server: &MockServer, job_name: &str) -> String {
+    let path = server.home.join("jobs").join(format!("kraken-job-{}.pid", job_name));
+    fs::read_to_string(path).unwrap_or_default().trim().to_string()
+}
+
+// Starts a job that runs `on_term` on SIGTERM, and waits until it's running
+fn start(server: &MockServer, job_name: &str, options: &[&str], on_term: &str) -> Child {
+    let ready = server.home.join("ready");
+    let command = format!("trap '{}' TERM; touch {}; while true; do sleep 0.1; done", on_term, ready.display());
+
+    let mut args = vec!["new", "job", "-n", job_name];
+    args.extend(options);
+    args.push(&command);
+
+    let kraken = server.command(&args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let started = Instant::now();
+    while !ready.exists() || daemon_pid(server, job_name).is_empty() {
+        if started.elapsed() > Duration::from_secs(20) {
+            panic!("the job never started");
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    kraken
+}
+
+// Sends the job's daemon SIGTERM and waits for the job to be marked interrupted
+fn stop(server: &MockServer, job_name: &str) -> Duration {
+    let stopped = Instant::now();
+    Command::new("kill").arg("-TERM").arg(daemon_pid(server, job_name)).status().unwrap();
+
+    while !record(server, job_name).contains(r#""status": "interrupted""#) {
+        if stopped.elapsed() > Duration::from_secs(20) {
+            let _ = Command::new("kill").arg("-KILL").arg(daemon_pid(server, job_name)).status();
+            panic!("the job was never interrupted: {}", record(server, job_name));
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    stopped.elapsed()
+}
+
+#[test]
+fn stopped_jobs_are_interrupted_and_resumed() {
+    let server = MockServer::start("shutdown-resume");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "train"}"#);
+    expect_job(&server, "train");
+    expect_job(&server, "train-2");
+
+    let mut kraken = start(&server, "train", &["--resume-cmd", "echo resumed from checkpoint"], "echo saving checkpoint; exit 3");
+    stop(&server, "train");
+    kraken.wait().unwrap();
+
+    assert!(server.printed("train", "saving checkpoint"), "the job never got SIGTERM");
+    assert!(server.printed("train", "interrupted"), "the output doesn't say the job was interrupted");
+
+    assert!(server.wait_for(Duration::from_secs(20), |requests| requests.iter().any(|r| r.path == "/jobs/train/exit")));
+    let exit = &server.requests_to("POST", "/jobs/train/exit")[0];
+    assert_eq!(exit.body, r#"{"status":"interrupted","exitCode":3}"#);
+
+    let output = server.kraken(&["resume"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Resumed train as train-2"), "{}{}", stdout, String::from_utf8_lossy(&output.stderr));
+    assert!(server.printed("train-2", "resumed from checkpoint"), "the resumed job didn't run --resume-cmd");
+    assert!(record(&server, "train").contains(r#""resumed_as": "train-2""#), "{}", record(&server, "train"));
+
+    // Each interrupted job is only resumed once
+    let output = server.kraken(&["resume"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("No interrupted jobs to resume"));
+
+    let output = server.kraken(&["resume", "train"]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("train was already resumed as train-2"));
+}
+
+#[test]
+fn jobs_that_ignore_sigterm_are_killed_after_the_grace_period() {
+    let server = MockServer::start("shutdown-grace");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "stubborn"}"#);
+    expect_job(&server, "stubborn");
+
+    let mut kraken = start(&server, "stubborn", &["--grace-period", "1"], "");
+
+    let took = stop(&server, "stubborn");
+    kraken.wait().unwrap();
+    assert!(took < Duration::from_secs(10), "the job took {:?} to be killed", took);
+}
+
+#[test]
+fn killed_jobs_fail_instead_of_being_interrupted() {
+    let server = MockServer::start("shutdown-killed");
+    server.store_token("abc123");
+    server.respond("POST", "/jobs/new", 200, r#"{"_id": "1", "name": "unwanted"}"#);
+    expect_job(&server, "unwanted");
+
+    let mut kraken = start(&server, "unwanted", &[], "exit 0");
+
+    // What `kraken top` leaves before sending the same SIGTERM a shutdown does
+    fs::write(server.home.join("jobs").join("kraken-job-unwanted.killed"), "").unwrap();
+    Command::new("kill").arg("-TERM").arg(daemon_pid(&server, "unwanted")).status().unwrap();
+    kraken.wait().unwrap();
+
+    assert!(server.wait_for(Duration::from_secs(20), |requests| requests.iter().any(|r| r.path == "/jobs/unwanted/exit")));
+    let exit = &server.requests_to("POST", "/jobs/unwanted/exit")[0];
+    assert_eq!(exit.body, r#"{"status":"failed","exitCode":0}"#);
+    assert!(server.printed("unwanted", "killed"), "the output doesn't say the job was killed");
+
+    let output = server.kraken(&["resume"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("No interrupted jobs to resume"));
+}